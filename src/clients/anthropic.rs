@@ -37,19 +37,38 @@
 //! ```
 
 use crate::{
+    config::{ModelDefaults, RetryConfig},
     error::{ApiError, Result},
-    models::{ApiConfig, Message, Role},
+    models::{ApiConfig, Message, RateLimit, Role, ToolCall},
 };
-use futures::Stream;
+use futures::{future::BoxFuture, Stream};
 use reqwest::{header::HeaderMap, Client};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::HashMap, pin::Pin, time::Duration};
 use futures::StreamExt;
 use serde_json;
 
-pub(crate) const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
+/// Base URL requests go to unless another is configured.
+pub(crate) const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+/// Path of the chat endpoint below the base URL.
+const CHAT_PATH: &str = "/v1/messages";
 const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20241022";
 
+/// Stop reason and content block type Anthropic uses to signal a refusal.
+pub(crate) const REFUSAL: &str = "refusal";
+
+/// Stop reason for an answer cut off at `max_tokens`.
+pub(crate) const STOP_MAX_TOKENS: &str = "max_tokens";
+
+/// Content block type carrying Claude's extended thinking.
+pub(crate) const THINKING: &str = "thinking";
+
+/// Delta type carrying the signature of a thinking block, which has no text.
+pub(crate) const SIGNATURE_DELTA: &str = "signature_delta";
+
+/// Response header carrying Anthropic's id for a request.
+const DEFAULT_REQUEST_ID_HEADER: &str = "request-id";
+
 /// Client for interacting with Anthropic's Claude models.
 ///
 /// This client handles authentication, request construction, and response parsing
@@ -66,6 +85,12 @@ const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20241022";
 pub struct AnthropicClient {
     pub(crate) client: Client,
     api_token: String,
+    model_defaults: HashMap<String, ModelDefaults>,
+    retry: RetryConfig,
+    denied_params: Vec<String>,
+    header_policy: super::HeaderPolicy,
+    request_id_header: String,
+    base_url: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -79,23 +104,47 @@ pub struct AnthropicResponse {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
     pub usage: Usage,
+    /// Anthropic's id for the request, read from the response headers
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// Rate-limit state, read from the response headers
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ContentBlock {
     #[serde(rename = "type")]
     pub content_type: String,
+    /// Block text, or the thinking of a `thinking` block
+    #[serde(default, alias = "thinking")]
     pub text: String,
+    /// The call requested by a `tool_use` block
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub tool_call: Option<ToolCall>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Only reported when prompt caching was used
+    #[serde(default)]
     pub cache_creation_input_tokens: u32,
+    #[serde(default)]
     pub cache_read_input_tokens: u32,
 }
 
+impl Usage {
+    /// Adds the usage of another call to this one.
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AnthropicRequest {
     messages: Vec<AnthropicMessage>,
@@ -109,7 +158,8 @@ pub(crate) struct AnthropicRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AnthropicMessage {
     role: String,
-    content: String,
+    /// Plain text, or content blocks for messages with tool blocks
+    content: serde_json::Value,
 }
 
 // Event types for streaming responses
@@ -118,22 +168,19 @@ pub(crate) struct AnthropicMessage {
 pub enum StreamEvent {
     #[serde(rename = "message_start")]
     MessageStart {
-        message: AnthropicResponse,
+        message: Box<AnthropicResponse>,
     },
     #[serde(rename = "content_block_start")]
-    #[allow(dead_code)]
     ContentBlockStart {
         index: usize,
         content_block: ContentBlock,
     },
     #[serde(rename = "content_block_delta")]
-    #[allow(dead_code)]
     ContentBlockDelta {
         index: usize,
         delta: ContentDelta,
     },
     #[serde(rename = "content_block_stop")]
-    #[allow(dead_code)]
     ContentBlockStop {
         index: usize,
     },
@@ -153,6 +200,9 @@ pub enum StreamEvent {
 pub struct ContentDelta {
     #[serde(rename = "type")]
     pub delta_type: String,
+    /// Delta text, the thinking of a `thinking_delta` or the partial tool
+    /// input of an `input_json_delta`
+    #[serde(default, alias = "thinking", alias = "partial_json")]
     pub text: String,
 }
 
@@ -163,6 +213,65 @@ pub struct MessageDelta {
     pub stop_sequence: Option<String>,
 }
 
+/// Returns whether an answer without content is unexpected for a stop reason.
+///
+/// Stopping at a stop sequence or the token limit, or refusing, can
+/// legitimately produce no text; an ordinary end of turn should not.
+pub(crate) fn is_unexpected_empty(stop_reason: Option<&str>) -> bool {
+    matches!(stop_reason, None | Some("end_turn"))
+}
+
+impl AnthropicResponse {
+    /// Returns whether the response has no content although Claude ended its
+    /// turn normally.
+    pub fn is_unexpectedly_empty(&self) -> bool {
+        self.content.iter().all(|block| block.text.is_empty())
+            && is_unexpected_empty(self.stop_reason.as_deref())
+    }
+
+    /// Returns the text of the answer, without thinking blocks.
+    pub fn answer_text(&self) -> String {
+        self.content
+            .iter()
+            .filter(|block| block.content_type == "text")
+            .map(|block| block.text.as_str())
+            .collect()
+    }
+
+    /// Appends a continuation of this answer, requested after it was cut
+    /// off at `max_tokens`.
+    ///
+    /// The continuation's first text block is joined onto the last text
+    /// block, its usage is added and its stop reason and rate-limit state
+    /// replace this one's.
+    pub fn append(&mut self, continuation: AnthropicResponse) {
+        let mut blocks = continuation.content.into_iter().peekable();
+        let last_text = self.content.iter_mut().rev().find(|block| block.content_type == "text");
+        if let (Some(last), Some(first)) = (last_text, blocks.peek()) {
+            if first.content_type == "text" {
+                last.text.push_str(&first.text);
+                blocks.next();
+            }
+        }
+        self.content.extend(blocks);
+        self.usage.add(&continuation.usage);
+        self.stop_reason = continuation.stop_reason;
+        self.stop_sequence = continuation.stop_sequence;
+        if continuation.rate_limit.is_some() {
+            self.rate_limit = continuation.rate_limit;
+        }
+    }
+
+    /// Returns whether Claude refused to answer.
+    ///
+    /// A refusal is signalled either by a `refusal` stop reason or by a
+    /// `refusal` content block.
+    pub fn is_refusal(&self) -> bool {
+        self.stop_reason.as_deref() == Some(REFUSAL)
+            || self.content.iter().any(|block| block.content_type == REFUSAL)
+    }
+}
+
 impl AnthropicClient {
     /// Creates a new Anthropic client instance.
     ///
@@ -177,9 +286,129 @@ impl AnthropicClient {
         Self {
             client: Client::new(),
             api_token,
+            model_defaults: HashMap::new(),
+            retry: RetryConfig::default(),
+            denied_params: Vec::new(),
+            header_policy: super::HeaderPolicy::default(),
+            request_id_header: DEFAULT_REQUEST_ID_HEADER.to_string(),
+            base_url: ANTHROPIC_BASE_URL.to_string(),
         }
     }
 
+    /// Sets the HTTP client used for upstream requests.
+    ///
+    /// Lets callers share one connection pool and TLS/proxy setup across
+    /// all provider clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The configured HTTP client
+    ///
+    /// # Returns
+    ///
+    /// The client using the given HTTP client
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets per-model request defaults applied by `build_request`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_defaults` - Default body parameters keyed by model id
+    ///
+    /// # Returns
+    ///
+    /// The client with the given model defaults
+    pub fn with_model_defaults(mut self, model_defaults: HashMap<String, ModelDefaults>) -> Self {
+        self.model_defaults = model_defaults;
+        self
+    }
+
+    /// Sets the retry policy used for non-streaming requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry policy
+    ///
+    /// # Returns
+    ///
+    /// The client with the retry policy applied
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the request body parameters clients may not pass through.
+    ///
+    /// # Arguments
+    ///
+    /// * `denied_params` - Keys stripped from `config.body` before it is merged
+    ///
+    /// # Returns
+    ///
+    /// The client with the denylist applied
+    pub fn with_denied_params(mut self, denied_params: Vec<String>) -> Self {
+        self.denied_params = denied_params;
+        self
+    }
+
+    /// Sets which client-supplied headers are forwarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The header allowlist and denylist
+    ///
+    /// # Returns
+    ///
+    /// The client with the header policy applied
+    pub fn with_header_policy(mut self, policy: super::HeaderPolicy) -> Self {
+        self.header_policy = policy;
+        self
+    }
+
+    /// Sets the response header the provider's request id is read from.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header name, or `None` to keep the provider default
+    ///
+    /// # Returns
+    ///
+    /// The client reading request ids from the given header
+    pub fn with_request_id_header(mut self, header: Option<String>) -> Self {
+        if let Some(header) = header {
+            self.request_id_header = header;
+        }
+        self
+    }
+
+    /// Sets the base URL requests are sent to.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The base URL, or `None` to keep the provider default
+    ///
+    /// # Returns
+    ///
+    /// The client sending requests below the given base URL
+    pub fn with_base_url(mut self, base_url: Option<String>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url;
+        }
+        self
+    }
+
+    /// Returns the chat endpoint for a request.
+    ///
+    /// A `base_url` in the request configuration, which the handlers only
+    /// let through for authorized callers, replaces the client's own.
+    fn endpoint(&self, config: &ApiConfig) -> String {
+        let base_url = config.base_url.as_deref().unwrap_or(&self.base_url);
+        format!("{}{}", base_url.trim_end_matches('/'), CHAT_PATH)
+    }
+
     /// Builds the HTTP headers required for Anthropic API requests.
     ///
     /// # Arguments
@@ -223,7 +452,7 @@ impl AnthropicClient {
         );
 
         if let Some(custom) = custom_headers {
-            headers.extend(super::build_headers(custom)?);
+            headers.extend(super::build_headers(custom, &self.header_policy)?);
         }
 
         Ok(headers)
@@ -231,6 +460,9 @@ impl AnthropicClient {
 
     /// Constructs a request object for the Anthropic API.
     ///
+    /// Keys in the denylist are dropped from `config.body` before any default
+    /// is read from it, so denying `model` or `max_tokens` sends the default.
+    ///
     /// # Arguments
     ///
     /// * `messages` - Vector of messages to send to the model
@@ -257,13 +489,27 @@ impl AnthropicClient {
                     Role::Assistant => "assistant".to_string(),
                     Role::System => unreachable!(),
                 },
-                content: msg.content,
+                content: message_content(msg),
             })
             .collect();
 
+        // Strip protected and denied fields once, so no default is read from them
+        let mut body = match &config.body {
+            serde_json::Value::Object(body) => body.clone(),
+            _ => serde_json::Map::new(),
+        };
+        body.remove("stream");
+        body.remove("messages");
+        body.remove("system");
+        for key in &self.denied_params {
+            if body.remove(key).is_some() {
+                tracing::warn!("Removed denied parameter {} from Anthropic request body", key);
+            }
+        }
+
         // Create base request with required fields
         let default_model = serde_json::json!(DEFAULT_MODEL);
-        let model_value = config.body.get("model").unwrap_or(&default_model);
+        let model_value = body.get("model").unwrap_or(&default_model);
         
         let default_max_tokens = if let Some(model_str) = model_value.as_str() {
             if model_str.contains("claude-3-opus") {
@@ -280,7 +526,7 @@ impl AnthropicClient {
             "messages": filtered_messages,
             "stream": stream,
             "model": model_value,
-            "max_tokens": config.body.get("max_tokens").unwrap_or(&default_max_tokens_json)
+            "max_tokens": body.get("max_tokens").unwrap_or(&default_max_tokens_json)
         });
 
         // Add system if present
@@ -291,31 +537,49 @@ impl AnthropicClient {
             }
         }
 
-        // Merge additional configuration from config.body while protecting critical fields
-        if let serde_json::Value::Object(mut map) = request_value {
-            if let serde_json::Value::Object(mut body) = serde_json::to_value(&config.body).unwrap_or_default() {
-                // Remove protected fields from config body
-                body.remove("stream");
-                body.remove("messages");
-                body.remove("system");
-                
-                // Merge remaining fields from config.body
-                for (key, value) in body {
-                    map.insert(key, value);
+        // Apply per-model defaults from config; explicit body values still win below
+        if let serde_json::Value::Object(ref mut map) = request_value {
+            let model = map.get("model").and_then(|m| m.as_str()).unwrap_or(DEFAULT_MODEL);
+            if let Some(defaults) = self.model_defaults.get(model) {
+                for (key, value) in defaults {
+                    if key != "stream" && key != "messages" && key != "system" {
+                        map.insert(key.clone(), value.clone());
+                    }
                 }
             }
+        }
+
+        // Merge the remaining body fields, combining nested objects
+        let fallback = serde_json::Value::Object(body.clone());
+        if let serde_json::Value::Object(mut map) = request_value {
+            super::deep_merge(&mut map, body);
             request_value = serde_json::Value::Object(map);
         }
 
         // Convert the merged JSON value into our request structure
-        serde_json::from_value(request_value).unwrap_or_else(|_| AnthropicRequest {
+        serde_json::from_value(request_value).unwrap_or(AnthropicRequest {
             messages: filtered_messages,
             stream,
             system,
-            additional_params: config.body.clone(),
+            additional_params: fallback,
         })
     }
 
+    /// Returns the `max_tokens` a request with this configuration would send.
+    ///
+    /// Accounts for the built-in default, per-model defaults and any value
+    /// in the request body.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration options for the request
+    pub(crate) fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        self.build_request(Vec::new(), None, false, config)
+            .additional_params
+            .get("max_tokens")
+            .and_then(serde_json::Value::as_u64)
+    }
+
     /// Sends a non-streaming chat request to the Anthropic API.
     ///
     /// # Arguments
@@ -342,12 +606,33 @@ impl AnthropicClient {
     ) -> Result<AnthropicResponse> {
         let headers = self.build_headers(Some(&config.headers))?;
         let request = self.build_request(messages, system, false, config);
+        let endpoint = self.endpoint(config);
 
+        let mut attempt = 1;
+        loop {
+            match self.send_chat(&endpoint, &headers, &request).await {
+                Err(e) if e.is_retryable() && attempt < self.retry.max_attempts => {
+                    tracing::warn!("Anthropic attempt {} failed, retrying: {}", attempt, e);
+                    tokio::time::sleep(Duration::from_millis(self.retry.backoff_ms * attempt as u64)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Performs a single non-streaming request attempt.
+    async fn send_chat(
+        &self,
+        endpoint: &str,
+        headers: &HeaderMap,
+        request: &AnthropicRequest,
+    ) -> Result<AnthropicResponse> {
         let response = self
             .client
-            .post(ANTHROPIC_API_URL)
-            .headers(headers)
-            .json(&request)
+            .post(endpoint)
+            .headers(headers.clone())
+            .json(request)
             .send()
             .await
             .map_err(|e| ApiError::AnthropicError { 
@@ -357,20 +642,22 @@ impl AnthropicClient {
                 code: None
             })?;
 
-        if !response.status().is_success() {
+        let status = response.status();
+        if !status.is_success() {
             let error = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ApiError::AnthropicError { 
-                message: error,
-                type_: "api_error".to_string(),
-                param: None,
-                code: None
-            });
+            return Err(status_error(status, error));
         }
 
-        response
+        let request_id = response
+            .headers()
+            .get(&self.request_id_header)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let rate_limit = super::parse_rate_limit(response.headers(), super::RateLimitHeaders::Anthropic);
+        let mut response = response
             .json::<AnthropicResponse>()
             .await
             .map_err(|e| ApiError::AnthropicError { 
@@ -378,7 +665,10 @@ impl AnthropicClient {
                 type_: "parse_error".to_string(),
                 param: None,
                 code: None
-            })
+            })?;
+        response.request_id = request_id;
+        response.rate_limit = rate_limit;
+        Ok(response)
     }
 
     /// Sends a streaming chat request to the Anthropic API.
@@ -399,6 +689,7 @@ impl AnthropicClient {
     ///
     /// The stream may yield `ApiError::AnthropicError` if:
     /// - The API request fails
+    /// - The response status is not successful
     /// - Stream processing encounters an error
     /// - Response events cannot be parsed
     pub fn chat_stream(
@@ -413,11 +704,12 @@ impl AnthropicClient {
         };
 
         let request = self.build_request(messages, system, true, config);
+        let endpoint = self.endpoint(config);
         let client = self.client.clone();
 
         Box::pin(async_stream::try_stream! {
-            let mut stream = client
-                .post(ANTHROPIC_API_URL)
+            let response = client
+                .post(endpoint)
                 .headers(headers)
                 .json(&request)
                 .send()
@@ -427,10 +719,22 @@ impl AnthropicClient {
                     type_: "request_failed".to_string(),
                     param: None,
                     code: None
-                })?
-                .bytes_stream();
+                })?;
 
-            let mut data = String::new();
+            let response = match response.status() {
+                status if status.is_success() => response,
+                status => {
+                    let error = response
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Unknown error".to_string());
+                    Err(status_error(status, error))?
+                }
+            };
+
+            let mut stream = response.bytes_stream();
+
+            let mut frames = SseBuffer::default();
             
             while let Some(chunk) = stream.next().await {
                 let chunk = chunk.map_err(|e| ApiError::AnthropicError { 
@@ -439,41 +743,297 @@ impl AnthropicClient {
                     param: None,
                     code: None
                 })?;
-                data.push_str(&String::from_utf8_lossy(&chunk));
-
-                let mut start = 0;
-                while let Some(end) = data[start..].find("\n\n") {
-                    let end = start + end;
-                    let event_data = &data[start..end];
-                    start = end + 2;
-
-                    if event_data.starts_with("event: ") {
-                        let _event_line = &event_data["event: ".len()..];
-                        if let Some(data_line) = event_data.lines().nth(1) {
-                            if data_line.starts_with("data: ") {
-                                let json_data = &data_line["data: ".len()..];
-                                if let Ok(event) = serde_json::from_str::<StreamEvent>(json_data) {
-                                    yield event;
-                                }
-                            }
-                        }
-                    }
-                }
 
-                if start > 0 {
-                    data = data[start..].to_string();
+                for event in frames.push(&chunk) {
+                    yield event;
                 }
             }
         })
     }
 }
 
+/// Builds the error for an unsuccessful Anthropic response.
+///
+/// # Arguments
+///
+/// * `status` - The response status
+/// * `body` - The response body
+fn status_error(status: reqwest::StatusCode, body: String) -> ApiError {
+    // 5xx (including 529 overloaded) and 429 are transient
+    let type_ = if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        "server_error"
+    } else {
+        "api_error"
+    };
+    ApiError::AnthropicError {
+        message: body,
+        type_: type_.to_string(),
+        param: None,
+        code: None
+    }
+}
+
+/// Reassembles SSE frames from chunks that may split them anywhere.
+///
+/// Bytes are buffered until a blank line ends a frame, so a frame, a line,
+/// a line ending or a multi-byte character split between reads is decoded
+/// only once it is complete.
+#[derive(Debug, Default)]
+struct SseBuffer {
+    data: Vec<u8>,
+}
+
+impl SseBuffer {
+    /// Appends a chunk and returns the events of the frames it completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - Bytes read from the response body
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<StreamEvent>` - The parsed events, in order
+    fn push(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        self.data.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some((end, separator)) = frame_end(&self.data) {
+            let frame = String::from_utf8_lossy(&self.data[..end]).replace("\r\n", "\n");
+            self.data.drain(..end + separator);
+            events.extend(parse_sse_frame(&frame));
+        }
+        events
+    }
+}
+
+/// Finds the blank line ending the first complete frame.
+///
+/// # Returns
+///
+/// * `Option<(usize, usize)>` - The frame's length and the length of its separator
+fn frame_end(data: &[u8]) -> Option<(usize, usize)> {
+    (0..data.len()).find_map(|i| {
+        if data[i..].starts_with(b"\n\n") {
+            Some((i, 2))
+        } else if data[i..].starts_with(b"\r\n\r\n") {
+            Some((i, 4))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parses a single SSE frame into a stream event.
+///
+/// The frame is read field by field rather than by position, so comment
+/// lines, `event:` lines and multi-line `data:` fields in any order are
+/// handled, and a keep-alive `ping` frame never shifts the parsing of the
+/// frames around it.
+///
+/// # Arguments
+///
+/// * `frame` - The text of one frame, without the terminating blank line
+///
+/// # Returns
+///
+/// * `Option<StreamEvent>` - The parsed event, or None if the frame carries no recognised data
+fn parse_sse_frame(frame: &str) -> Option<StreamEvent> {
+    let data: Vec<&str> = frame
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|value| value.strip_prefix(' ').unwrap_or(value))
+        .collect();
+    if data.is_empty() {
+        return None;
+    }
+
+    let json_data = data.join("\n");
+    match serde_json::from_str::<StreamEvent>(&json_data) {
+        Ok(event) => Some(event),
+        Err(e) => {
+            tracing::debug!("Skipping unrecognised Anthropic stream frame: {} ({})", json_data, e);
+            None
+        }
+    }
+}
+
+impl super::AnswerProvider for AnthropicClient {
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<AnthropicResponse>> {
+        Box::pin(AnthropicClient::chat(self, messages, system, config))
+    }
+
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>> {
+        AnthropicClient::chat_stream(self, messages, system, config)
+    }
+
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        AnthropicClient::effective_max_tokens(self, config)
+    }
+
+    fn request_body(&self, messages: &[Message], system: Option<&str>, config: &ApiConfig) -> Option<serde_json::Value> {
+        serde_json::to_value(self.build_request(messages.to_vec(), system.map(String::from), false, config)).ok()
+    }
+}
+
 /// Converts an Anthropic content block into the application's generic content block type.
 impl From<ContentBlock> for crate::models::response::ContentBlock {
     fn from(block: ContentBlock) -> Self {
         Self {
             content_type: block.content_type,
             text: block.text,
+            tool_call: block.tool_call,
+        }
+    }
+}
+
+/// Builds a message's Anthropic content.
+///
+/// Messages without tool blocks are sent as plain text. Otherwise the text
+/// becomes a text block, placed after the tool results of a user message,
+/// which Anthropic requires to come first, and before the tool calls of an
+/// assistant message.
+///
+/// # Arguments
+///
+/// * `msg` - The message to convert
+///
+/// # Returns
+///
+/// * `serde_json::Value` - A string or an array of content blocks
+fn message_content(msg: Message) -> serde_json::Value {
+    if msg.tool_blocks.is_empty() {
+        return serde_json::Value::String(msg.content);
+    }
+
+    let text = (!msg.content.is_empty())
+        .then(|| serde_json::json!({ "type": "text", "text": msg.content }));
+    let tools = msg
+        .tool_blocks
+        .iter()
+        .map(|block| serde_json::to_value(block).unwrap_or_default());
+    let blocks = match msg.role {
+        Role::User => tools.chain(text).collect(),
+        _ => text.into_iter().chain(tools).collect(),
+    };
+    serde_json::Value::Array(blocks)
+}
+
+/// Returns the model a request body will use, falling back to the default.
+///
+/// # Arguments
+///
+/// * `body` - The client-supplied request body
+pub(crate) fn requested_model(body: &serde_json::Value) -> &str {
+    body.get("model").and_then(|m| m.as_str()).unwrap_or(DEFAULT_MODEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denied_model_and_max_tokens_fall_back_to_defaults() {
+        let client = AnthropicClient::new("token".to_string())
+            .with_denied_params(vec!["model".to_string(), "max_tokens".to_string()]);
+        let config = ApiConfig {
+            body: serde_json::json!({ "model": "claude-3-opus-20240229", "max_tokens": 100_000 }),
+            ..Default::default()
+        };
+
+        let request = serde_json::to_value(client.build_request(Vec::new(), None, false, &config)).unwrap();
+
+        assert_eq!(request["model"], serde_json::json!(DEFAULT_MODEL));
+        assert_eq!(request["max_tokens"], serde_json::json!(8192));
+    }
+
+    #[test]
+    fn usage_without_cache_fields_defaults_to_zero() {
+        let response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [{ "type": "text", "text": "answer" }],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 12, "output_tokens": 34 },
+        }))
+        .unwrap();
+
+        assert_eq!(response.usage.input_tokens, 12);
+        assert_eq!(response.usage.output_tokens, 34);
+        assert_eq!(response.usage.cache_creation_input_tokens, 0);
+        assert_eq!(response.usage.cache_read_input_tokens, 0);
+    }
+
+    #[test]
+    fn sse_frames_skip_pings_comments_and_ids() {
+        let stream = concat!(
+            ": connected\n\n",
+            "event: content_block_delta\n",
+            "id: 1\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+            "event: ping\n",
+            "data: {\"type\":\"ping\"}\n\n",
+            ": keep-alive\n\n",
+            "id: 2\n\n",
+            ": comment before the event\n",
+            "id: 3\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\", \"}}\n\n",
+            "event: ping\n",
+            "id: 4\n",
+            "data: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\n",
+            "data: \"delta\":{\"type\":\"text_delta\",\"text\":\"world\"}}\n",
+            "id: 5\n\n",
+        );
+
+        let events: Vec<StreamEvent> = stream.split("\n\n").filter_map(parse_sse_frame).collect();
+        let deltas: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ContentBlockDelta { delta, .. } => Some(delta.text.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(deltas, ["Hello", ", ", "world"]);
+        assert_eq!(events.iter().filter(|event| matches!(event, StreamEvent::Ping)).count(), 2);
+        assert_eq!(events.len(), 5);
+    }
+
+    #[test]
+    fn sse_frames_split_across_chunks_parse_once() {
+        for newline in ["\n", "\r\n"] {
+            let stream = [
+                "event: content_block_delta",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"héllo\"}}",
+                "",
+                "",
+            ]
+            .join(newline);
+
+            let mut frames = SseBuffer::default();
+            let events: Vec<StreamEvent> = stream.as_bytes().iter().flat_map(|byte| frames.push(&[*byte])).collect();
+
+            assert_eq!(events.len(), 1, "{:?}", newline);
+            match &events[0] {
+                StreamEvent::ContentBlockDelta { delta, .. } => assert_eq!(delta.text, "héllo"),
+                other => panic!("unexpected event: {:?}", other),
+            }
+            assert!(frames.data.is_empty());
         }
     }
 }
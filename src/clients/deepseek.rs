@@ -28,6 +28,7 @@
 //! let messages = vec![Message {
 //!     role: "user".to_string(),
 //!     content: "Hello, how are you?".to_string(),
+//!     tool_blocks: Vec::new(),
 //! }];
 //!
 //! let config = ApiConfig::default();
@@ -55,18 +56,33 @@
 //! All public methods return `Result` types with appropriate error variants.
 
 use crate::{
+    config::{ModelDefaults, RetryConfig},
     error::{ApiError, Result},
-    models::{ApiConfig, Message},
+    models::{ApiConfig, Message, RateLimit},
 };
-use futures::Stream;
-use reqwest::{header::HeaderMap, Client};
+use futures::{future::BoxFuture, Stream};
+use reqwest::{header::HeaderMap, Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::HashMap, pin::Pin, time::Duration};
 use futures::StreamExt;
 use serde_json;
 
-pub(crate) const DEEPSEEK_API_URL: &str = "https://api.deepseek.com/chat/completions";
+/// Base URL requests go to unless another is configured.
+pub(crate) const DEEPSEEK_BASE_URL: &str = "https://api.deepseek.com";
+/// Path of the chat endpoint below the base URL.
+const CHAT_PATH: &str = "/chat/completions";
 const DEFAULT_MODEL: &str = "deepseek-reasoner";
+/// `response_format` type sent when neither the request nor the server sets one.
+const DEFAULT_RESPONSE_FORMAT: &str = "text";
+const STREAM_DONE_SENTINEL: &str = "[DONE]";
+/// Finish reason DeepSeek reports when it ran out of tokens.
+pub(crate) const FINISH_REASON_LENGTH: &str = "length";
+/// Models that ignore sampling parameters, so none are sent unless the request sets them.
+const MODELS_WITHOUT_SAMPLING: &[&str] = &["deepseek-reasoner"];
+/// Parameters left out for `MODELS_WITHOUT_SAMPLING`, even when a default sets them.
+const SAMPLING_PARAMS: &[&str] = &["temperature", "top_p"];
+/// Response header carrying DeepSeek's id for a request.
+const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
 
 /// Client for interacting with DeepSeek's AI models.
 ///
@@ -84,6 +100,13 @@ const DEFAULT_MODEL: &str = "deepseek-reasoner";
 pub struct DeepSeekClient {
     pub(crate) client: Client,
     api_token: String,
+    model_defaults: HashMap<String, ModelDefaults>,
+    retry: RetryConfig,
+    denied_params: Vec<String>,
+    header_policy: super::HeaderPolicy,
+    request_id_header: String,
+    base_url: String,
+    default_response_format: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -93,8 +116,16 @@ pub struct DeepSeekResponse {
     pub created: i64,
     pub model: String,
     pub choices: Vec<Choice>,
+    /// Missing from some DeepSeek-compatible gateways; treated as zero usage
+    #[serde(default)]
     pub usage: Usage,
     pub system_fingerprint: String,
+    /// DeepSeek's id for the request, read from the response headers
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// Rate-limit state, read from the response headers
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -139,24 +170,48 @@ pub struct StreamResponse {
     pub system_fingerprint: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Token usage reported by DeepSeek.
+///
+/// Only the top-level token counts are always present. The detailed
+/// breakdowns are missing from some DeepSeek-compatible gateways and
+/// default to zero, so costs are computed without cache discounts.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    #[serde(default)]
     pub prompt_tokens_details: PromptTokensDetails,
+    #[serde(default)]
     pub completion_tokens_details: CompletionTokensDetails,
+    #[serde(default)]
     pub prompt_cache_hit_tokens: u32,
+    #[serde(default)]
     pub prompt_cache_miss_tokens: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Usage {
+    /// Adds the usage of another call to this one.
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.prompt_tokens_details.cached_tokens += other.prompt_tokens_details.cached_tokens;
+        self.completion_tokens_details.reasoning_tokens += other.completion_tokens_details.reasoning_tokens;
+        self.prompt_cache_hit_tokens += other.prompt_cache_hit_tokens;
+        self.prompt_cache_miss_tokens += other.prompt_cache_miss_tokens;
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct PromptTokensDetails {
+    #[serde(default)]
     pub cached_tokens: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct CompletionTokensDetails {
+    #[serde(default)]
     pub reasoning_tokens: u32,
 }
 
@@ -173,9 +228,146 @@ impl DeepSeekClient {
         Self {
             client: Client::new(),
             api_token,
+            model_defaults: HashMap::new(),
+            retry: RetryConfig::default(),
+            denied_params: Vec::new(),
+            header_policy: super::HeaderPolicy::default(),
+            request_id_header: DEFAULT_REQUEST_ID_HEADER.to_string(),
+            base_url: DEEPSEEK_BASE_URL.to_string(),
+            default_response_format: DEFAULT_RESPONSE_FORMAT.to_string(),
         }
     }
 
+    /// Sets the HTTP client used for upstream requests.
+    ///
+    /// Lets callers share one connection pool and TLS/proxy setup across
+    /// all provider clients.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The configured HTTP client
+    ///
+    /// # Returns
+    ///
+    /// The client using the given HTTP client
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets per-model request defaults applied by `build_request`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_defaults` - Default body parameters keyed by model id
+    ///
+    /// # Returns
+    ///
+    /// The client with the given model defaults
+    pub fn with_model_defaults(mut self, model_defaults: HashMap<String, ModelDefaults>) -> Self {
+        self.model_defaults = model_defaults;
+        self
+    }
+
+    /// Sets the retry policy used for non-streaming requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry policy
+    ///
+    /// # Returns
+    ///
+    /// The client with the retry policy applied
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the request body parameters clients may not pass through.
+    ///
+    /// # Arguments
+    ///
+    /// * `denied_params` - Keys stripped from `config.body` before it is merged
+    ///
+    /// # Returns
+    ///
+    /// The client with the denylist applied
+    pub fn with_denied_params(mut self, denied_params: Vec<String>) -> Self {
+        self.denied_params = denied_params;
+        self
+    }
+
+    /// Sets which client-supplied headers are forwarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The header allowlist and denylist
+    ///
+    /// # Returns
+    ///
+    /// The client with the header policy applied
+    pub fn with_header_policy(mut self, policy: super::HeaderPolicy) -> Self {
+        self.header_policy = policy;
+        self
+    }
+
+    /// Sets the response header the provider's request id is read from.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header name, or `None` to keep the provider default
+    ///
+    /// # Returns
+    ///
+    /// The client reading request ids from the given header
+    pub fn with_request_id_header(mut self, header: Option<String>) -> Self {
+        if let Some(header) = header {
+            self.request_id_header = header;
+        }
+        self
+    }
+
+    /// Sets the base URL requests are sent to.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The base URL, or `None` to keep the provider default
+    ///
+    /// # Returns
+    ///
+    /// The client sending requests below the given base URL
+    pub fn with_base_url(mut self, base_url: Option<String>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url;
+        }
+        self
+    }
+
+    /// Sets the `response_format` type used when a request sets none.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format type, e.g. `json_object`, or `None` for `text`
+    ///
+    /// # Returns
+    ///
+    /// The client sending the given format by default
+    pub fn with_default_response_format(mut self, format: Option<String>) -> Self {
+        if let Some(format) = format {
+            self.default_response_format = format;
+        }
+        self
+    }
+
+    /// Returns the chat endpoint for a request.
+    ///
+    /// A `base_url` in the request configuration, which the handlers only
+    /// let through for authorized callers, replaces the client's own.
+    fn endpoint(&self, config: &ApiConfig) -> String {
+        let base_url = config.base_url.as_deref().unwrap_or(&self.base_url);
+        format!("{}{}", base_url.trim_end_matches('/'), CHAT_PATH)
+    }
+
     /// Builds the HTTP headers required for DeepSeek API requests.
     ///
     /// # Arguments
@@ -219,7 +411,7 @@ impl DeepSeekClient {
         );
 
         if let Some(custom) = custom_headers {
-            headers.extend(super::build_headers(custom)?);
+            headers.extend(super::build_headers(custom, &self.header_policy)?);
         }
 
         Ok(headers)
@@ -227,6 +419,13 @@ impl DeepSeekClient {
 
     /// Constructs a request object for the DeepSeek API.
     ///
+    /// Keys in the denylist are dropped from `config.body` before any default
+    /// is read from it. Sampling defaults, including per-model ones, are only
+    /// sent to models that honor them, and the default `response_format`
+    /// only when `config.body` has none.
+    /// Parameters set to `null` in `config.body` are left out of the
+    /// request, which also suppresses a default.
+    ///
     /// # Arguments
     ///
     /// * `messages` - Vector of messages to send to the model
@@ -237,42 +436,88 @@ impl DeepSeekClient {
     ///
     /// A `DeepSeekRequest` object configured with the provided parameters and defaults
     pub(crate) fn build_request(&self, messages: Vec<Message>, stream: bool, config: &ApiConfig) -> DeepSeekRequest {
+        // Strip protected and denied fields once, so no default is read from them
+        let mut body = match &config.body {
+            serde_json::Value::Object(body) => body.clone(),
+            _ => serde_json::Map::new(),
+        };
+        body.remove("stream");
+        body.remove("messages");
+        for key in &self.denied_params {
+            if body.remove(key).is_some() {
+                tracing::warn!("Removed denied parameter {} from DeepSeek request body", key);
+            }
+        }
+
         // Create a base request with required fields
-        let mut request_value = serde_json::json!({
-            "messages": messages,
-            "stream": stream,
-            // Set defaults only if not provided in config
-            "model": config.body.get("model").unwrap_or(&serde_json::json!(DEFAULT_MODEL)),
-            "max_tokens": config.body.get("max_tokens").unwrap_or(&serde_json::json!(8192)),
-            "temperature": config.body.get("temperature").unwrap_or(&serde_json::json!(1.0)),
-            "response_format": {
-                "type": "text"
+        let mut map = serde_json::Map::new();
+        map.insert("messages".to_string(), serde_json::json!(messages));
+        map.insert("stream".to_string(), serde_json::json!(stream));
+        // Set defaults only if not provided in the body
+        map.insert(
+            "model".to_string(),
+            body.get("model").cloned().unwrap_or_else(|| serde_json::json!(DEFAULT_MODEL)),
+        );
+        map.insert(
+            "max_tokens".to_string(),
+            body.get("max_tokens").cloned().unwrap_or_else(|| serde_json::json!(8192)),
+        );
+
+        if body.get("response_format").is_none() {
+            map.insert(
+                "response_format".to_string(),
+                serde_json::json!({ "type": self.default_response_format }),
+            );
+        }
+        map.insert("temperature".to_string(), serde_json::json!(1.0));
+
+        // Apply per-model defaults from config; explicit body values still win below
+        let model = map.get("model").and_then(|m| m.as_str()).unwrap_or(DEFAULT_MODEL).to_string();
+        if let Some(defaults) = self.model_defaults.get(&model) {
+            for (key, value) in defaults {
+                if key != "stream" && key != "messages" {
+                    map.insert(key.clone(), value.clone());
+                }
             }
-        });
+        }
 
-        // Merge additional configuration from config.body while protecting critical fields
-        if let serde_json::Value::Object(mut map) = request_value {
-            if let serde_json::Value::Object(mut body) = serde_json::to_value(&config.body).unwrap_or_default() {
-                // Remove protected fields from config body
-                body.remove("stream");
-                body.remove("messages");
-                
-                // Merge remaining fields from config.body
-                for (key, value) in body {
-                    map.insert(key, value);
+        // Models that ignore sampling only get the parameters the caller set
+        if MODELS_WITHOUT_SAMPLING.contains(&model.as_str()) {
+            for key in SAMPLING_PARAMS {
+                if !body.contains_key(*key) {
+                    map.remove(*key);
                 }
             }
-            request_value = serde_json::Value::Object(map);
         }
 
+        // Merge the remaining body fields, combining nested objects
+        super::deep_merge(&mut map, body.clone());
+        // A null value means "don't send this parameter"
+        map.retain(|_, value| !value.is_null());
+
         // Convert the merged JSON value into our request structure
-        serde_json::from_value(request_value).unwrap_or_else(|_| DeepSeekRequest {
+        serde_json::from_value(serde_json::Value::Object(map)).unwrap_or(DeepSeekRequest {
             messages,
             stream,
-            additional_params: config.body.clone(),
+            additional_params: serde_json::Value::Object(body),
         })
     }
 
+    /// Returns the `max_tokens` a request with this configuration would send.
+    ///
+    /// Accounts for the built-in default, per-model defaults and any value
+    /// in the request body.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration options for the request
+    pub(crate) fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        self.build_request(Vec::new(), false, config)
+            .additional_params
+            .get("max_tokens")
+            .and_then(serde_json::Value::as_u64)
+    }
+
     /// Sends a non-streaming chat request to the DeepSeek API.
     ///
     /// # Arguments
@@ -297,48 +542,104 @@ impl DeepSeekClient {
     ) -> Result<DeepSeekResponse> {
         let headers = self.build_headers(Some(&config.headers))?;
         let request = self.build_request(messages, false, config);
+        let endpoint = self.endpoint(config);
 
+        let mut attempt = 1;
+        loop {
+            match self.send_chat(&endpoint, &headers, &request).await {
+                Ok(response) => return Ok(response),
+                Err(failure) if failure.error.is_retryable() && attempt < self.retry.max_attempts => {
+                    tracing::warn!("DeepSeek attempt {} failed, retrying: {}", attempt, failure.error);
+                    let backoff = Duration::from_millis(self.retry.backoff_ms * attempt as u64);
+                    let delay = match failure.error {
+                        ApiError::DeepSeekError { retry_after: Some(secs), .. } => {
+                            backoff.max(Duration::from_secs(secs.min(self.retry.max_retry_after_secs)))
+                        }
+                        _ => backoff,
+                    };
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(failure) => return Err(failure.into_error()),
+            }
+        }
+    }
+
+    /// Performs a single non-streaming request attempt.
+    ///
+    /// The body is read as text before parsing so a malformed response can
+    /// be reported if no retry succeeds.
+    async fn send_chat(
+        &self,
+        endpoint: &str,
+        headers: &HeaderMap,
+        request: &DeepSeekRequest,
+    ) -> std::result::Result<DeepSeekResponse, AttemptError> {
         let response = self
             .client
-            .post(DEEPSEEK_API_URL)
-            .headers(headers)
-            .json(&request)
+            .post(endpoint)
+            .headers(headers.clone())
+            .json(request)
             .send()
             .await
             .map_err(|e| ApiError::DeepSeekError { 
                 message: format!("Request failed: {}", e),
                 type_: "request_failed".to_string(),
                 param: None,
-                code: None
+                code: None,
+                retry_after: None,
+                status: None
             })?;
 
-        if !response.status().is_success() {
-            let error = response
+        let status = response.status();
+        if !status.is_success() {
+            let headers = response.headers().clone();
+            let body = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ApiError::DeepSeekError { 
-                message: error,
-                type_: "api_error".to_string(),
-                param: None,
-                code: None
-            });
+            return Err(parse_deepseek_error(status, &headers, &body).into());
         }
 
-        response
-            .json::<DeepSeekResponse>()
+        let request_id = response
+            .headers()
+            .get(&self.request_id_header)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let rate_limit = super::parse_rate_limit(response.headers(), super::RateLimitHeaders::OpenAi);
+        let body = response
+            .text()
             .await
             .map_err(|e| ApiError::DeepSeekError { 
+                message: format!("Failed to read response: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                retry_after: None,
+                status: None
+            })?;
+
+        let mut response = serde_json::from_str::<DeepSeekResponse>(&body).map_err(|e| AttemptError {
+            error: ApiError::DeepSeekError {
                 message: format!("Failed to parse response: {}", e),
                 type_: "parse_error".to_string(),
                 param: None,
-                code: None
-            })
+                code: None,
+                retry_after: None,
+                status: None
+            },
+            body: Some(body.clone()),
+        })?;
+        response.request_id = request_id;
+        response.rate_limit = rate_limit;
+        Ok(response)
     }
 
     /// Sends a streaming chat request to the DeepSeek API.
     ///
     /// Returns a stream that yields chunks of the model's response as they arrive.
+    /// The stream ends when DeepSeek sends its `[DONE]` sentinel or the connection
+    /// closes, so trailing chunks such as the final usage report are always yielded.
     ///
     /// # Arguments
     ///
@@ -366,11 +667,12 @@ impl DeepSeekClient {
         };
 
         let request = self.build_request(messages, true, config);
+        let endpoint = self.endpoint(config);
         let client = self.client.clone();
 
         Box::pin(async_stream::try_stream! {
-            let mut stream = client
-                .post(DEEPSEEK_API_URL)
+            let response = client
+                .post(endpoint)
                 .headers(headers)
                 .json(&request)
                 .send()
@@ -379,18 +681,35 @@ impl DeepSeekClient {
                     message: format!("Request failed: {}", e),
                     type_: "request_failed".to_string(),
                     param: None,
-                    code: None
-                })?
-                .bytes_stream();
+                    code: None,
+                    retry_after: None,
+                    status: None
+                })?;
+
+            let response = match response.status() {
+                status if status.is_success() => response,
+                status => {
+                    let headers = response.headers().clone();
+                    let body = response
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Unknown error".to_string());
+                    Err(parse_deepseek_error(status, &headers, &body))?
+                }
+            };
+
+            let mut stream = response.bytes_stream();
 
             let mut data = String::new();
             
-            while let Some(chunk) = stream.next().await {
+            'chunks: while let Some(chunk) = stream.next().await {
                 let chunk = chunk.map_err(|e| ApiError::DeepSeekError { 
                     message: format!("Stream error: {}", e),
                     type_: "stream_error".to_string(),
                     param: None,
-                    code: None
+                    code: None,
+                    retry_after: None,
+                    status: None
                 })?;
                 data.push_str(&String::from_utf8_lossy(&chunk));
 
@@ -400,8 +719,12 @@ impl DeepSeekClient {
                     let line = &data[start..end].trim();
                     start = end + 2;
                     
-                    if line.starts_with("data: ") {
-                        let json_data = &line["data: ".len()..];
+                    if let Some(json_data) = line.strip_prefix("data: ") {
+                        // DeepSeek terminates the stream with an explicit sentinel
+                        if json_data.trim() == STREAM_DONE_SENTINEL {
+                            break 'chunks;
+                        }
+
                         if let Ok(response) = serde_json::from_str::<StreamResponse>(json_data) {
                             yield response;
                         }
@@ -415,3 +738,383 @@ impl DeepSeekClient {
         })
     }
 }
+
+impl super::ReasoningProvider for DeepSeekClient {
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<DeepSeekResponse>> {
+        Box::pin(DeepSeekClient::chat(self, messages, config))
+    }
+
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse>> + Send>> {
+        DeepSeekClient::chat_stream(self, messages, config)
+    }
+
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        DeepSeekClient::effective_max_tokens(self, config)
+    }
+
+    fn request_body(&self, messages: &[Message], config: &ApiConfig) -> Option<serde_json::Value> {
+        serde_json::to_value(self.build_request(messages.to_vec(), false, config)).ok()
+    }
+
+    fn api_token(&self) -> &str {
+        &self.api_token
+    }
+}
+
+/// Longest malformed body quoted in an error, in characters.
+const MAX_ERROR_BODY_CHARS: usize = 2000;
+
+/// A failed request attempt.
+#[derive(Debug)]
+struct AttemptError {
+    error: ApiError,
+    /// The response body, if it could not be parsed
+    body: Option<String>,
+}
+
+impl From<ApiError> for AttemptError {
+    fn from(error: ApiError) -> Self {
+        Self { error, body: None }
+    }
+}
+
+impl AttemptError {
+    /// Returns the error for the caller, quoting any malformed body.
+    fn into_error(self) -> ApiError {
+        match (self.error, self.body) {
+            (ApiError::DeepSeekError { message, type_, param, code, retry_after, status }, Some(body)) => {
+                let body: String = body.chars().take(MAX_ERROR_BODY_CHARS).collect();
+                ApiError::DeepSeekError {
+                    message: format!("{}; body: {}", message, body),
+                    type_,
+                    param,
+                    code,
+                    retry_after,
+                    status,
+                }
+            }
+            (error, _) => error,
+        }
+    }
+}
+
+/// Error envelope returned by the DeepSeek API.
+#[derive(Debug, Deserialize)]
+struct DeepSeekErrorBody {
+    error: DeepSeekErrorDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepSeekErrorDetails {
+    message: String,
+    #[serde(rename = "type")]
+    type_: Option<String>,
+    param: Option<String>,
+    code: Option<serde_json::Value>,
+}
+
+/// Converts a failed DeepSeek HTTP response into an `ApiError`.
+///
+/// Parses DeepSeek's error JSON when present, falling back to the raw body
+/// as the message. Responses without an error type are classified by status
+/// so 5xx and 429 responses remain retryable.
+///
+/// # Arguments
+///
+/// * `status` - The HTTP status of the response
+/// * `headers` - The response headers, checked for `Retry-After`
+/// * `body` - The raw response body
+///
+/// # Returns
+///
+/// An `ApiError::DeepSeekError` describing the failure
+pub(crate) fn parse_deepseek_error(status: StatusCode, headers: &HeaderMap, body: &str) -> ApiError {
+    let retry_after = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let fallback_type = if status == StatusCode::TOO_MANY_REQUESTS {
+        "rate_limit_error"
+    } else if status.is_server_error() {
+        "server_error"
+    } else {
+        "api_error"
+    };
+
+    match serde_json::from_str::<DeepSeekErrorBody>(body) {
+        Ok(DeepSeekErrorBody { error }) => ApiError::DeepSeekError {
+            message: error.message,
+            type_: error.type_.unwrap_or_else(|| fallback_type.to_string()),
+            param: error.param,
+            code: error.code.map(|code| match code {
+                serde_json::Value::String(code) => code,
+                other => other.to_string(),
+            }),
+            retry_after,
+            status: Some(status.as_u16()),
+        },
+        Err(_) => ApiError::DeepSeekError {
+            message: body.to_string(),
+            type_: fallback_type.to_string(),
+            param: None,
+            code: None,
+            retry_after,
+            status: Some(status.as_u16()),
+        },
+    }
+}
+
+/// Parameters validated against DeepSeek's accepted range before sending.
+const PENALTY_PARAMS: [&str; 2] = ["frequency_penalty", "presence_penalty"];
+
+/// Validates DeepSeek-specific body parameters.
+///
+/// Checks that `frequency_penalty` and `presence_penalty`, when present, are
+/// numbers within DeepSeek's accepted range of -2.0 to 2.0, that `seed` is a
+/// non-negative integer and that `logit_bias` maps token ids to biases
+/// between -100 and 100. Valid values are forwarded unchanged by the generic
+/// body merge in `build_request`.
+///
+/// # Arguments
+///
+/// * `body` - The client-supplied DeepSeek request body
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` naming the first invalid parameter
+pub(crate) fn validate_body(body: &serde_json::Value) -> Result<()> {
+    for param in PENALTY_PARAMS {
+        let Some(value) = body.get(param) else {
+            continue;
+        };
+        match value.as_f64() {
+            Some(penalty) if (-2.0..=2.0).contains(&penalty) => {}
+            _ => {
+                return Err(ApiError::BadRequest {
+                    message: format!("{} must be a number between -2.0 and 2.0", param),
+                })
+            }
+        }
+    }
+
+    if body.get("seed").is_some_and(|seed| seed.as_u64().is_none()) {
+        return Err(ApiError::BadRequest {
+            message: "seed must be a non-negative integer".to_string(),
+        });
+    }
+
+    if let Some(logit_bias) = body.get("logit_bias") {
+        validate_logit_bias(logit_bias)?;
+    }
+
+    Ok(())
+}
+
+/// Validates a `logit_bias` map.
+///
+/// The map must be an object whose keys are token ids and whose values are
+/// numbers within the accepted bias range of -100 to 100.
+///
+/// # Arguments
+///
+/// * `logit_bias` - The client-supplied `logit_bias` value
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` naming the first invalid entry
+fn validate_logit_bias(logit_bias: &serde_json::Value) -> Result<()> {
+    let Some(biases) = logit_bias.as_object() else {
+        return Err(ApiError::BadRequest {
+            message: "logit_bias must be an object mapping token ids to biases".to_string(),
+        });
+    };
+
+    for (token, bias) in biases {
+        if token.parse::<u32>().is_err() {
+            return Err(ApiError::BadRequest {
+                message: format!("logit_bias key {:?} is not a token id", token),
+            });
+        }
+        match bias.as_f64() {
+            Some(bias) if (-100.0..=100.0).contains(&bias) => {}
+            _ => {
+                return Err(ApiError::BadRequest {
+                    message: format!("logit_bias for token {} must be a number between -100 and 100", token),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the model a request body will use, falling back to the default.
+///
+/// # Arguments
+///
+/// * `body` - The client-supplied request body
+pub(crate) fn requested_model(body: &serde_json::Value) -> &str {
+    body.get("model").and_then(|m| m.as_str()).unwrap_or(DEFAULT_MODEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Role;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const VALID_BODY: &str = r#"{"id":"r1","object":"chat.completion","created":0,"model":"deepseek-reasoner","choices":[{"index":0,"message":{"role":"assistant","content":"","reasoning_content":"thought"},"logprobs":null,"finish_reason":"stop"}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2},"system_fingerprint":"fp"}"#;
+
+    /// Serves one canned 200 response per connection, in order.
+    async fn serve(bodies: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for body in bodies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 8192];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn client(base_url: String, max_attempts: u32) -> DeepSeekClient {
+        DeepSeekClient::new("token".to_string())
+            .with_base_url(Some(base_url))
+            .with_retry(RetryConfig {
+                max_attempts,
+                backoff_ms: 1,
+                max_retry_after_secs: 0,
+            })
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "hi".to_string(),
+            tool_blocks: Vec::new(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn truncated_body_is_retried() {
+        let truncated = &VALID_BODY[..VALID_BODY.len() / 2];
+        let base_url = serve(vec![truncated, VALID_BODY]).await;
+
+        let response = client(base_url, 2).chat(messages(), &ApiConfig::default()).await.unwrap();
+
+        assert_eq!(response.id, "r1");
+        assert_eq!(response.choices[0].message.reasoning_content.as_deref(), Some("thought"));
+    }
+
+    #[tokio::test]
+    async fn final_parse_error_quotes_body() {
+        let truncated = &VALID_BODY[..20];
+        let base_url = serve(vec![truncated, truncated]).await;
+
+        let error = client(base_url, 2).chat(messages(), &ApiConfig::default()).await.unwrap_err();
+
+        match error {
+            ApiError::DeepSeekError { message, type_, .. } => {
+                assert_eq!(type_, "parse_error");
+                assert!(message.ends_with(&format!("; body: {}", truncated)), "{}", message);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reasoner_gets_no_temperature_from_model_defaults() {
+        let defaults = HashMap::from([(
+            DEFAULT_MODEL.to_string(),
+            ModelDefaults::from([
+                ("temperature".to_string(), serde_json::json!(0.6)),
+                ("top_p".to_string(), serde_json::json!(0.9)),
+            ]),
+        )]);
+        let client = DeepSeekClient::new("token".to_string()).with_model_defaults(defaults);
+
+        let request = serde_json::to_value(client.build_request(messages(), false, &ApiConfig::default())).unwrap();
+        assert!(request.get("temperature").is_none(), "{}", request);
+        assert!(request.get("top_p").is_none(), "{}", request);
+
+        let config = ApiConfig {
+            body: serde_json::json!({ "temperature": 0.2 }),
+            ..Default::default()
+        };
+        let request = serde_json::to_value(client.build_request(messages(), false, &config)).unwrap();
+        assert_eq!(request["temperature"], serde_json::json!(0.2));
+    }
+
+    #[test]
+    fn denied_model_and_max_tokens_fall_back_to_defaults() {
+        let client = DeepSeekClient::new("token".to_string()).with_denied_params(vec![
+            "model".to_string(),
+            "max_tokens".to_string(),
+            "response_format".to_string(),
+        ]);
+        let config = ApiConfig {
+            body: serde_json::json!({
+                "model": "deepseek-chat",
+                "max_tokens": 100_000,
+                "response_format": { "type": "json_object" },
+            }),
+            ..Default::default()
+        };
+
+        let request = serde_json::to_value(client.build_request(messages(), false, &config)).unwrap();
+
+        assert_eq!(request["model"], serde_json::json!(DEFAULT_MODEL));
+        assert_eq!(request["max_tokens"], serde_json::json!(8192));
+        assert_eq!(request["response_format"], serde_json::json!({ "type": DEFAULT_RESPONSE_FORMAT }));
+    }
+
+    #[test]
+    fn retry_after_only_makes_rate_limits_and_server_errors_retryable() {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::RETRY_AFTER, "3".parse().unwrap());
+        let body = r#"{"error":{"message":"m","type":"invalid_request_error"}}"#;
+
+        for (status, retryable) in [
+            (StatusCode::BAD_REQUEST, false),
+            (StatusCode::UNAUTHORIZED, false),
+            (StatusCode::TOO_MANY_REQUESTS, true),
+            (StatusCode::SERVICE_UNAVAILABLE, true),
+        ] {
+            let error = parse_deepseek_error(status, &headers, body);
+            assert_eq!(error.is_retryable(), retryable, "{}", status);
+        }
+    }
+
+    #[test]
+    fn rate_limit_errors_map_to_too_many_requests() {
+        let error = parse_deepseek_error(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), "slow down");
+
+        let (status, response) = error.to_status_and_response();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.error.type_, "deepseek_rate_limit_error");
+
+        let error = parse_deepseek_error(StatusCode::BAD_REQUEST, &HeaderMap::new(), "bad");
+        assert_eq!(error.to_status_and_response().0, StatusCode::BAD_REQUEST);
+    }
+}
+
@@ -0,0 +1,257 @@
+//! Canned in-memory providers for local development and tests.
+//!
+//! When `providers.mock_enabled` is set, or the server is started with
+//! `--mock`, these providers replace the real DeepSeek and Anthropic
+//! clients. They return a fixed reasoning trace and a fixed answer with
+//! usage numbers counted by the bundled tokenizers, and stream both word
+//! by word with a short delay so clients see realistic pacing. No API keys
+//! are needed and nothing leaves the process.
+
+use super::{
+    anthropic::{self, AnthropicResponse},
+    deepseek::{self, DeepSeekResponse},
+    AnswerProvider, ReasoningProvider,
+};
+use crate::{
+    error::Result,
+    models::{ApiConfig, Message, Provider},
+    tokenizer,
+};
+use futures::{future::BoxFuture, Stream};
+use std::{pin::Pin, time::Duration};
+
+/// Reasoning returned by the mock DeepSeek provider.
+const MOCK_REASONING: &str = "The user is asking a question. This is a mock reasoning trace \
+produced without calling DeepSeek. A real response would break the problem down step by step \
+before handing the reasoning over to the answering model.";
+
+/// Answer returned by the mock Anthropic provider.
+const MOCK_ANSWER: &str = "This is a mock answer produced without calling Anthropic. \
+Configure real API keys and disable mock mode to get genuine responses.";
+
+/// Model id reported by the mock DeepSeek provider.
+const MOCK_DEEPSEEK_MODEL: &str = "deepseek-reasoner";
+
+/// Model id reported by the mock Anthropic provider.
+const MOCK_ANTHROPIC_MODEL: &str = "claude-3-5-sonnet-20241022";
+
+/// Delay between streamed chunks.
+const MOCK_CHUNK_DELAY: Duration = Duration::from_millis(20);
+
+/// Splits text into word-sized chunks, keeping the whitespace after each word.
+fn chunks(text: &str) -> Vec<String> {
+    text.split_inclusive(' ').map(String::from).collect()
+}
+
+/// Counts the prompt tokens of a conversation with the provider's tokenizer.
+fn prompt_tokens(provider: Provider, messages: &[Message], system: Option<&str>) -> u32 {
+    let text = messages
+        .iter()
+        .map(|msg| msg.content.as_str())
+        .chain(system)
+        .collect::<Vec<_>>()
+        .join("\n");
+    tokenizer::count_tokens(provider, &text) as u32
+}
+
+/// Mock replacement for the DeepSeek reasoning provider.
+#[derive(Debug, Default)]
+pub struct MockDeepSeekClient;
+
+impl MockDeepSeekClient {
+    fn usage(messages: &[Message]) -> deepseek::Usage {
+        let prompt_tokens = prompt_tokens(Provider::DeepSeek, messages, None);
+        let reasoning_tokens = tokenizer::count_tokens(Provider::DeepSeek, MOCK_REASONING) as u32;
+        deepseek::Usage {
+            prompt_tokens,
+            completion_tokens: reasoning_tokens,
+            total_tokens: prompt_tokens + reasoning_tokens,
+            prompt_tokens_details: deepseek::PromptTokensDetails { cached_tokens: 0 },
+            completion_tokens_details: deepseek::CompletionTokensDetails { reasoning_tokens },
+            prompt_cache_hit_tokens: 0,
+            prompt_cache_miss_tokens: prompt_tokens,
+        }
+    }
+
+    fn stream_chunk(
+        delta: deepseek::StreamDelta,
+        finish_reason: Option<String>,
+        usage: Option<deepseek::Usage>,
+    ) -> deepseek::StreamResponse {
+        deepseek::StreamResponse {
+            id: "mock-deepseek".to_string(),
+            object: "chat.completion.chunk".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: MOCK_DEEPSEEK_MODEL.to_string(),
+            choices: vec![deepseek::StreamChoice {
+                index: 0,
+                delta,
+                logprobs: None,
+                finish_reason,
+            }],
+            usage,
+            system_fingerprint: "mock".to_string(),
+        }
+    }
+}
+
+impl ReasoningProvider for MockDeepSeekClient {
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        _config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<DeepSeekResponse>> {
+        Box::pin(async move {
+            Ok(DeepSeekResponse {
+                id: "mock-deepseek".to_string(),
+                object: "chat.completion".to_string(),
+                created: chrono::Utc::now().timestamp(),
+                model: MOCK_DEEPSEEK_MODEL.to_string(),
+                choices: vec![deepseek::Choice {
+                    index: 0,
+                    message: deepseek::AssistantMessage {
+                        role: "assistant".to_string(),
+                        content: Some(String::new()),
+                        reasoning_content: Some(MOCK_REASONING.to_string()),
+                    },
+                    logprobs: None,
+                    finish_reason: Some("stop".to_string()),
+                }],
+                usage: Self::usage(&messages),
+                system_fingerprint: "mock".to_string(),
+                request_id: Some("mock-deepseek".to_string()),
+                rate_limit: None,
+            })
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        _config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<deepseek::StreamResponse>> + Send>> {
+        Box::pin(async_stream::stream! {
+            for chunk in chunks(MOCK_REASONING) {
+                tokio::time::sleep(MOCK_CHUNK_DELAY).await;
+                yield Ok(Self::stream_chunk(
+                    deepseek::StreamDelta {
+                        role: None,
+                        content: None,
+                        reasoning_content: Some(chunk),
+                    },
+                    None,
+                    None,
+                ));
+            }
+
+            yield Ok(Self::stream_chunk(
+                deepseek::StreamDelta {
+                    role: None,
+                    content: Some(String::new()),
+                    reasoning_content: None,
+                },
+                Some("stop".to_string()),
+                Some(Self::usage(&messages)),
+            ));
+        })
+    }
+
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        config.body.get("max_tokens").and_then(serde_json::Value::as_u64)
+    }
+}
+
+/// Mock replacement for the Anthropic answer provider.
+#[derive(Debug, Default)]
+pub struct MockAnthropicClient;
+
+impl MockAnthropicClient {
+    fn usage(messages: &[Message], system: Option<&str>) -> anthropic::Usage {
+        anthropic::Usage {
+            input_tokens: prompt_tokens(Provider::Anthropic, messages, system),
+            output_tokens: tokenizer::count_tokens(Provider::Anthropic, MOCK_ANSWER) as u32,
+            cache_creation_input_tokens: 0,
+            cache_read_input_tokens: 0,
+        }
+    }
+
+    fn response(content: Vec<anthropic::ContentBlock>, usage: anthropic::Usage) -> AnthropicResponse {
+        AnthropicResponse {
+            id: "mock-anthropic".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            model: MOCK_ANTHROPIC_MODEL.to_string(),
+            content,
+            stop_reason: Some("end_turn".to_string()),
+            stop_sequence: None,
+            usage,
+            request_id: Some("mock-anthropic".to_string()),
+            rate_limit: None,
+        }
+    }
+}
+
+impl AnswerProvider for MockAnthropicClient {
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        _config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<AnthropicResponse>> {
+        Box::pin(async move {
+            let content = vec![anthropic::ContentBlock {
+                content_type: "text".to_string(),
+                text: MOCK_ANSWER.to_string(),
+                tool_call: None,
+            }];
+            Ok(Self::response(content, Self::usage(&messages, system.as_deref())))
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        _config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<anthropic::StreamEvent>> + Send>> {
+        Box::pin(async_stream::stream! {
+            let usage = Self::usage(&messages, system.as_deref());
+            yield Ok(anthropic::StreamEvent::MessageStart {
+                message: Box::new(Self::response(Vec::new(), usage.clone())),
+            });
+            yield Ok(anthropic::StreamEvent::ContentBlockStart {
+                index: 0,
+                content_block: anthropic::ContentBlock {
+                    content_type: "text".to_string(),
+                    text: String::new(),
+                    tool_call: None,
+                },
+            });
+
+            for chunk in chunks(MOCK_ANSWER) {
+                tokio::time::sleep(MOCK_CHUNK_DELAY).await;
+                yield Ok(anthropic::StreamEvent::ContentBlockDelta {
+                    index: 0,
+                    delta: anthropic::ContentDelta {
+                        delta_type: "text_delta".to_string(),
+                        text: chunk,
+                    },
+                });
+            }
+
+            yield Ok(anthropic::StreamEvent::ContentBlockStop { index: 0 });
+            yield Ok(anthropic::StreamEvent::MessageDelta {
+                delta: anthropic::MessageDelta {
+                    stop_reason: Some("end_turn".to_string()),
+                    stop_sequence: None,
+                },
+                usage: Some(usage),
+            });
+            yield Ok(anthropic::StreamEvent::MessageStop);
+        })
+    }
+
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64> {
+        config.body.get("max_tokens").and_then(serde_json::Value::as_u64)
+    }
+}
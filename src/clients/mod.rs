@@ -3,28 +3,278 @@
 //! This module contains client implementations for different AI model providers:
 //! - `anthropic`: Client for Anthropic's Claude models
 //! - `deepseek`: Client for DeepSeek's reasoning models
+//! - `mock`: Canned in-memory providers for local development and tests
 //!
 //! Each client handles authentication, request building, and response parsing
-//! specific to its provider's API.
+//! specific to its provider's API. The handlers use them through the
+//! `ReasoningProvider` and `AnswerProvider` traits so the mock providers can
+//! stand in for the real ones.
 
 pub mod anthropic;
 pub mod deepseek;
+pub mod mock;
 
 pub use anthropic::AnthropicClient;
 pub use deepseek::DeepSeekClient;
 
-use crate::error::Result;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use std::collections::HashMap;
+use crate::{
+    config::HttpConfig,
+    error::Result,
+    models::{ApiConfig, Message, RateLimit},
+};
+use futures::{future::BoxFuture, Stream};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Certificate, Client, Proxy,
+};
+use std::{collections::HashMap, pin::Pin};
+
+/// A provider for the reasoning stage of the pipeline.
+pub trait ReasoningProvider: Send + Sync {
+    /// Sends a non-streaming chat request.
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<deepseek::DeepSeekResponse>>;
+
+    /// Sends a streaming chat request.
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<deepseek::StreamResponse>> + Send>>;
+
+    /// Returns the `max_tokens` a request with this configuration would send.
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64>;
+
+    /// Returns the body a non-streaming request would send, for `debug_echo`.
+    ///
+    /// `None` for providers that make no HTTP request.
+    fn request_body(&self, _messages: &[Message], _config: &ApiConfig) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Returns the API token requests are sent with, which scopes cached
+    /// reasoning to the caller.
+    ///
+    /// Empty for providers that make no HTTP request.
+    fn api_token(&self) -> &str {
+        ""
+    }
+}
+
+/// A provider for the answer stage of the pipeline.
+pub trait AnswerProvider: Send + Sync {
+    /// Sends a non-streaming chat request.
+    fn chat<'a>(
+        &'a self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &'a ApiConfig,
+    ) -> BoxFuture<'a, Result<anthropic::AnthropicResponse>>;
+
+    /// Sends a streaming chat request.
+    fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        system: Option<String>,
+        config: &ApiConfig,
+    ) -> Pin<Box<dyn Stream<Item = Result<anthropic::StreamEvent>> + Send>>;
+
+    /// Returns the `max_tokens` a request with this configuration would send.
+    fn effective_max_tokens(&self, config: &ApiConfig) -> Option<u64>;
+
+    /// Returns the body a non-streaming request would send, for `debug_echo`.
+    ///
+    /// `None` for providers that make no HTTP request.
+    fn request_body(
+        &self,
+        _messages: &[Message],
+        _system: Option<&str>,
+        _config: &ApiConfig,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Naming scheme of a provider's rate-limit response headers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RateLimitHeaders {
+    /// `anthropic-ratelimit-{requests,tokens,input-tokens,output-tokens}-{limit,remaining,reset}`
+    Anthropic,
+    /// `x-ratelimit-{limit,remaining,reset}-{requests,tokens}`, as used by OpenAI-compatible APIs
+    OpenAi,
+}
+
+/// Reads a provider's rate-limit state from its response headers.
+///
+/// # Arguments
+///
+/// * `headers` - Headers of a successful provider response
+/// * `scheme` - The provider's header naming scheme
+///
+/// # Returns
+///
+/// * `Option<RateLimit>` - The rate-limit state, or None if the response has no rate-limit headers
+pub(crate) fn parse_rate_limit(headers: &HeaderMap, scheme: RateLimitHeaders) -> Option<RateLimit> {
+    let text = |name: String| {
+        headers
+            .get(name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let bucket = |kind: &str| {
+        let (limit, remaining, reset) = match scheme {
+            RateLimitHeaders::Anthropic => (
+                format!("anthropic-ratelimit-{}-limit", kind),
+                format!("anthropic-ratelimit-{}-remaining", kind),
+                format!("anthropic-ratelimit-{}-reset", kind),
+            ),
+            RateLimitHeaders::OpenAi => (
+                format!("x-ratelimit-limit-{}", kind),
+                format!("x-ratelimit-remaining-{}", kind),
+                format!("x-ratelimit-reset-{}", kind),
+            ),
+        };
+        (
+            text(limit).and_then(|value| value.parse::<u64>().ok()),
+            text(remaining).and_then(|value| value.parse::<u64>().ok()),
+            text(reset),
+        )
+    };
+    let token_kinds: &[&str] = match scheme {
+        RateLimitHeaders::Anthropic => &["tokens", "input-tokens", "output-tokens"],
+        RateLimitHeaders::OpenAi => &["tokens"],
+    };
+
+    let (requests_limit, requests_remaining, requests_reset) = bucket("requests");
+    // The most constrained token kind, by tokens remaining
+    let (tokens_limit, tokens_remaining, tokens_reset) = token_kinds
+        .iter()
+        .map(|kind| bucket(kind))
+        .filter(|(_, remaining, _)| remaining.is_some())
+        .min_by_key(|(_, remaining, _)| *remaining)
+        .unwrap_or_default();
+
+    let rate_limit = RateLimit {
+        requests_limit,
+        requests_remaining,
+        requests_reset,
+        tokens_limit,
+        tokens_remaining,
+        tokens_reset,
+    };
+    (rate_limit != RateLimit::default()).then_some(rate_limit)
+}
+
+/// Builds the HTTP client shared by all provider clients.
+///
+/// Applies the TLS settings from the configuration: an optional extra root
+/// certificate, for networks with a TLS-inspecting proxy, and the
+/// development-only `danger_accept_invalid_certs` switch. An explicit
+/// `proxy_url` routes all traffic through that proxy; otherwise the
+/// standard proxy environment variables are honored.
+///
+/// # Arguments
+///
+/// * `config` - The HTTP configuration
+///
+/// # Returns
+///
+/// * `anyhow::Result<Client>` - The configured client
+///
+/// # Errors
+///
+/// Returns an error if the root certificate cannot be read or parsed, the
+/// proxy URL is invalid, or the client cannot be built
+pub fn build_http_client(config: &HttpConfig) -> anyhow::Result<Client> {
+    Ok(http_client_builder(config)?.build()?)
+}
+
+/// Starts an HTTP client with the TLS and proxy settings of [`build_http_client`].
+///
+/// # Arguments
+///
+/// * `config` - The HTTP configuration
+///
+/// # Returns
+///
+/// * `anyhow::Result<reqwest::ClientBuilder>` - The configured builder
+///
+/// # Errors
+///
+/// Returns an error if the root certificate cannot be read or parsed, or
+/// the proxy URL is invalid
+pub fn http_client_builder(config: &HttpConfig) -> anyhow::Result<reqwest::ClientBuilder> {
+    let mut builder = Client::builder();
+
+    if let Some(path) = &config.root_cert_path {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read root certificate {}: {}", path, e))?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    if let Some(url) = &config.proxy_url {
+        let mut proxy = Proxy::all(url)?;
+        if let Some(username) = &config.proxy_username {
+            proxy = proxy.basic_auth(username, config.proxy_password.as_deref().unwrap_or_default());
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    if config.danger_accept_invalid_certs {
+        tracing::warn!("TLS certificate verification is disabled for upstream requests");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
+
+/// Headers carrying credentials or the API version, which client-supplied
+/// headers may only set when explicitly allowed.
+const PROTECTED_HEADERS: &[&str] = &["x-api-key", "authorization", "anthropic-version"];
+
+/// Which client-supplied headers are forwarded to a provider.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderPolicy {
+    /// Headers that may be forwarded; any unprotected header when unset
+    pub allowed: Option<Vec<String>>,
+    /// Headers that are never forwarded
+    pub denied: Vec<String>,
+}
+
+impl HeaderPolicy {
+    /// Checks whether a client-supplied header may be forwarded.
+    ///
+    /// Names are compared case-insensitively. Protected headers are only
+    /// forwarded when they are listed in `allowed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name
+    pub fn permits(&self, name: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+        if listed(&self.denied) {
+            return false;
+        }
+        match &self.allowed {
+            Some(allowed) => listed(allowed),
+            None => !PROTECTED_HEADERS.iter().any(|n| n.eq_ignore_ascii_case(name)),
+        }
+    }
+}
 
 /// Converts a HashMap of string headers to a reqwest HeaderMap.
 ///
 /// This function is used internally by clients to convert user-provided
-/// header maps into the format required by reqwest.
+/// header maps into the format required by reqwest. Headers the policy
+/// does not permit are dropped and logged at debug level.
 ///
 /// # Arguments
 ///
 /// * `headers` - A HashMap containing header names and values as strings
+/// * `policy` - Which headers may be forwarded
 ///
 /// # Returns
 ///
@@ -36,10 +286,15 @@ use std::collections::HashMap;
 /// Returns `ApiError::BadRequest` if:
 /// - A header name contains invalid characters
 /// - A header value contains invalid characters
-pub(crate) fn build_headers(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+pub(crate) fn build_headers(headers: &HashMap<String, String>, policy: &HeaderPolicy) -> Result<HeaderMap> {
     let mut header_map = HeaderMap::new();
     
     for (key, value) in headers {
+        if !policy.permits(key) {
+            tracing::debug!("Dropped custom header {} not permitted for the provider", key);
+            continue;
+        }
+
         let header_name = HeaderName::from_bytes(key.as_bytes())
             .map_err(|e| crate::error::ApiError::BadRequest { 
                 message: format!("Invalid header name: {}", e) 
@@ -55,3 +310,164 @@ pub(crate) fn build_headers(headers: &HashMap<String, String>) -> Result<HeaderM
     
     Ok(header_map)
 }
+
+/// Merges `source` into `target`, recursing into objects present in both.
+///
+/// Nested objects are combined field by field, so a client overriding one
+/// field of an object keeps the object's other fields. Any other value in
+/// `source`, including arrays, replaces the value in `target`.
+///
+/// # Arguments
+///
+/// * `target` - The object merged into
+/// * `source` - The object whose fields take precedence
+pub(crate) fn deep_merge(
+    target: &mut serde_json::Map<String, serde_json::Value>,
+    source: serde_json::Map<String, serde_json::Value>,
+) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(serde_json::Value::Object(existing)), serde_json::Value::Object(value)) => {
+                deep_merge(existing, value);
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// How long a warmup request may take before it is abandoned.
+const WARMUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Primes the connection pool with a HEAD request to each provider.
+///
+/// Establishing TLS to both providers up front spares the first request
+/// after startup that latency. Any response, including an error status,
+/// leaves a pooled connection behind; failures are only logged.
+///
+/// # Arguments
+///
+/// * `client` - The shared HTTP client used for provider requests
+/// * `deepseek_base_url` - The configured DeepSeek base URL, if any
+/// * `anthropic_base_url` - The configured Anthropic base URL, if any
+pub async fn warmup(client: &Client, deepseek_base_url: Option<&str>, anthropic_base_url: Option<&str>) {
+    let targets = [
+        ("DeepSeek", deepseek_base_url.unwrap_or(deepseek::DEEPSEEK_BASE_URL)),
+        ("Anthropic", anthropic_base_url.unwrap_or(anthropic::ANTHROPIC_BASE_URL)),
+    ];
+    let requests = targets.into_iter().map(|(provider, url)| async move {
+        let start = std::time::Instant::now();
+        match client.head(url).timeout(WARMUP_TIMEOUT).send().await {
+            Ok(_) => tracing::info!("Warmed up {} connection in {}ms", provider, start.elapsed().as_millis()),
+            Err(e) => tracing::warn!("Failed to warm up {} connection: {}", provider, e),
+        }
+    });
+    futures::future::join_all(requests).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Role;
+    use serde_json::json;
+
+    fn object(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        match value {
+            serde_json::Value::Object(map) => map,
+            other => panic!("not an object: {}", other),
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "hi".to_string(),
+            tool_blocks: Vec::new(),
+        }]
+    }
+
+    #[test]
+    fn deep_merge_combines_nested_objects() {
+        let mut target = object(json!({
+            "model": "m",
+            "thinking": { "type": "enabled", "budget_tokens": 1024 },
+            "metadata": { "user": { "id": "a", "tier": "free" } },
+        }));
+        deep_merge(&mut target, object(json!({
+            "thinking": { "budget_tokens": 2048 },
+            "metadata": { "user": { "tier": "pro" }, "trace": "t" },
+            "top_p": 0.9,
+        })));
+
+        assert_eq!(serde_json::Value::Object(target), json!({
+            "model": "m",
+            "thinking": { "type": "enabled", "budget_tokens": 2048 },
+            "metadata": { "user": { "id": "a", "tier": "pro" }, "trace": "t" },
+            "top_p": 0.9,
+        }));
+    }
+
+    #[test]
+    fn deep_merge_replaces_arrays_scalars_and_mismatched_types() {
+        let mut target = object(json!({
+            "stop": ["a", "b"],
+            "temperature": 1.0,
+            "response_format": { "type": "text" },
+            "tools": [{ "name": "x" }],
+        }));
+        deep_merge(&mut target, object(json!({
+            "stop": ["c"],
+            "temperature": 0.2,
+            "response_format": "json",
+            "tools": { "name": "y" },
+        })));
+
+        assert_eq!(serde_json::Value::Object(target), json!({
+            "stop": ["c"],
+            "temperature": 0.2,
+            "response_format": "json",
+            "tools": { "name": "y" },
+        }));
+    }
+
+    #[test]
+    fn deepseek_body_cannot_override_protected_keys() {
+        let client = deepseek::DeepSeekClient::new("token".to_string());
+        let config = ApiConfig {
+            body: json!({
+                "stream": true,
+                "messages": [{ "role": "user", "content": "injected" }],
+                "max_tokens": 7,
+            }),
+            ..Default::default()
+        };
+
+        let body = ReasoningProvider::request_body(&client, &messages(), &config).unwrap();
+
+        assert_eq!(body["stream"], json!(false));
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "hi" }]));
+        assert_eq!(body["max_tokens"], json!(7));
+    }
+
+    #[test]
+    fn anthropic_body_cannot_override_protected_keys() {
+        let client = anthropic::AnthropicClient::new("token".to_string());
+        let config = ApiConfig {
+            body: json!({
+                "stream": true,
+                "messages": [{ "role": "user", "content": "injected" }],
+                "system": "injected",
+                "max_tokens": 7,
+            }),
+            ..Default::default()
+        };
+
+        let body = AnswerProvider::request_body(&client, &messages(), Some("server"), &config).unwrap();
+
+        assert_eq!(body["stream"], json!(false));
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "hi" }]));
+        assert_eq!(body["system"], json!("server"));
+        assert_eq!(body["max_tokens"], json!(7));
+    }
+}
@@ -4,8 +4,12 @@
 //! and environment variables. It includes pricing configurations for different
 //! AI model providers and server settings.
 
+use crate::{
+    clients::HeaderPolicy,
+    models::{DuplicateSystemPolicy, EmptyMessagePolicy, Message},
+};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 /// Root configuration structure containing all application settings.
 ///
@@ -15,8 +19,42 @@ use std::path::Path;
 pub struct Config {
     pub server: ServerConfig,
     pub pricing: PricingConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub model_defaults: HashMap<String, ModelDefaults>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub reasoning: ReasoningConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub providers: ProvidersConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub request_log: RequestLogConfig,
+    /// Keys in `config.toml` that match no setting, reported by `validate`
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
 }
 
+/// Default request body parameters for a single model.
+///
+/// Keys are provider request fields such as `temperature` or `max_tokens`.
+/// Values here override the built-in defaults but never a value supplied
+/// explicitly in the request body.
+pub type ModelDefaults = HashMap<String, serde_json::Value>;
+
 /// Server-specific configuration settings.
 ///
 /// Contains settings related to the HTTP server, such as the
@@ -25,6 +63,524 @@ pub struct Config {
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// How long finished streams stay available for `Last-Event-ID` replay (0 disables)
+    #[serde(default = "default_stream_replay_ttl_secs")]
+    pub stream_replay_ttl_secs: u64,
+    /// How long responses are reused for a repeated `Idempotency-Key` (0 disables)
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub idempotency_ttl_secs: u64,
+    /// Bearer token required by the `/admin` endpoints (disabled when unset)
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Open connections to both providers at startup; `/ready` fails until done
+    #[serde(default)]
+    pub warmup: bool,
+    /// Reconnection delay suggested to SSE clients with a `retry:` field
+    #[serde(default)]
+    pub sse_retry_ms: Option<u64>,
+    /// Echo the providers' remaining requests and tokens as response headers
+    #[serde(default)]
+    pub rate_limit_headers: bool,
+    /// Log a warning when a provider has less than this fraction of its
+    /// request or token limit left
+    #[serde(default)]
+    pub rate_limit_warning_ratio: Option<f64>,
+    /// Seconds between log lines reporting the running streaming tasks
+    #[serde(default)]
+    pub task_log_interval_secs: Option<u64>,
+    /// Refuse to start when `config.toml` has keys that match no setting,
+    /// as with `--strict-config`
+    #[serde(default)]
+    pub strict_config: bool,
+}
+
+fn default_stream_replay_ttl_secs() -> u64 {
+    300
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    3600
+}
+
+/// Limits applied to incoming requests.
+///
+/// Bounds the amount of work a single request can trigger upstream.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LimitsConfig {
+    /// Maximum number of non-system messages forwarded to the providers
+    #[serde(default)]
+    pub max_history_messages: Option<usize>,
+    /// Maximum total size in bytes of the response content and raw bodies
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    /// Maximum number of content blocks in a response
+    #[serde(default)]
+    pub max_content_blocks: Option<usize>,
+    /// Maximum `max_tokens` forwarded to either provider; larger values are clamped
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// Maximum simultaneous streaming responses per set of client API tokens
+    #[serde(default)]
+    pub max_streams_per_client: Option<usize>,
+    /// Maximum non-system messages forwarded to DeepSeek, on top of
+    /// `max_history_messages`
+    #[serde(default)]
+    pub deepseek_max_history: Option<usize>,
+    /// Maximum non-system messages forwarded to Anthropic, on top of
+    /// `max_history_messages`
+    #[serde(default)]
+    pub anthropic_max_history: Option<usize>,
+    /// Maximum answer candidates (`n`) per request; unset allows only one,
+    /// since every candidate is billed as a full Anthropic call
+    #[serde(default)]
+    pub max_n: Option<u32>,
+}
+
+/// Reasoning formatting configuration.
+///
+/// Controls how the DeepSeek reasoning is wrapped when shown to the client
+/// and, separately, when handed to Anthropic as an assistant message. An
+/// empty tag disables the wrapper for that destination.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReasoningConfig {
+    #[serde(default)]
+    pub display_format: ReasoningDisplayFormat,
+    #[serde(default = "default_reasoning_tag")]
+    pub display_tag: String,
+    #[serde(default = "default_reasoning_tag")]
+    pub handoff_tag: String,
+    /// Template for the Anthropic-bound message, with a `{reasoning}`
+    /// placeholder; overrides `handoff_tag` when set
+    #[serde(default)]
+    pub handoff_template: Option<String>,
+    /// Remove spans wrapped in the display or handoff tag from Claude's answer
+    #[serde(default)]
+    pub strip_leaked_reasoning: bool,
+    /// Reasoning shorter than this many characters is not passed to Anthropic
+    #[serde(default)]
+    pub min_reasoning_chars: Option<usize>,
+    /// Extra DeepSeek calls made when the reasoning is too short (non-streaming only)
+    #[serde(default)]
+    pub short_reasoning_retries: u32,
+    /// How reasoning longer than `summary_max_chars` is condensed before
+    /// it is handed to Anthropic (non-streaming sequential requests only)
+    #[serde(default)]
+    pub summarization: ReasoningSummarization,
+    /// Reasoning length in characters past which it is condensed
+    #[serde(default = "default_summary_max_chars")]
+    pub summary_max_chars: usize,
+    /// Anthropic model writing `llm_summary` summaries
+    #[serde(default = "default_summary_model")]
+    pub summary_model: String,
+    /// `max_tokens` of the `llm_summary` call
+    #[serde(default = "default_summary_max_tokens")]
+    pub summary_max_tokens: u32,
+}
+
+fn default_reasoning_tag() -> String {
+    "thinking".to_string()
+}
+
+fn default_summary_max_chars() -> usize {
+    4000
+}
+
+fn default_summary_model() -> String {
+    "claude-3-5-haiku-20241022".to_string()
+}
+
+fn default_summary_max_tokens() -> u32 {
+    1024
+}
+
+impl Default for ReasoningConfig {
+    fn default() -> Self {
+        Self {
+            display_format: ReasoningDisplayFormat::default(),
+            display_tag: default_reasoning_tag(),
+            handoff_tag: default_reasoning_tag(),
+            handoff_template: None,
+            strip_leaked_reasoning: false,
+            min_reasoning_chars: None,
+            short_reasoning_retries: 0,
+            summarization: ReasoningSummarization::default(),
+            summary_max_chars: default_summary_max_chars(),
+            summary_model: default_summary_model(),
+            summary_max_tokens: default_summary_max_tokens(),
+        }
+    }
+}
+
+/// How reasoning is formatted in the content shown to clients.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningDisplayFormat {
+    /// Wrapped in `display_tag` XML tags
+    #[default]
+    XmlTags,
+    /// A fenced ```` ```reasoning ```` code block
+    MarkdownFence,
+    /// A markdown `> ` blockquote
+    Blockquote,
+    /// The raw reasoning with no wrapper
+    None,
+}
+
+/// How long reasoning is condensed before it is handed to Anthropic.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningSummarization {
+    /// Passed on in full
+    #[default]
+    None,
+    /// Cut to its start and end
+    Truncate,
+    /// Summarized by an extra call to `summary_model`
+    LlmSummary,
+}
+
+/// Batch processing configuration.
+///
+/// Controls how many requests a single batch call may contain and how
+/// many of them are processed concurrently.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BatchConfig {
+    #[serde(default = "default_batch_max_concurrency")]
+    pub max_concurrency: usize,
+    /// Maximum number of requests in one batch; larger batches are rejected
+    #[serde(default = "default_batch_max_items")]
+    pub max_items: usize,
+}
+
+fn default_batch_max_concurrency() -> usize {
+    4
+}
+
+fn default_batch_max_items() -> usize {
+    32
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: default_batch_max_concurrency(),
+            max_items: default_batch_max_items(),
+        }
+    }
+}
+
+/// Outbound HTTP client configuration.
+///
+/// Applies to every upstream request made by the provider clients.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct HttpConfig {
+    /// PEM file with an additional trusted root certificate
+    #[serde(default)]
+    pub root_cert_path: Option<String>,
+    /// Disables TLS certificate verification (development only)
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// HTTP(S) or SOCKS5 proxy for all upstream requests; overrides the
+    /// `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` environment variables
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// Username for HTTP proxy authentication (SOCKS5 credentials go in the URL)
+    #[serde(default)]
+    pub proxy_username: Option<String>,
+    /// Password for proxy authentication
+    #[serde(default)]
+    pub proxy_password: Option<String>,
+}
+
+/// Delivery settings for `callback_url` webhooks.
+///
+/// Failed deliveries (network errors and non-2xx responses) are retried
+/// up to `max_attempts` times in total, waiting `backoff_ms` multiplied
+/// by the attempt number in between.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookConfig {
+    /// Secret used to sign deliveries with HMAC-SHA256 (unsigned when unset)
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_webhook_backoff_ms")]
+    pub backoff_ms: u64,
+    /// Callback hosts any client may use, trusted to resolve to any
+    /// address; other hosts need the admin token and public addresses
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    3
+}
+
+fn default_webhook_backoff_ms() -> u64 {
+    1000
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            max_attempts: default_webhook_max_attempts(),
+            backoff_ms: default_webhook_backoff_ms(),
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+/// Content moderation applied to user messages before any provider call.
+///
+/// Messages are rejected if they match any `blocked_patterns` regex or,
+/// when `endpoint` is set, if the moderation service flags them.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ModerationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Regular expressions that reject a message when matched
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+    /// External moderation service receiving `{"input": [...]}`
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Bearer token sent to the moderation service
+    #[serde(default)]
+    pub endpoint_token: Option<String>,
+    #[serde(default = "default_moderation_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_moderation_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            blocked_patterns: Vec::new(),
+            endpoint: None,
+            endpoint_token: None,
+            timeout_ms: default_moderation_timeout_ms(),
+        }
+    }
+}
+
+/// Audit log of completed requests, kept apart from the tracing output.
+///
+/// Each completed request is appended to `path` as one JSON line with its
+/// id, models, token counts and cost. Message content is only recorded with
+/// `include_content`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RequestLogConfig {
+    /// JSONL file requests are appended to; logging is disabled when unset
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Also record each request's messages
+    #[serde(default)]
+    pub include_content: bool,
+    /// Rotate the file to `<path>.1` once it would grow past this size
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// Caching of upstream results across requests.
+///
+/// With `reasoning` enabled, the DeepSeek reasoning for a conversation is
+/// reused by later requests with the same messages and DeepSeek config,
+/// while Anthropic still produces a fresh answer every time.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub reasoning: bool,
+    /// How long cached reasoning is reused
+    #[serde(default = "default_reasoning_cache_ttl_secs")]
+    pub reasoning_ttl_secs: u64,
+}
+
+fn default_reasoning_cache_ttl_secs() -> u64 {
+    3600
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            reasoning: false,
+            reasoning_ttl_secs: default_reasoning_cache_ttl_secs(),
+        }
+    }
+}
+
+/// Per-provider configuration.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ProvidersConfig {
+    #[serde(default)]
+    pub deepseek: ProviderConfig,
+    #[serde(default)]
+    pub anthropic: ProviderConfig,
+    /// Replace both providers with canned in-memory responses (no API keys needed)
+    #[serde(default)]
+    pub mock_enabled: bool,
+}
+
+/// Settings for a single upstream provider.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ProviderConfig {
+    /// Models clients may request; any model is allowed when unset
+    #[serde(default)]
+    pub allowed_models: Option<Vec<String>>,
+    /// Retry policy for this provider; falls back to the global `[retry]` policy
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Request body parameters stripped from client-supplied `config.body`
+    #[serde(default)]
+    pub denied_params: Vec<String>,
+    /// Response header holding the provider's request id (provider default when unset)
+    #[serde(default)]
+    pub request_id_header: Option<String>,
+    /// Base URL requests are sent to (the provider's public API when unset)
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Client-supplied headers that may be forwarded; any unprotected header when unset
+    #[serde(default)]
+    pub allowed_headers: Option<Vec<String>>,
+    /// Client-supplied headers that are never forwarded
+    #[serde(default)]
+    pub denied_headers: Vec<String>,
+    /// Extra calls made when a successful answer has no content (Anthropic only)
+    #[serde(default)]
+    pub empty_content_retries: u32,
+    /// Follow-up calls continuing an answer cut off at `max_tokens` (Anthropic only)
+    #[serde(default)]
+    pub auto_continue: u32,
+    /// Extra calls made when an answer does not match the request's
+    /// `response_schema` (Anthropic only)
+    #[serde(default)]
+    pub schema_retries: u32,
+    /// `response_format` type sent when a request sets none, `text` when
+    /// unset (DeepSeek only)
+    #[serde(default)]
+    pub default_response_format: Option<String>,
+}
+
+impl ProviderConfig {
+    /// Checks whether the given model may be requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model id, including any default applied by the client
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|m| m == model))
+    }
+
+    /// Builds the policy for client-supplied headers.
+    pub fn header_policy(&self) -> HeaderPolicy {
+        HeaderPolicy {
+            allowed: self.allowed_headers.clone(),
+            denied: self.denied_headers.clone(),
+        }
+    }
+
+    /// Resolves the retry policy for this provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `global` - The global retry policy used when no override is set
+    pub fn retry_policy(&self, global: &RetryConfig) -> RetryConfig {
+        self.retry.clone().unwrap_or_else(|| global.clone())
+    }
+}
+
+/// Server-side prompt configuration.
+///
+/// `examples` are few-shot messages inserted after the system prompt and
+/// before the client's conversation. They are sent to both providers but
+/// never count against the history limit.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PromptConfig {
+    /// System prompt used when a request provides none
+    #[serde(default)]
+    pub default_system_prompt: Option<String>,
+    #[serde(default)]
+    pub examples: Vec<Message>,
+    /// Normalize line endings and redundant whitespace in message content
+    #[serde(default)]
+    pub normalize_messages: bool,
+    /// How a system prompt given both at the root and in the messages is handled
+    #[serde(default)]
+    pub on_duplicate_system: DuplicateSystemPolicy,
+    /// How messages with empty content are handled
+    #[serde(default)]
+    pub on_empty_message: EmptyMessagePolicy,
+    /// Transforms applied to every request's messages, in order
+    #[serde(default)]
+    pub transforms: Vec<TransformKind>,
+    /// Patterns replaced by `redact_pii`; built-in PII patterns when unset
+    #[serde(default)]
+    pub pii_patterns: Option<Vec<String>>,
+    /// Text substituted for redacted matches (`[REDACTED]` when unset)
+    #[serde(default)]
+    pub pii_replacement: Option<String>,
+    /// Text joining system prompt fragments (a blank line when unset)
+    #[serde(default)]
+    pub system_separator: Option<String>,
+    /// Language Claude is told to answer in, e.g. `French`; requests may
+    /// set their own `answer_language`
+    #[serde(default)]
+    pub answer_language: Option<String>,
+}
+
+/// A built-in message transform.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformKind {
+    /// Normalize line endings and redundant whitespace
+    NormalizeWhitespace,
+    /// Replace personal data matched by `pii_patterns`
+    RedactPii,
+}
+
+/// Retry policy for upstream provider calls.
+///
+/// Transient failures (network errors, 5xx and 429 responses, and
+/// malformed response bodies) are retried up to `max_attempts` times in
+/// total, waiting `backoff_ms` multiplied by the attempt number in between.
+/// A longer `Retry-After` from the provider is honored up to
+/// `max_retry_after_secs`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_retry_after_secs")]
+    pub max_retry_after_secs: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    1
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_retry_after_secs() -> u64 {
+    30
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            backoff_ms: default_backoff_ms(),
+            max_retry_after_secs: default_max_retry_after_secs(),
+        }
+    }
 }
 
 /// Pricing configuration for all supported AI models.
@@ -37,15 +593,79 @@ pub struct PricingConfig {
     pub anthropic: AnthropicPricing,
 }
 
+impl PricingConfig {
+    /// Returns these rates with the sections of `pricing_override` replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `pricing_override` - Alternate rates supplied with a request
+    pub fn with_override(&self, pricing_override: &PricingOverride) -> PricingConfig {
+        PricingConfig {
+            deepseek: pricing_override.deepseek.clone().unwrap_or_else(|| self.deepseek.clone()),
+            anthropic: pricing_override.anthropic.clone().unwrap_or_else(|| self.anthropic.clone()),
+        }
+    }
+}
+
+/// Hypothetical rates a request's costs are reported with.
+///
+/// Each section present replaces the configured one for that request
+/// only. Only reported costs change; the spend counters keep using the
+/// configured rates.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PricingOverride {
+    #[serde(default)]
+    pub deepseek: Option<DeepSeekPricing>,
+    #[serde(default)]
+    pub anthropic: Option<AnthropicPricing>,
+}
+
 /// DeepSeek-specific pricing configuration.
 ///
 /// Contains pricing rates for different aspects of DeepSeek API usage,
-/// including cached and non-cached requests.
+/// including cached and non-cached requests. Rates are keyed by model id;
+/// the top-level rates are the fallback for models not listed.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DeepSeekPricing {
     pub input_cache_hit_price: f64,   // per million tokens
     pub input_cache_miss_price: f64,  // per million tokens
     pub output_price: f64,            // per million tokens
+    #[serde(default)]
+    pub models: HashMap<String, DeepSeekModelPricing>,
+}
+
+/// Pricing rates for a single DeepSeek model.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeepSeekModelPricing {
+    pub input_cache_hit_price: f64,   // per million tokens
+    pub input_cache_miss_price: f64,  // per million tokens
+    pub output_price: f64,            // per million tokens
+}
+
+impl DeepSeekPricing {
+    /// Looks up the rates for a DeepSeek model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The DeepSeek model id
+    ///
+    /// # Returns
+    ///
+    /// * `(DeepSeekModelPricing, bool)` - The rates, and whether they are the
+    ///   fallback estimate because the model has no entry of its own
+    pub fn for_model(&self, model: &str) -> (DeepSeekModelPricing, bool) {
+        match self.models.get(model) {
+            Some(pricing) => (pricing.clone(), false),
+            None => (
+                DeepSeekModelPricing {
+                    input_cache_hit_price: self.input_cache_hit_price,
+                    input_cache_miss_price: self.input_cache_miss_price,
+                    output_price: self.output_price,
+                },
+                true,
+            ),
+        }
+    }
 }
 
 /// Anthropic-specific pricing configuration.
@@ -87,13 +707,162 @@ impl Config {
     /// - The config file cannot be read
     /// - The TOML content cannot be parsed
     /// - The parsed content doesn't match the expected structure
-    pub fn load() -> anyhow::Result<Self> {
+    /// - The file has keys that match no setting, in strict mode; the error
+    ///   is an [`UnknownKeys`]
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Reject unknown keys even without `server.strict_config`
+    pub fn load(strict: bool) -> anyhow::Result<Self> {
         let config_path = Path::new("config.toml");
-        let config = config::Config::builder()
+        let source = config::Config::builder()
             .add_source(config::File::from(config_path))
             .build()?;
 
-        Ok(config.try_deserialize()?)
+        let mut config: Self = source.clone().try_deserialize()?;
+
+        // Serde skips keys it does not know, so a misspelled section would
+        // silently fall back to its defaults
+        let raw: serde_json::Value = source.try_deserialize()?;
+        let known = serde_json::to_value(&config)?;
+        let mut unknown_keys = Vec::new();
+        find_unknown_keys(&raw, &known, "", &mut unknown_keys);
+        if !unknown_keys.is_empty() && (strict || config.server.strict_config) {
+            return Err(UnknownKeys(unknown_keys).into());
+        }
+        config.unknown_keys = unknown_keys;
+
+        Ok(config)
+    }
+
+    /// Checks the configuration for values that parse but cannot work.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - A description of every problem found; empty if the configuration is valid
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .unknown_keys
+            .iter()
+            .map(|key| format!("{}: unknown key, ignored", key))
+            .collect();
+
+        if format!("{}:{}", self.server.host, self.server.port)
+            .parse::<std::net::SocketAddr>()
+            .is_err()
+        {
+            problems.push(format!(
+                "server: {}:{} is not a valid listen address",
+                self.server.host, self.server.port
+            ));
+        }
+
+        if let Some(ratio) = self.server.rate_limit_warning_ratio {
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                problems.push(format!("server.rate_limit_warning_ratio: {} is not between 0 and 1", ratio));
+            }
+        }
+
+        let deepseek = &self.pricing.deepseek;
+        let mut prices = vec![
+            ("pricing.deepseek.input_cache_hit_price".to_string(), deepseek.input_cache_hit_price),
+            ("pricing.deepseek.input_cache_miss_price".to_string(), deepseek.input_cache_miss_price),
+            ("pricing.deepseek.output_price".to_string(), deepseek.output_price),
+        ];
+        for (model, pricing) in &deepseek.models {
+            let prefix = format!("pricing.deepseek.models.{}", model);
+            prices.push((format!("{}.input_cache_hit_price", prefix), pricing.input_cache_hit_price));
+            prices.push((format!("{}.input_cache_miss_price", prefix), pricing.input_cache_miss_price));
+            prices.push((format!("{}.output_price", prefix), pricing.output_price));
+        }
+        let anthropic = &self.pricing.anthropic;
+        for (name, pricing) in [
+            ("claude_3_sonnet", &anthropic.claude_3_sonnet),
+            ("claude_3_haiku", &anthropic.claude_3_haiku),
+            ("claude_3_opus", &anthropic.claude_3_opus),
+        ] {
+            let prefix = format!("pricing.anthropic.{}", name);
+            prices.push((format!("{}.input_price", prefix), pricing.input_price));
+            prices.push((format!("{}.output_price", prefix), pricing.output_price));
+            prices.push((format!("{}.cache_write_price", prefix), pricing.cache_write_price));
+            prices.push((format!("{}.cache_read_price", prefix), pricing.cache_read_price));
+        }
+        for (field, price) in prices {
+            if !price.is_finite() || price < 0.0 {
+                problems.push(format!("{}: {} is not a valid price", field, price));
+            }
+        }
+
+        if let Some(template) = &self.reasoning.handoff_template {
+            if !template.contains("{reasoning}") {
+                problems.push("reasoning.handoff_template: missing the {reasoning} placeholder".to_string());
+            }
+        }
+
+        for pattern in self.prompt.pii_patterns.iter().flatten() {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!("prompt.pii_patterns: {}", e));
+            }
+        }
+
+        for pattern in &self.moderation.blocked_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(format!("moderation.blocked_patterns: {}", e));
+            }
+        }
+
+        if let Some(path) = &self.http.root_cert_path {
+            if !Path::new(path).is_file() {
+                problems.push(format!("http.root_cert_path: {} does not exist", path));
+            }
+        }
+        for (provider, base_url) in [
+            ("deepseek", &self.providers.deepseek.base_url),
+            ("anthropic", &self.providers.anthropic.base_url),
+        ] {
+            if let Some(base_url) = base_url {
+                if !is_http_url(base_url) {
+                    problems.push(format!("providers.{}.base_url: {} is not a valid http(s) URL", provider, base_url));
+                }
+            }
+        }
+        if let Some(proxy_url) = &self.http.proxy_url {
+            if reqwest::Url::parse(proxy_url).is_err() {
+                problems.push(format!("http.proxy_url: {} is not a valid URL", proxy_url));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Keys in `config.toml` that match no setting, rejected in strict mode.
+#[derive(Debug, thiserror::Error)]
+#[error("config.toml has unknown keys: {}", .0.join(", "))]
+pub struct UnknownKeys(pub Vec<String>);
+
+/// Collects the keys of a parsed config file that no setting read.
+///
+/// Every setting is serialized back, so a key of the file missing from the
+/// serialized config was ignored. Map-valued settings such as
+/// `model_defaults` serialize their entries too and never report them.
+///
+/// # Arguments
+///
+/// * `raw` - The config file as parsed, before deserialization
+/// * `known` - The deserialized config, serialized again
+/// * `path` - Dotted path of `raw`, empty at the root
+/// * `unknown` - Receives the dotted path of each unknown key
+fn find_unknown_keys(raw: &serde_json::Value, known: &serde_json::Value, path: &str, unknown: &mut Vec<String>) {
+    let (serde_json::Value::Object(raw), serde_json::Value::Object(known)) = (raw, known) else {
+        return;
+    };
+    for (key, value) in raw {
+        let key_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        match known.get(key) {
+            Some(known) => find_unknown_keys(value, known, &key_path, unknown),
+            None => unknown.push(key_path),
+        }
     }
 }
 
@@ -107,12 +876,39 @@ impl Default for Config {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
                 port: 3000,
+                stream_replay_ttl_secs: default_stream_replay_ttl_secs(),
+                idempotency_ttl_secs: default_idempotency_ttl_secs(),
+                admin_token: None,
+                warmup: false,
+                sse_retry_ms: None,
+                rate_limit_headers: false,
+                rate_limit_warning_ratio: None,
+                task_log_interval_secs: None,
+                strict_config: false,
             },
             pricing: PricingConfig {
                 deepseek: DeepSeekPricing {
                     input_cache_hit_price: 0.14,
                     input_cache_miss_price: 0.55,
                     output_price: 2.19,
+                    models: HashMap::from([
+                        (
+                            "deepseek-chat".to_string(),
+                            DeepSeekModelPricing {
+                                input_cache_hit_price: 0.07,
+                                input_cache_miss_price: 0.27,
+                                output_price: 1.10,
+                            },
+                        ),
+                        (
+                            "deepseek-reasoner".to_string(),
+                            DeepSeekModelPricing {
+                                input_cache_hit_price: 0.14,
+                                input_cache_miss_price: 0.55,
+                                output_price: 2.19,
+                            },
+                        ),
+                    ]),
                 },
                 anthropic: AnthropicPricing {
                     claude_3_sonnet: ModelPricing {
@@ -135,6 +931,24 @@ impl Default for Config {
                     },
                 },
             },
+            batch: BatchConfig::default(),
+            model_defaults: HashMap::new(),
+            limits: LimitsConfig::default(),
+            reasoning: ReasoningConfig::default(),
+            retry: RetryConfig::default(),
+            prompt: PromptConfig::default(),
+            providers: ProvidersConfig::default(),
+            http: HttpConfig::default(),
+            webhook: WebhookConfig::default(),
+            moderation: ModerationConfig::default(),
+            cache: CacheConfig::default(),
+            request_log: RequestLogConfig::default(),
+            unknown_keys: Vec::new(),
         }
     }
 }
+
+/// Returns whether a string is an absolute `http` or `https` URL.
+pub fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
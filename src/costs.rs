@@ -0,0 +1,116 @@
+//! Cumulative cost accounting across requests.
+//!
+//! Every completed request adds its combined provider cost to a set of
+//! process-wide counters. Operators can read and reset the totals through
+//! the admin endpoints, e.g. at billing boundaries. The totals live in
+//! memory only and start from zero whenever the server restarts.
+//!
+//! Requests carrying `tags` are also totalled per tag, for chargeback. The
+//! number of distinct tags tracked is capped so that clients cannot grow
+//! the counters, or the metrics labels built from them, without bound.
+
+use dashmap::DashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Number of counter units per dollar; costs are tracked in micro-dollars.
+const UNITS_PER_DOLLAR: f64 = 1_000_000.0;
+
+/// Distinct tag key and value pairs totalled before new ones are ignored.
+const MAX_TAG_SERIES: usize = 1000;
+
+/// A point-in-time copy of the counters.
+#[derive(Debug, Clone)]
+pub struct CostSnapshot {
+    pub total_cost: f64,
+    pub requests: u64,
+    /// Totals per tag, sorted by tag and value
+    pub by_tag: Vec<TagSnapshot>,
+}
+
+/// A point-in-time copy of the totals for one tag value.
+#[derive(Debug, Clone)]
+pub struct TagSnapshot {
+    pub tag: String,
+    pub value: String,
+    pub total_cost: f64,
+    pub requests: u64,
+}
+
+/// Running totals for one tag value.
+#[derive(Debug, Default)]
+struct TagTotals {
+    micro_dollars: AtomicU64,
+    requests: AtomicU64,
+}
+
+/// Running totals of provider cost and completed requests.
+#[derive(Debug, Default)]
+pub struct CostCounters {
+    micro_dollars: AtomicU64,
+    requests: AtomicU64,
+    by_tag: DashMap<(String, String), TagTotals>,
+}
+
+impl CostCounters {
+    /// Adds the cost of one completed request.
+    ///
+    /// # Arguments
+    ///
+    /// * `cost` - The request's combined cost in dollars
+    /// * `tags` - The request's cost attribution tags
+    pub fn record(&self, cost: f64, tags: &HashMap<String, String>) {
+        let units = (cost.max(0.0) * UNITS_PER_DOLLAR).round() as u64;
+        self.micro_dollars.fetch_add(units, Ordering::Relaxed);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        for (tag, value) in tags {
+            let key = (tag.clone(), value.clone());
+            if !self.by_tag.contains_key(&key) && self.by_tag.len() >= MAX_TAG_SERIES {
+                tracing::warn!("Tracking {} tag values already, not totalling {}={}", MAX_TAG_SERIES, tag, value);
+                continue;
+            }
+            let totals = self.by_tag.entry(key).or_default();
+            totals.micro_dollars.fetch_add(units, Ordering::Relaxed);
+            totals.requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the current totals.
+    pub fn snapshot(&self) -> CostSnapshot {
+        let mut by_tag: Vec<TagSnapshot> = self
+            .by_tag
+            .iter()
+            .map(|entry| TagSnapshot {
+                tag: entry.key().0.clone(),
+                value: entry.key().1.clone(),
+                total_cost: entry.micro_dollars.load(Ordering::Relaxed) as f64 / UNITS_PER_DOLLAR,
+                requests: entry.requests.load(Ordering::Relaxed),
+            })
+            .collect();
+        by_tag.sort_by(|a, b| (&a.tag, &a.value).cmp(&(&b.tag, &b.value)));
+
+        CostSnapshot {
+            total_cost: self.micro_dollars.load(Ordering::Relaxed) as f64 / UNITS_PER_DOLLAR,
+            requests: self.requests.load(Ordering::Relaxed),
+            by_tag,
+        }
+    }
+
+    /// Zeroes the counters and forgets every tag.
+    ///
+    /// # Returns
+    ///
+    /// * `CostSnapshot` - The totals accumulated up to the reset
+    pub fn reset(&self) -> CostSnapshot {
+        let by_tag = self.snapshot().by_tag;
+        self.by_tag.clear();
+        CostSnapshot {
+            total_cost: self.micro_dollars.swap(0, Ordering::Relaxed) as f64 / UNITS_PER_DOLLAR,
+            requests: self.requests.swap(0, Ordering::Relaxed),
+            by_tag,
+        }
+    }
+}
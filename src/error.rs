@@ -60,6 +60,31 @@ pub enum ApiError {
         header: String,
     },
 
+    #[error("Not found: {message}")]
+    NotFound {
+        message: String,
+    },
+
+    #[error("Unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+    },
+
+    #[error("Content flagged: {message}")]
+    ContentFlagged {
+        message: String,
+    },
+
+    #[error("Too many requests: {message}")]
+    TooManyRequests {
+        message: String,
+    },
+
+    #[error("Service unavailable: {message}")]
+    ServiceUnavailable {
+        message: String,
+    },
+
     #[error("Invalid system prompt configuration")]
     InvalidSystemPrompt,
 
@@ -69,6 +94,10 @@ pub enum ApiError {
         type_: String,
         param: Option<String>,
         code: Option<String>,
+        /// Seconds to wait before retrying, from the `Retry-After` header
+        retry_after: Option<u64>,
+        /// HTTP status DeepSeek answered with, if it answered
+        status: Option<u16>,
     },
 
     #[error("Anthropic API error: {message}")]
@@ -90,13 +119,33 @@ pub enum ApiError {
     },
 }
 
-/// Implements conversion of API errors into HTTP responses.
-///
-/// Maps each error variant to an appropriate HTTP status code and
-/// formats the error details into a consistent JSON response structure.
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
-        let (status, error_response) = match &self {
+impl ApiError {
+    /// Returns whether the error is a transient upstream failure worth retrying.
+    ///
+    /// Network failures, 5xx/429 responses and malformed success bodies are
+    /// retryable; validation errors and other 4xx responses are not, even
+    /// when they carry a `Retry-After` header.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::DeepSeekError { retry_after: Some(_), status: Some(status), .. }
+                if *status == 429 || *status >= 500 => true,
+            ApiError::DeepSeekError { type_, .. } | ApiError::AnthropicError { type_, .. } => {
+                matches!(
+                    type_.as_str(),
+                    "request_failed" | "server_error" | "parse_error" | "rate_limit_error"
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Maps the error to its HTTP status code and response body.
+    ///
+    /// # Returns
+    ///
+    /// * `(StatusCode, ErrorResponse)` - The status code and error envelope for this error
+    pub fn to_status_and_response(&self) -> (StatusCode, ErrorResponse) {
+        match self {
             ApiError::BadRequest { message } => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
@@ -119,6 +168,61 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
+            ApiError::NotFound { message } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "not_found".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::Unauthorized { message } => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "unauthorized".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::ContentFlagged { message } => (
+                StatusCode::FORBIDDEN,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "content_flagged".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::TooManyRequests { message } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "too_many_requests".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::ServiceUnavailable { message } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "service_unavailable".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
             ApiError::InvalidSystemPrompt => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
@@ -130,8 +234,13 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
-            ApiError::DeepSeekError { message, type_, param, code } => (
-                StatusCode::BAD_REQUEST,
+            ApiError::DeepSeekError { message, type_, param, code, status, .. } => (
+                // Rate limits stay distinguishable so clients can back off
+                if *status == Some(429) || type_ == "rate_limit_error" {
+                    StatusCode::TOO_MANY_REQUESTS
+                } else {
+                    StatusCode::BAD_REQUEST
+                },
                 ErrorResponse {
                     error: ErrorDetails {
                         message: format!("DeepSeek API Error: {}", message),
@@ -174,7 +283,25 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
-        };
+        }
+    }
+
+    /// Builds the error envelope for this error without a status code.
+    ///
+    /// Used where errors are embedded in a larger response body,
+    /// such as per-item failures in a batch.
+    pub fn to_error_response(&self) -> ErrorResponse {
+        self.to_status_and_response().1
+    }
+}
+
+/// Implements conversion of API errors into HTTP responses.
+///
+/// Maps each error variant to an appropriate HTTP status code and
+/// formats the error details into a consistent JSON response structure.
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error_response) = self.to_status_and_response();
 
         (status, Json(error_response)).into_response()
     }
@@ -0,0 +1,25 @@
+//! Request extractors with API-shaped rejections.
+//!
+//! axum's built-in `Json` extractor rejects malformed bodies with a plain
+//! text response. `ApiJson` wraps it so every rejection is reported as an
+//! `ApiError::BadRequest` in the standard `ErrorResponse` envelope.
+
+use crate::error::ApiError;
+use axum::extract::{rejection::JsonRejection, FromRequest};
+
+/// JSON body extractor that rejects with `ApiError::BadRequest`.
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// Converts a JSON body rejection into a bad request error.
+///
+/// The message includes axum's description of the failure, which names
+/// the malformed or missing field when the body did not match the schema.
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest {
+            message: rejection.body_text(),
+        }
+    }
+}
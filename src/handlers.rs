@@ -6,30 +6,105 @@
 //! usage tracking and cost calculations.
 
 use crate::{
-    clients::{AnthropicClient, DeepSeekClient},
-    config::Config,
-    error::{ApiError, Result, SseResponse},
+    clients::{
+        anthropic,
+        deepseek::{self, DeepSeekResponse},
+        mock::{MockAnthropicClient, MockDeepSeekClient},
+        AnswerProvider, AnthropicClient, DeepSeekClient, ReasoningProvider,
+    },
+    config::{Config, PricingConfig, ReasoningSummarization},
+    costs::{CostCounters, CostSnapshot},
+    error::{ApiError, Result, SseResponse, SseResult},
+    extract::ApiJson,
     models::{
-        ApiRequest, ApiResponse, ContentBlock, CombinedUsage, DeepSeekUsage, AnthropicUsage,
-        ExternalApiResponse, Message, Role, StreamEvent,
+        Adjustment, ApiConfig, ApiRequest, ApiResponse, BatchItem, Candidate, CallbackAccepted, ContentBlock, ContentMode, CostSummary, DebugEcho, StreamGranularity, DuplicateSystemPolicy, EmptyMessagePolicy, Finish, FinishModels, ReadyStatus, SeedInfo, CombinedUsage, DeepSeekUsage, AnthropicUsage,
+        ExternalApiResponse, IdentifiedEvent, Message, RateLimit, RateLimits, RawBodyLine, Phase, PipelineMode, Provider, ReasoningInjection, ReasoningResponse, Role, StreamMode, TokenizeRequest, TokenizeResponse, StreamEvent, StreamFormat, TaggedCost, Timings, ToolCall, VerboseTransfer, trim_history,
     },
+    idempotency::IdempotencyStore,
+    moderation::Moderator,
+    reasoning::{self, DisplayFormatter, LeakedTagFilter},
+    reasoning_cache::{CachedReasoning, ReasoningCache},
+    replay::{BufferedEvent, ReplayBuffer, ReplayStore},
+    schema,
+    stream_limit::StreamLimiter,
+    request_log::{RequestLog, RequestLogEntry},
+    tasks::TaskGauge,
+    ttft::{FirstTokenTimer, TtftMetrics},
+    tokenizer,
+    transform::TransformPipeline,
+    webhook,
 };
 use axum::{
-    extract::State,
-    response::{sse::Event, IntoResponse},
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::IntoResponse,
     Json,
 };
 use chrono::Utc;
-use futures::StreamExt;
-use std::{sync::Arc, collections::HashMap};
+use futures::{Stream, StreamExt};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::{Duration, Instant},
+};
 use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+/// Response header carrying the id of a streaming request.
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Request header used to deduplicate retried non-streaming requests.
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response header reporting per-stage latency to browsers and RUM tools.
+const SERVER_TIMING_HEADER: &str = "Server-Timing";
+
+/// Response header echoing the combined cost of a non-streaming request.
+const TOTAL_COST_HEADER: &str = "X-Total-Cost";
+
+/// Response header echoing DeepSeek's id for the upstream request.
+const DEEPSEEK_REQUEST_ID_HEADER: &str = "X-DeepSeek-Request-Id";
+
+/// Response header echoing Anthropic's id for the upstream request.
+const ANTHROPIC_REQUEST_ID_HEADER: &str = "X-Anthropic-Request-Id";
+
+/// Separator joining system prompt fragments when none is configured.
+const DEFAULT_SYSTEM_SEPARATOR: &str = "\n\n";
+
+/// Maximum length of a request's `answer_language`, in characters.
+const MAX_ANSWER_LANGUAGE_CHARS: usize = 64;
+
+/// Number of streamed reasoning deltas between `reasoning_progress` events.
+const REASONING_PROGRESS_INTERVAL: u32 = 32;
+
+/// Stop reason reported in `finish` for a stream cut off by `max_duration_ms`.
+const STOP_CANCELLED_BY_TIMEOUT: &str = "cancelled_by_timeout";
 
 /// Application state shared across request handlers.
 ///
-/// Contains configuration that needs to be accessible
-/// to all request handlers.
+/// Contains configuration and shared runtime state that needs
+/// to be accessible to all request handlers.
 pub struct AppState {
     pub config: Config,
+    pub http: reqwest::Client,
+    pub replay: ReplayStore,
+    pub idempotency: IdempotencyStore,
+    pub costs: Arc<CostCounters>,
+    pub moderator: Moderator,
+    pub reasoning_cache: ReasoningCache,
+    pub streams: StreamLimiter,
+    /// Streaming tasks currently running, for spotting leaked tasks
+    pub stream_tasks: TaskGauge,
+    /// Time to first token of chat streams
+    pub ttft: Arc<TtftMetrics>,
+    pub request_log: RequestLog,
+    pub transforms: TransformPipeline,
+    /// Cleared until the startup warmup, if enabled, has finished
+    pub ready: AtomicBool,
 }
 
 /// Extracts API tokens from request headers.
@@ -49,550 +124,4261 @@ pub struct AppState {
 fn extract_api_tokens(
     headers: &axum::http::HeaderMap,
 ) -> Result<(String, String)> {
-    let deepseek_token = headers
-        .get("X-DeepSeek-API-Token")
-        .ok_or_else(|| ApiError::MissingHeader { 
-            header: "X-DeepSeek-API-Token".to_string() 
-        })?
-        .to_str()
-        .map_err(|_| ApiError::BadRequest { 
-            message: "Invalid DeepSeek API token".to_string() 
-        })?
-        .to_string();
-
-    let anthropic_token = headers
-        .get("X-Anthropic-API-Token")
-        .ok_or_else(|| ApiError::MissingHeader { 
-            header: "X-Anthropic-API-Token".to_string() 
-        })?
-        .to_str()
-        .map_err(|_| ApiError::BadRequest { 
-            message: "Invalid Anthropic API token".to_string() 
-        })?
-        .to_string();
+    let deepseek_token = extract_api_token(headers, "X-DeepSeek-API-Token", "DeepSeek")?;
+    let anthropic_token = extract_api_token(headers, "X-Anthropic-API-Token", "Anthropic")?;
 
     Ok((deepseek_token, anthropic_token))
 }
 
-/// Calculates the cost of DeepSeek API usage.
+/// Extracts a single provider API token from request headers.
 ///
 /// # Arguments
 ///
-/// * `input_tokens` - Number of input tokens processed
-/// * `output_tokens` - Number of output tokens generated
-/// * `_reasoning_tokens` - Number of tokens used for reasoning
-/// * `cached_tokens` - Number of tokens retrieved from cache
-/// * `config` - Configuration containing pricing information
+/// * `headers` - The HTTP headers containing the API token
+/// * `header` - Name of the header carrying the token
+/// * `provider` - Provider name used in error messages
 ///
 /// # Returns
 ///
-/// The total cost in dollars for the API usage
-fn calculate_deepseek_cost(
-    input_tokens: u32,
-    output_tokens: u32,
-    _reasoning_tokens: u32,
-    cached_tokens: u32,
-    config: &Config,
-) -> f64 {
-    let cache_hit_cost = (cached_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_hit_price;
-    let cache_miss_cost = ((input_tokens - cached_tokens) as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_miss_price;
-    let output_cost = (output_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.output_price;
-    
-    cache_hit_cost + cache_miss_cost + output_cost
+/// * `Result<String>` - The API token
+///
+/// # Errors
+///
+/// Returns `ApiError::MissingHeader` if the token is missing
+/// Returns `ApiError::BadRequest` if the token is malformed
+fn extract_api_token(
+    headers: &axum::http::HeaderMap,
+    header: &str,
+    provider: &str,
+) -> Result<String> {
+    Ok(headers
+        .get(header)
+        .ok_or_else(|| ApiError::MissingHeader { 
+            header: header.to_string() 
+        })?
+        .to_str()
+        .map_err(|_| ApiError::BadRequest { 
+            message: format!("Invalid {} API token", provider) 
+        })?
+        .to_string())
 }
 
-/// Calculates the cost of Anthropic API usage.
+/// Builds the provider clients for a request.
+///
+/// Extracts the API tokens from the request headers and applies the
+/// client-level settings from the configuration. Both clients share the
+/// application's HTTP client and its connection pool. In mock mode the
+/// canned providers are returned and no tokens are required.
 ///
 /// # Arguments
 ///
-/// * `model` - The specific Claude model used
-/// * `input_tokens` - Number of input tokens processed
-/// * `output_tokens` - Number of output tokens generated
-/// * `cache_write_tokens` - Number of tokens written to cache
-/// * `cache_read_tokens` - Number of tokens read from cache
-/// * `config` - Configuration containing pricing information
+/// * `state` - Application state containing configuration and the HTTP client
+/// * `headers` - The HTTP headers containing the API tokens
 ///
 /// # Returns
 ///
-/// The total cost in dollars for the API usage
-fn calculate_anthropic_cost(
-    model: &str,
-    input_tokens: u32,
-    output_tokens: u32,
-    cache_write_tokens: u32,
-    cache_read_tokens: u32,
-    config: &Config,
-) -> f64 {
-    let pricing = if model.contains("claude-3-5-sonnet") {
-        &config.pricing.anthropic.claude_3_sonnet
-    } else if model.contains("claude-3-5-haiku") {
-        &config.pricing.anthropic.claude_3_haiku
-    } else if model.contains("claude-3-opus") {
-        &config.pricing.anthropic.claude_3_opus
-    } else {
-        &config.pricing.anthropic.claude_3_sonnet // default to sonnet pricing
-    };
+/// * `Result<(Box<dyn ReasoningProvider>, Box<dyn AnswerProvider>)>` - The configured clients
+///
+/// # Errors
+///
+/// Returns an error if the API tokens are missing or malformed
+pub(crate) fn build_clients(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> Result<(Box<dyn ReasoningProvider>, Box<dyn AnswerProvider>)> {
+    let config = &state.config;
+    if config.providers.mock_enabled {
+        return Ok((Box::new(MockDeepSeekClient), Box::new(MockAnthropicClient)));
+    }
+    let (deepseek_token, anthropic_token) = extract_api_tokens(headers)?;
 
-    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
-    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
-    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
-    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+    let deepseek_client = deepseek_client(config, &state.http, deepseek_token);
+    let anthropic_client = AnthropicClient::new(anthropic_token)
+        .with_http_client(state.http.clone())
+        .with_model_defaults(config.model_defaults.clone())
+        .with_retry(config.providers.anthropic.retry_policy(&config.retry))
+        .with_denied_params(config.providers.anthropic.denied_params.clone())
+        .with_header_policy(config.providers.anthropic.header_policy())
+        .with_request_id_header(config.providers.anthropic.request_id_header.clone())
+        .with_base_url(config.providers.anthropic.base_url.clone());
 
-    input_cost + output_cost + cache_write_cost + cache_read_cost
+    Ok((Box::new(deepseek_client), Box::new(anthropic_client)))
 }
 
-/// Formats a cost value as a dollar amount string.
+/// Builds only the DeepSeek client for a request.
+///
+/// Used by endpoints that never call Anthropic, so the Anthropic
+/// token is not required.
 ///
 /// # Arguments
 ///
-/// * `cost` - The cost value to format
+/// * `state` - Application state containing configuration and the HTTP client
+/// * `headers` - The HTTP headers containing the DeepSeek API token
 ///
 /// # Returns
 ///
-/// A string representing the cost with 3 decimal places and $ prefix
-fn format_cost(cost: f64) -> String {
-    format!("${:.3}", cost)
+/// * `Result<Box<dyn ReasoningProvider>>` - The configured client
+///
+/// # Errors
+///
+/// Returns an error if the DeepSeek token is missing or malformed
+fn build_deepseek_client(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> Result<Box<dyn ReasoningProvider>> {
+    let config = &state.config;
+    if config.providers.mock_enabled {
+        return Ok(Box::new(MockDeepSeekClient));
+    }
+    let deepseek_token = extract_api_token(headers, "X-DeepSeek-API-Token", "DeepSeek")?;
+
+    Ok(Box::new(deepseek_client(config, &state.http, deepseek_token)))
 }
 
-/// Main handler for chat requests.
-///
-/// Routes requests to either streaming or non-streaming handlers
-/// based on the request configuration.
+/// Configures a DeepSeek client from the provider settings.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `config` - Configuration containing the DeepSeek provider settings
+/// * `http` - The shared HTTP client
+/// * `token` - The DeepSeek API token
 ///
 /// # Returns
 ///
-/// * `Result<Response>` - The API response or an error
-pub async fn handle_chat(
-    state: State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<axum::response::Response> {
-    if request.stream {
-        let stream_response = chat_stream(state, headers, Json(request)).await?;
-        Ok(stream_response.into_response())
-    } else {
-        let json_response = chat(state, headers, Json(request)).await?;
-        Ok(json_response.into_response())
-    }
+/// * `DeepSeekClient` - The configured client
+fn deepseek_client(config: &Config, http: &reqwest::Client, token: String) -> DeepSeekClient {
+    DeepSeekClient::new(token)
+        .with_http_client(http.clone())
+        .with_model_defaults(config.model_defaults.clone())
+        .with_retry(config.providers.deepseek.retry_policy(&config.retry))
+        .with_denied_params(config.providers.deepseek.denied_params.clone())
+        .with_header_policy(config.providers.deepseek.header_policy())
+        .with_request_id_header(config.providers.deepseek.request_id_header.clone())
+        .with_base_url(config.providers.deepseek.base_url.clone())
+        .with_default_response_format(config.providers.deepseek.default_response_format.clone())
 }
 
-/// Handler for non-streaming chat requests.
-///
-/// Processes the request through both AI models sequentially,
-/// combining their responses and tracking usage.
+/// Validates a request before any upstream call is made.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `config` - Configuration containing the provider model allowlists
+/// * `request` - The chat request
+/// * `uses_anthropic` - Whether the request will reach Anthropic
 ///
-/// # Returns
+/// # Errors
 ///
-/// * `Result<Json<ApiResponse>>` - The combined API response or an error
-pub(crate) async fn chat(
-    State(state): State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<Json<ApiResponse>> {
-    // Validate system prompt
-    if !request.validate_system_prompt() {
+/// Returns `ApiError::InvalidSystemPrompt` if the system prompt is duplicated
+/// and `prompt.on_duplicate_system` is `reject`, or `ApiError::BadRequest` if a provider parameter is out of range, a
+/// requested model is not allowed, or a message is empty and `prompt.on_empty_message` is `reject`
+fn validate_request(config: &Config, request: &ApiRequest, uses_anthropic: bool) -> Result<()> {
+    if !request.validate_system_prompt() && config.prompt.on_duplicate_system == DuplicateSystemPolicy::Reject {
         return Err(ApiError::InvalidSystemPrompt);
     }
+    if request.has_empty_system_fragments() {
+        return Err(ApiError::BadRequest {
+            message: "system must contain at least one non-empty fragment".to_string(),
+        });
+    }
 
-    // Extract API tokens
-    let (deepseek_token, anthropic_token) = extract_api_tokens(&headers)?;
-
-    // Initialize clients
-    let deepseek_client = DeepSeekClient::new(deepseek_token);
-    let anthropic_client = AnthropicClient::new(anthropic_token);
+    if config.prompt.on_empty_message == EmptyMessagePolicy::Reject {
+        if let Some(index) = request.empty_message_index() {
+            return Err(ApiError::BadRequest {
+                message: format!("messages[{}] has empty content", index),
+            });
+        }
+    }
 
-    // Get messages with system prompt
-    let messages = request.get_messages_with_system();
+    if let Some(message) = request.invalid_tags() {
+        return Err(ApiError::BadRequest { message });
+    }
+    if let Some(language) = &request.answer_language {
+        if language.trim().is_empty()
+            || language.chars().count() > MAX_ANSWER_LANGUAGE_CHARS
+            || language.contains(['\n', '\r'])
+        {
+            return Err(ApiError::BadRequest {
+                message: format!("answer_language must be a single line of 1 to {} characters", MAX_ANSWER_LANGUAGE_CHARS),
+            });
+        }
+    }
 
-    // Call DeepSeek API
-    let deepseek_response = deepseek_client.chat(messages.clone(), &request.deepseek_config).await?;
-    
-    // Store response metadata
-    let deepseek_status: u16 = 200;
-    let deepseek_headers = HashMap::new(); // Headers not available when using high-level chat method
+    crate::clients::deepseek::validate_body(&request.deepseek_config.body)?;
+    for (field, base_url) in [
+        ("deepseek_config.base_url", &request.deepseek_config.base_url),
+        ("anthropic_config.base_url", &request.anthropic_config.base_url),
+    ] {
+        if base_url.as_deref().is_some_and(|url| !crate::config::is_http_url(url)) {
+            return Err(ApiError::BadRequest {
+                message: format!("{} must be an absolute http(s) URL", field),
+            });
+        }
+    }
 
-    // Extract reasoning content and wrap in thinking tags
-    let reasoning_content = deepseek_response
-        .choices
-        .first()
-        .and_then(|c| c.message.reasoning_content.as_ref())
-        .ok_or_else(|| ApiError::DeepSeekError { 
-            message: "No reasoning content in response".to_string(),
-            type_: "missing_content".to_string(),
-            param: None,
-            code: None
-        })?;
+    if request.pipeline_mode == PipelineMode::Parallel && request.reasoning_injection != ReasoningInjection::None {
+        return Err(ApiError::BadRequest {
+            message: "pipeline_mode 'parallel' requires reasoning_injection 'none'".to_string(),
+        });
+    }
 
-    let thinking_content = format!("<thinking>\n{}\n</thinking>", reasoning_content);
+    let deepseek_model = crate::clients::deepseek::requested_model(&request.deepseek_config.body);
+    if !config.providers.deepseek.allows_model(deepseek_model) {
+        return Err(ApiError::BadRequest {
+            message: format!("DeepSeek model '{}' is not allowed", deepseek_model),
+        });
+    }
 
-    // Add thinking content to messages for Anthropic
-    let mut anthropic_messages = messages;
-    anthropic_messages.push(Message {
-        role: Role::Assistant,
-        content: thinking_content.clone(),
-    });
+    if uses_anthropic {
+        let anthropic_model = crate::clients::anthropic::requested_model(&request.anthropic_config.body);
+        if !config.providers.anthropic.allows_model(anthropic_model) {
+            return Err(ApiError::BadRequest {
+                message: format!("Anthropic model '{}' is not allowed", anthropic_model),
+            });
+        }
 
-    // Call Anthropic API
-    let anthropic_response = anthropic_client.chat(
-        anthropic_messages,
-        request.get_system_prompt().map(String::from),
-        &request.anthropic_config
-    ).await?;
-    
-    // Store response metadata
-    let anthropic_status: u16 = 200;
-    let anthropic_headers = HashMap::new(); // Headers not available when using high-level chat method
+        // Each candidate is a full Anthropic call, so `n` multiplies the cost
+        let max_n = config.limits.max_n.unwrap_or(1);
+        if request.n.is_some_and(|n| n == 0 || n > max_n) {
+            return Err(ApiError::BadRequest {
+                message: format!("n must be between 1 and {}", max_n),
+            });
+        }
+    }
 
-    // Calculate usage costs
-    let deepseek_cost = calculate_deepseek_cost(
-        deepseek_response.usage.prompt_tokens,
-        deepseek_response.usage.completion_tokens,
-        deepseek_response.usage.completion_tokens_details.reasoning_tokens,
-        deepseek_response.usage.prompt_tokens_details.cached_tokens,
-        &state.config,
-    );
+    Ok(())
+}
 
-    let anthropic_cost = calculate_anthropic_cost(
-        &anthropic_response.model,
-        anthropic_response.usage.input_tokens,
-        anthropic_response.usage.output_tokens,
-        anthropic_response.usage.cache_creation_input_tokens,
-        anthropic_response.usage.cache_read_input_tokens,
-        &state.config,
-    );
+/// Applies server-side limits and defaults to a request.
+///
+/// Resolves a duplicated system prompt, drops or fills in empty messages,
+/// clamps each provider's effective `max_tokens` to the configured limit
+/// and notes when the default system prompt will be used.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the limits and defaults
+/// * `deepseek_client` - Client used to resolve DeepSeek's effective parameters
+/// * `anthropic_client` - Client used to resolve Anthropic's, if it will be called
+/// * `request` - The request to adjust in place
+///
+/// # Returns
+///
+/// * `Vec<Adjustment>` - Every change made, for reporting back to the client
+fn adjust_request(
+    config: &Config,
+    deepseek_client: &dyn ReasoningProvider,
+    anthropic_client: Option<&dyn AnswerProvider>,
+    request: &mut ApiRequest,
+) -> Vec<Adjustment> {
+    let mut adjustments = Vec::new();
 
-    // Combine thinking content with Anthropic's response
-    let mut content = Vec::new();
-    
-    // Add thinking block first
-    content.push(ContentBlock::text(thinking_content));
-    
-    // Add Anthropic's response blocks
-    content.extend(anthropic_response.content.clone().into_iter()
-        .map(ContentBlock::from_anthropic));
+    request.join_system_fragments(config.prompt.system_separator.as_deref().unwrap_or(DEFAULT_SYSTEM_SEPARATOR));
+    if let Some((original, effective)) = request.resolve_duplicate_system(config.prompt.on_duplicate_system) {
+        adjustments.push(Adjustment {
+            field: "system".to_string(),
+            original: serde_json::json!(original),
+            effective: serde_json::json!(effective),
+        });
+    }
 
-    // Build response with captured headers
-    let response = ApiResponse {
-        created: Utc::now(),
-        content,
-        deepseek_response: request.verbose.then(|| ExternalApiResponse {
-            status: deepseek_status,
-            headers: deepseek_headers,
-            body: serde_json::to_value(&deepseek_response).unwrap_or_default(),
-        }),
-        anthropic_response: request.verbose.then(|| ExternalApiResponse {
-            status: anthropic_status,
-            headers: anthropic_headers,
-            body: serde_json::to_value(&anthropic_response).unwrap_or_default(),
-        }),
-        combined_usage: CombinedUsage {
-            total_cost: format_cost(deepseek_cost + anthropic_cost),
-            deepseek_usage: DeepSeekUsage {
-                input_tokens: deepseek_response.usage.prompt_tokens,
-                output_tokens: deepseek_response.usage.completion_tokens,
-                reasoning_tokens: deepseek_response.usage.completion_tokens_details.reasoning_tokens,
-                cached_input_tokens: deepseek_response.usage.prompt_tokens_details.cached_tokens,
-                total_tokens: deepseek_response.usage.total_tokens,
-                total_cost: format_cost(deepseek_cost),
-            },
-            anthropic_usage: AnthropicUsage {
-                input_tokens: anthropic_response.usage.input_tokens,
-                output_tokens: anthropic_response.usage.output_tokens,
-                cached_write_tokens: anthropic_response.usage.cache_creation_input_tokens,
-                cached_read_tokens: anthropic_response.usage.cache_read_input_tokens,
-                total_tokens: anthropic_response.usage.input_tokens + anthropic_response.usage.output_tokens,
-                total_cost: format_cost(anthropic_cost),
+    for index in request.resolve_empty_messages(config.prompt.on_empty_message) {
+        tracing::warn!("Message {} has empty content, applying {:?} policy", index, config.prompt.on_empty_message);
+        adjustments.push(Adjustment {
+            field: format!("messages[{}].content", index),
+            original: serde_json::json!(""),
+            effective: match config.prompt.on_empty_message {
+                EmptyMessagePolicy::Space => serde_json::json!(" "),
+                _ => serde_json::Value::Null,
             },
-        },
-    };
+        });
+    }
+
+    // Forward the top-level seed to DeepSeek; Anthropic has no seed parameter
+    if let Some(seed) = request.seed {
+        if let serde_json::Value::Object(body) = &mut request.deepseek_config.body {
+            if let Some(original) = body.insert("seed".to_string(), serde_json::json!(seed)) {
+                if original != serde_json::json!(seed) {
+                    adjustments.push(Adjustment {
+                        field: "deepseek_config.body.seed".to_string(),
+                        original,
+                        effective: serde_json::json!(seed),
+                    });
+                }
+            }
+        } else {
+            request.deepseek_config.body = serde_json::json!({ "seed": seed });
+        }
+    }
+
+    if let Some(limit) = config.limits.max_tokens {
+        let deepseek_max_tokens = deepseek_client.effective_max_tokens(&request.deepseek_config);
+        adjustments.extend(clamp_max_tokens(
+            "deepseek_config.body.max_tokens",
+            deepseek_max_tokens,
+            limit,
+            &mut request.deepseek_config.body,
+        ));
+
+        if let Some(anthropic_client) = anthropic_client {
+            let anthropic_max_tokens = anthropic_client.effective_max_tokens(&request.anthropic_config);
+            adjustments.extend(clamp_max_tokens(
+                "anthropic_config.body.max_tokens",
+                anthropic_max_tokens,
+                limit,
+                &mut request.anthropic_config.body,
+            ));
+        }
+    }
+
+    if let Some(default_system) = &config.prompt.default_system_prompt {
+        let falls_back = [Provider::DeepSeek, Provider::Anthropic]
+            .into_iter()
+            .any(|provider| request.get_system_prompt_for(provider, None).is_none());
+        if falls_back {
+            adjustments.push(Adjustment {
+                field: "system".to_string(),
+                original: serde_json::Value::Null,
+                effective: serde_json::json!(default_system),
+            });
+        }
+    }
 
-    Ok(Json(response))
+    adjustments
 }
 
-/// Handler for streaming chat requests.
-///
-/// Processes the request through both AI models sequentially,
-/// streaming their responses as Server-Sent Events.
+/// Clamps a provider's `max_tokens` to the configured limit.
 ///
 /// # Arguments
 ///
-/// * `state` - Application state containing configuration
-/// * `headers` - HTTP request headers
-/// * `request` - The parsed chat request
+/// * `field` - Name of the field, as reported to the client
+/// * `effective` - The `max_tokens` the provider would otherwise receive
+/// * `limit` - The configured maximum
+/// * `body` - The provider request body to update
 ///
 /// # Returns
 ///
-/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
-pub(crate) async fn chat_stream(
-    State(state): State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-    Json(request): Json<ApiRequest>,
-) -> Result<SseResponse> {
-    // Validate system prompt
-    if !request.validate_system_prompt() {
-        return Err(ApiError::InvalidSystemPrompt);
+/// * `Option<Adjustment>` - The change made, if the value exceeded the limit
+fn clamp_max_tokens(
+    field: &str,
+    effective: Option<u64>,
+    limit: u64,
+    body: &mut serde_json::Value,
+) -> Option<Adjustment> {
+    let original = effective.filter(|&max_tokens| max_tokens > limit)?;
+
+    if !body.is_object() {
+        *body = serde_json::json!({});
     }
+    body["max_tokens"] = serde_json::json!(limit);
 
-    // Extract API tokens
-    let (deepseek_token, anthropic_token) = extract_api_tokens(&headers)?;
+    Some(Adjustment {
+        field: field.to_string(),
+        original: serde_json::json!(original),
+        effective: serde_json::json!(limit),
+    })
+}
 
-    // Initialize clients
-    let deepseek_client = DeepSeekClient::new(deepseek_token);
-    let anthropic_client = AnthropicClient::new(anthropic_token);
+/// Messages prepared for each stage of the pipeline.
+struct PreparedMessages {
+    /// Messages sent to DeepSeek for reasoning
+    deepseek: Vec<Message>,
+    /// Messages sent to Anthropic, before the reasoning is added
+    anthropic: Vec<Message>,
+    /// Whether either stage saw a truncated history
+    history_truncated: bool,
+}
 
-    // Get messages with system prompt
-    let messages = request.get_messages_with_system();
+/// Assembles the messages forwarded to the providers.
+///
+/// Places the system prompt first and builds one message vector per
+/// provider from the full history, each trimmed to the tighter of the
+/// request's effective history limit and that provider's own cap. The
+/// configured few-shot examples are then inserted after the system prompt
+/// so they never count against the limits.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the limits, examples and transforms
+/// * `request` - The chat request
+/// * `adjustments` - Receives an adjustment for every truncated history
+///
+/// # Returns
+///
+/// * `PreparedMessages` - The messages for each provider and whether history was truncated
+fn prepare_messages(
+    state: &AppState,
+    request: &ApiRequest,
+    adjustments: &mut Vec<Adjustment>,
+) -> PreparedMessages {
+    let config = &state.config;
+    let messages = request.get_messages_with_system(
+        config.prompt.default_system_prompt.as_deref(),
+        &state.transforms,
+    );
+    let max_history = request.effective_max_history(config.limits.max_history_messages);
+    let limit_for = |provider_max: Option<usize>| match (max_history, provider_max) {
+        (Some(general), Some(provider)) => Some(general.min(provider)),
+        (general, provider) => general.or(provider),
+    };
+    let deepseek_max = limit_for(config.limits.deepseek_max_history);
+    let anthropic_max = limit_for(config.limits.anthropic_max_history);
 
-    // Create channel for stream events
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    let tx = Arc::new(tx);
+    let mut history_truncated = false;
+    let mut prepare = |field: &str, max_messages: Option<usize>| {
+        let (mut messages, truncated) = match max_messages {
+            Some(max_messages) => trim_history(messages.clone(), max_messages),
+            None => (messages.clone(), false),
+        };
 
-    // Spawn task to handle streaming
-    let config = state.config.clone();
-    let request_clone = request.clone();
-    tokio::spawn(async move {
-        let tx = tx.clone();
+        if truncated {
+            let count_conversation = |messages: &[Message]| {
+                messages.iter().filter(|msg| msg.role != Role::System).count()
+            };
+            adjustments.push(Adjustment {
+                field: field.to_string(),
+                original: serde_json::json!(count_conversation(&request.messages)),
+                effective: serde_json::json!(count_conversation(&messages)),
+            });
+            history_truncated = true;
+        }
 
-        // Start event
-        let _ = tx
-            .send(Ok(Event::default().event("start").data(
-                serde_json::to_string(&StreamEvent::Start {
-                    created: Utc::now(),
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+        let examples_at = messages.iter().take_while(|msg| msg.role == Role::System).count();
+        messages.splice(examples_at..examples_at, config.prompt.examples.iter().cloned());
+        messages
+    };
 
-        // Send initial thinking tag
-        let _ = tx
-            .send(Ok(Event::default().event("content").data(
-                serde_json::to_string(&StreamEvent::Content {
-                    content: vec![ContentBlock {
-                        content_type: "text".to_string(),
-                        text: "<thinking>\n".to_string(),
-                    }],
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+    let (deepseek, anthropic) = if deepseek_max == anthropic_max {
+        let messages = prepare("messages", deepseek_max);
+        (messages.clone(), messages)
+    } else {
+        (
+            prepare("messages.deepseek", deepseek_max),
+            prepare("messages.anthropic", anthropic_max),
+        )
+    };
 
-        // Stream from DeepSeek
-        let mut deepseek_usage = None;
-        let mut complete_reasoning = String::new();
-        let mut deepseek_stream = deepseek_client.chat_stream(messages.clone(), &request_clone.deepseek_config);
-        
-        while let Some(chunk) = deepseek_stream.next().await {
-            match chunk {
-                Ok(response) => {
-                    if let Some(choice) = response.choices.first() {
-                        // Check if reasoning_content is null and break if it is
-                        if choice.delta.reasoning_content.is_none() {
-                            break;
-                        }
+    PreparedMessages {
+        // DeepSeek only takes text, so tool calls and results are written out
+        deepseek: deepseek.into_iter().map(Message::with_tools_as_text).collect(),
+        anthropic,
+        history_truncated,
+    }
+}
 
-                        // Handle delta reasoning_content for streaming
-                        if let Some(reasoning) = &choice.delta.reasoning_content {
-                            if !reasoning.is_empty() {
-                                // Stream the reasoning content as a delta
-                                let _ = tx
-                                    .send(Ok(Event::default().event("content").data(
-                                        serde_json::to_string(&StreamEvent::Content {
-                                            content: vec![ContentBlock {
-                                                content_type: "text_delta".to_string(),
-                                                text: reasoning.to_string(),
-                                            }],
-                                        })
-                                        .unwrap_or_default(),
-                                    )))
-                                    .await;
-                                
-                                // Accumulate complete reasoning for later use
-                                complete_reasoning.push_str(reasoning);
-                            }
-                        }
-                    }
-                    
-                    // Store usage information if present
-                    if let Some(usage) = response.usage {
-                        deepseek_usage = Some(usage);
-                    }
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(
-                            serde_json::to_string(&StreamEvent::Error {
-                                message: e.to_string(),
+/// Resolves the minimum reasoning length for a request.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the server-wide minimum
+/// * `request` - The request, possibly setting its own minimum
+///
+/// # Returns
+///
+/// * `Option<usize>` - The minimum in characters, or None if any reasoning is accepted
+fn min_reasoning_chars(config: &Config, request: &ApiRequest) -> Option<usize> {
+    request
+        .min_reasoning_chars
+        .or(config.reasoning.min_reasoning_chars)
+        .filter(|&min_chars| min_chars > 0)
+}
+
+/// Returns whether reasoning meets the minimum length, ignoring surrounding whitespace.
+fn is_long_enough(reasoning: &str, min_chars: Option<usize>) -> bool {
+    min_chars.is_none_or(|min_chars| reasoning.trim().chars().count() >= min_chars)
+}
+
+/// Builds the error returned when the reasoning stayed below the minimum length.
+fn short_reasoning_error(reasoning: &str, min_chars: usize) -> ApiError {
+    ApiError::DeepSeekError {
+        message: format!(
+            "DeepSeek reasoning has {} characters, fewer than the required {}",
+            reasoning.trim().chars().count(),
+            min_chars
+        ),
+        type_: "short_reasoning".to_string(),
+        param: None,
+        code: None,
+        retry_after: None,
+        status: None,
+    }
+}
+
+/// Builds the error returned when every Anthropic attempt came back empty.
+fn empty_answer_error(retries: u32) -> ApiError {
+    ApiError::AnthropicError {
+        message: format!("Anthropic returned no content after {} retries", retries),
+        type_: "empty_response".to_string(),
+        param: None,
+        code: None,
+    }
+}
+
+/// Sends a non-streaming Anthropic request, retrying empty answers.
+///
+/// With `providers.anthropic.empty_content_retries` set, an answer that
+/// ends its turn normally without any content is requested again with the
+/// same messages, so the reasoning is reused rather than recomputed. The
+/// usage of the empty answers is added to the returned response.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the retry count
+/// * `client` - The Anthropic client
+/// * `messages` - Messages to send, including the injected reasoning
+/// * `system` - Optional system prompt
+/// * `api_config` - Anthropic request configuration
+///
+/// # Returns
+///
+/// * `Result<AnthropicResponse>` - The first answer with content
+///
+/// # Errors
+///
+/// Returns any error from the Anthropic call, or `ApiError::AnthropicError`
+/// if the answer is still empty after every retry
+async fn anthropic_chat(
+    config: &Config,
+    client: &dyn AnswerProvider,
+    messages: Vec<Message>,
+    system: Option<String>,
+    api_config: &ApiConfig,
+) -> Result<anthropic::AnthropicResponse> {
+    let retries = config.providers.anthropic.empty_content_retries;
+    let mut attempt = 0;
+    // Usage of the empty answers, which are billed like any other call
+    let mut discarded = anthropic::Usage::default();
+    loop {
+        let mut response = client.chat(messages.clone(), system.clone(), api_config).await?;
+        if retries == 0 || !response.is_unexpectedly_empty() {
+            response.usage.add(&discarded);
+            return Ok(response);
+        }
+        if attempt == retries {
+            return Err(empty_answer_error(retries));
+        }
+        discarded.add(&response.usage);
+        attempt += 1;
+        tracing::warn!("Anthropic returned no content, retrying ({}/{})", attempt, retries);
+    }
+}
+
+/// Sends a non-streaming Anthropic request, continuing answers cut off at
+/// `max_tokens`.
+///
+/// With `providers.anthropic.auto_continue` set, an answer that stops at
+/// its token limit is requested again with the answer so far as an
+/// assistant prefill, and the continuation is appended to it. Usage is
+/// summed across the calls.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the continuation limit
+/// * `client` - The Anthropic client
+/// * `messages` - Messages to send, including the injected reasoning
+/// * `system` - Optional system prompt
+/// * `api_config` - Anthropic request configuration
+///
+/// # Returns
+///
+/// * `Result<(AnthropicResponse, u32)>` - The joined answer and the number of continuations
+///
+/// # Errors
+///
+/// Returns any error from `anthropic_chat`
+async fn anthropic_answer(
+    config: &Config,
+    client: &dyn AnswerProvider,
+    messages: Vec<Message>,
+    system: Option<String>,
+    api_config: &ApiConfig,
+) -> Result<(anthropic::AnthropicResponse, u32)> {
+    let max_continuations = config.providers.anthropic.auto_continue;
+    let mut response = anthropic_chat(config, client, messages.clone(), system.clone(), api_config).await?;
+
+    let mut continuations = 0;
+    while continuations < max_continuations
+        && response.stop_reason.as_deref() == Some(anthropic::STOP_MAX_TOKENS)
+    {
+        continuations += 1;
+        tracing::debug!("Anthropic answer hit max_tokens, continuing ({}/{})", continuations, max_continuations);
+        let prefilled = continuation_messages(&messages, &response.answer_text());
+        let continuation = anthropic_chat(config, client, prefilled, system.clone(), api_config).await?;
+        response.append(continuation);
+    }
+
+    Ok((response, continuations))
+}
+
+/// Gets an answer from Anthropic that matches the request's response schema.
+///
+/// Runs [`anthropic_answer`] and, when a schema is given, checks the answer
+/// against it. An invalid answer is requested again up to
+/// `providers.anthropic.schema_retries` times, with the violations listed
+/// in a follow-up user message; the usage of every call is summed. An
+/// answer still invalid after the last attempt is returned as is.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the retry counts
+/// * `client` - The Anthropic client
+/// * `messages` - Messages to send, including the injected reasoning and prefill
+/// * `system` - Optional system prompt
+/// * `api_config` - Anthropic request configuration
+/// * `validator` - The compiled response schema, if the request has one
+/// * `prefill` - The request's assistant prefill, which the answer continues
+///
+/// # Returns
+///
+/// * `Result<(anthropic::AnthropicResponse, u32, Option<bool>)>` - The answer, the
+///   number of continuation calls made, and whether it matches the schema
+///
+/// # Errors
+///
+/// Returns an error if any Anthropic call fails
+async fn schema_checked_answer(
+    config: &Config,
+    client: &dyn AnswerProvider,
+    messages: Vec<Message>,
+    system: Option<String>,
+    api_config: &ApiConfig,
+    validator: Option<&jsonschema::Validator>,
+    prefill: Option<&str>,
+) -> Result<(anthropic::AnthropicResponse, u32, Option<bool>)> {
+    let (mut response, mut continuations) =
+        anthropic_answer(config, client, messages.clone(), system.clone(), api_config).await?;
+    let Some(validator) = validator else {
+        return Ok((response, continuations, None));
+    };
+
+    let max_retries = config.providers.anthropic.schema_retries;
+    let mut retries = 0;
+    loop {
+        let answer = response.answer_text();
+        let errors = schema::check(validator, &format!("{}{}", prefill.unwrap_or_default().trim_end(), answer));
+        if errors.is_empty() {
+            return Ok((response, continuations, Some(true)));
+        }
+        if retries >= max_retries {
+            tracing::warn!("Anthropic answer does not match the response schema: {}", errors.join("; "));
+            return Ok((response, continuations, Some(false)));
+        }
+
+        retries += 1;
+        tracing::debug!("Anthropic answer does not match the response schema, retrying ({}/{})", retries, max_retries);
+        let mut retry_messages = continuation_messages(&messages, &answer);
+        retry_messages.push(Message {
+            role: Role::User,
+            content: schema::correction_prompt(&errors),
+            tool_blocks: Vec::new(),
+        });
+        if let Some(prefill) = prefill {
+            reasoning::prefill(&mut retry_messages, prefill);
+        }
+
+        let (retry, retry_continuations) =
+            anthropic_answer(config, client, retry_messages, system.clone(), api_config).await?;
+        let spent = response.usage;
+        response = retry;
+        response.usage.add(&spent);
+        continuations += retry_continuations;
+    }
+}
+
+/// Gets `n` independent answers to the same messages, concurrently.
+///
+/// Each answer is a separate [`schema_checked_answer`] call, with its own
+/// continuations and schema retries. Every candidate runs to completion,
+/// so the ones that finished can be billed when another fails.
+///
+/// # Arguments
+///
+/// * `n` - Number of answers, at least one
+/// * `config` - Configuration containing the retry counts
+/// * `client` - The Anthropic client
+/// * `messages` - Messages to send, including the injected reasoning and prefill
+/// * `system` - Optional system prompt
+/// * `api_config` - Anthropic request configuration
+/// * `validator` - The compiled response schema, if the request has one
+/// * `prefill` - The request's assistant prefill, which the answers continue
+///
+/// # Returns
+///
+/// * `Vec<Result<(anthropic::AnthropicResponse, u32, Option<bool>)>>` - The
+///   results in order, as returned by [`schema_checked_answer`]
+#[allow(clippy::too_many_arguments)]
+async fn answer_candidates(
+    n: u32,
+    config: &Config,
+    client: &dyn AnswerProvider,
+    messages: Vec<Message>,
+    system: Option<String>,
+    api_config: &ApiConfig,
+    validator: Option<&jsonschema::Validator>,
+    prefill: Option<&str>,
+) -> Vec<Result<(anthropic::AnthropicResponse, u32, Option<bool>)>> {
+    futures::future::join_all((0..n.max(1)).map(|_| {
+        schema_checked_answer(config, client, messages.clone(), system.clone(), api_config, validator, prefill)
+    }))
+    .await
+}
+
+/// Returns the answer candidates, or the first error among them.
+///
+/// When a candidate failed, the calls already paid for, the DeepSeek
+/// reasoning, the reasoning summary and the candidates that finished, are
+/// recorded in the cost counters before the error is returned.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration and cost counters
+/// * `request` - The request, for its pricing override and tags
+/// * `deepseek` - The DeepSeek response, unless the reasoning came from the cache
+/// * `summary` - The reasoning summary call, if one was made
+/// * `results` - The results of [`answer_candidates`]
+fn finished_candidates(
+    state: &AppState,
+    request: &ApiRequest,
+    deepseek: Option<&DeepSeekResponse>,
+    summary: Option<&anthropic::AnthropicResponse>,
+    results: Vec<Result<(anthropic::AnthropicResponse, u32, Option<bool>)>>,
+) -> Result<Vec<(anthropic::AnthropicResponse, u32, Option<bool>)>> {
+    if results.iter().any(Result::is_err) {
+        let finished: Vec<_> = results
+            .iter()
+            .filter_map(|result| result.as_ref().ok().map(|(response, _, _)| response))
+            .chain(summary)
+            .collect();
+        record_failed_spend(state, request, deepseek, &finished);
+    }
+    results.into_iter().collect()
+}
+
+/// Builds the messages for continuing a cut-off answer.
+///
+/// The answer so far becomes the final assistant turn, which Anthropic
+/// continues rather than answering anew. It is joined onto a trailing
+/// assistant message, such as the injected reasoning, and trailing
+/// whitespace is trimmed since Anthropic rejects it in a prefill.
+///
+/// # Arguments
+///
+/// * `messages` - Messages the answer was requested with
+/// * `answer` - The answer so far
+///
+/// # Returns
+///
+/// * `Vec<Message>` - Messages ending in the prefilled answer
+fn continuation_messages(messages: &[Message], answer: &str) -> Vec<Message> {
+    let mut messages = messages.to_vec();
+    match messages.last_mut() {
+        Some(last) if last.role == Role::Assistant => last.content.push_str(answer),
+        _ => messages.push(Message {
+            role: Role::Assistant,
+            content: answer.to_string(),
+            tool_blocks: Vec::new(),
+        }),
+    }
+    if let Some(last) = messages.last_mut() {
+        last.content.truncate(last.content.trim_end().len());
+    }
+    messages
+}
+
+/// Opens an Anthropic stream, retrying streams that end without content.
+///
+/// With `providers.anthropic.empty_content_retries` set, events are held
+/// back until the first content arrives. A stream that reaches
+/// `message_stop` after ending its turn normally before that is opened
+/// again; otherwise, including on transport and HTTP errors, the held
+/// events are replayed ahead of the rest of the stream. The empty attempts
+/// are still billed, so their usage is returned alongside the stream.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the retry count
+/// * `client` - The Anthropic client
+/// * `messages` - Messages to send, including the injected reasoning
+/// * `system` - Optional system prompt
+/// * `api_config` - Anthropic request configuration
+///
+/// # Returns
+///
+/// * A stream of Anthropic events, ending in an error if every attempt was
+///   empty, and the usage of the discarded empty attempts
+async fn anthropic_chat_stream(
+    config: &Config,
+    client: &dyn AnswerProvider,
+    messages: Vec<Message>,
+    system: Option<String>,
+    api_config: &ApiConfig,
+) -> (Pin<Box<dyn Stream<Item = Result<anthropic::StreamEvent>> + Send>>, anthropic::Usage) {
+    let mut discarded = anthropic::Usage::default();
+    let retries = config.providers.anthropic.empty_content_retries;
+    if retries == 0 {
+        return (client.chat_stream(messages, system, api_config), discarded);
+    }
+
+    let mut attempt = 0;
+    loop {
+        let mut stream = client.chat_stream(messages.clone(), system.clone(), api_config);
+        let mut held = Vec::new();
+        let mut stop_reason = None;
+        let mut has_content = false;
+        let mut stopped = false;
+        // Latest usage of this attempt, as priced for a streamed answer
+        let mut usage = None;
+        while let Some(item) = stream.next().await {
+            match &item {
+                Ok(anthropic::StreamEvent::MessageStart { message }) => usage = Some(message.usage.clone()),
+                Ok(anthropic::StreamEvent::ContentBlockStart { content_block, .. }) => {
+                    has_content = !content_block.text.is_empty() || content_block.tool_call.is_some();
+                }
+                Ok(anthropic::StreamEvent::ContentBlockDelta { delta, .. }) => {
+                    has_content = !delta.text.is_empty();
+                }
+                Ok(anthropic::StreamEvent::MessageDelta { delta, usage: delta_usage }) => {
+                    stop_reason = delta.stop_reason.clone();
+                    if delta_usage.is_some() {
+                        usage = delta_usage.clone();
+                    }
+                }
+                Ok(anthropic::StreamEvent::MessageStop) => stopped = true,
+                // Errors are passed through unchanged
+                Err(_) => has_content = true,
+                Ok(_) => {}
+            }
+            held.push(item);
+            if has_content {
+                break;
+            }
+        }
+
+        // Only a stream that completed normally counts as an empty answer
+        if has_content || !stopped || !anthropic::is_unexpected_empty(stop_reason.as_deref()) {
+            return (Box::pin(futures::stream::iter(held).chain(stream)), discarded);
+        }
+        if let Some(usage) = &usage {
+            discarded.add(usage);
+        }
+        if attempt == retries {
+            return (Box::pin(futures::stream::iter([Err(empty_answer_error(retries))])), discarded);
+        }
+        attempt += 1;
+        tracing::warn!("Anthropic stream ended with no content, retrying ({}/{})", attempt, retries);
+    }
+}
+
+/// Extracts the reasoning from a non-streaming DeepSeek response.
+///
+/// Falls back to the message content for models that do not produce
+/// `reasoning_content`, such as `deepseek-chat`. A field that is null or
+/// only whitespace counts as absent, so an empty `reasoning_content` does
+/// not hide the content.
+///
+/// # Arguments
+///
+/// * `response` - The DeepSeek response
+///
+/// # Returns
+///
+/// * `Result<&str>` - The reasoning content
+///
+/// # Errors
+///
+/// Returns `ApiError::DeepSeekError` if the response has no choices, or if
+/// both `reasoning_content` and `content` are null or empty
+fn reasoning_from(response: &DeepSeekResponse) -> Result<&str> {
+    let missing = |message: &str| ApiError::DeepSeekError {
+        message: message.to_string(),
+        type_: "missing_content".to_string(),
+        param: None,
+        code: None,
+        retry_after: None,
+        status: None
+    };
+    let message = &response
+        .choices
+        .first()
+        .ok_or_else(|| missing("DeepSeek response has no choices"))?
+        .message;
+    [&message.reasoning_content, &message.content]
+        .into_iter()
+        .find_map(|field| field.as_deref().filter(|text| !text.trim().is_empty()))
+        .ok_or_else(|| missing("DeepSeek response has neither reasoning_content nor content"))
+}
+
+/// Assembles the system prompt sent to Anthropic.
+///
+/// The request's Anthropic or shared system prompt, or the configured
+/// default, is followed by an instruction to answer in the request's
+/// `answer_language`, or in `prompt.answer_language`. The separator is the
+/// one joining system prompt fragments. DeepSeek's prompt is never changed.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the prompt defaults
+/// * `request` - The request after adjustments
+///
+/// # Returns
+///
+/// * `Option<String>` - The system prompt, or None if there is nothing to send
+fn anthropic_system_prompt(config: &Config, request: &ApiRequest) -> Option<String> {
+    let system = request.get_system_prompt_for(Provider::Anthropic, config.prompt.default_system_prompt.as_deref());
+    let language = request
+        .answer_language
+        .as_deref()
+        .or(config.prompt.answer_language.as_deref())
+        .map(str::trim)
+        .filter(|language| !language.is_empty());
+    let Some(language) = language else {
+        return system.map(String::from);
+    };
+
+    let instruction = format!("Respond only in {}.", language);
+    Some(match system {
+        Some(system) => {
+            let separator = config.prompt.system_separator.as_deref().unwrap_or(DEFAULT_SYSTEM_SEPARATOR);
+            format!("{}{}{}", system, separator, instruction)
+        }
+        None => instruction,
+    })
+}
+
+/// Describes the seed a request runs with, for echoing back to the client.
+///
+/// # Arguments
+///
+/// * `request` - The request after adjustments
+/// * `uses_anthropic` - Whether the request will reach Anthropic, which cannot be seeded
+///
+/// # Returns
+///
+/// * `Option<SeedInfo>` - The seed and the providers that ignore it, or None if unseeded
+fn seed_info(request: &ApiRequest, uses_anthropic: bool) -> Option<SeedInfo> {
+    request.effective_seed().map(|value| SeedInfo {
+        value,
+        unsupported_providers: if uses_anthropic { vec![Provider::Anthropic] } else { Vec::new() },
+    })
+}
+
+/// Converts DeepSeek usage into the response format and prices it.
+///
+/// # Arguments
+///
+/// * `usage` - DeepSeek usage, if the provider reported any
+/// * `model` - The DeepSeek model that produced the usage
+/// * `pricing` - The rates to price the usage with
+///
+/// # Returns
+///
+/// * `(DeepSeekUsage, f64)` - The usage statistics and their cost in dollars
+fn deepseek_usage_with_cost(
+    usage: Option<&crate::clients::deepseek::Usage>,
+    model: &str,
+    pricing: &PricingConfig,
+) -> (DeepSeekUsage, f64) {
+    let Some(usage) = usage else {
+        return (DeepSeekUsage::default(), 0.0);
+    };
+
+    let (cost, pricing_estimated) = calculate_deepseek_cost(
+        model,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.completion_tokens_details.reasoning_tokens,
+        usage.prompt_tokens_details.cached_tokens,
+        pricing,
+    );
+
+    (DeepSeekUsage {
+        input_tokens: usage.prompt_tokens,
+        output_tokens: usage.completion_tokens,
+        reasoning_tokens: usage.completion_tokens_details.reasoning_tokens,
+        cached_input_tokens: usage.prompt_tokens_details.cached_tokens,
+        total_tokens: usage.total_tokens,
+        total_cost: format_cost(cost),
+        pricing_estimated,
+        cached: false,
+    }, cost)
+}
+
+/// Fetches the DeepSeek reasoning, reusing cached reasoning when available.
+///
+/// Fresh reasoning is stored in the cache for later requests with the same
+/// messages and DeepSeek config. Reasoning shorter than the request's
+/// `min_reasoning_chars` is requested again up to
+/// `reasoning.short_reasoning_retries` times, and is neither cached nor
+/// taken from the cache. The rejected calls are still billed: their usage
+/// is added to the returned response, or recorded in the cost counters
+/// when every attempt was too short.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration, cache and cost counters
+/// * `request` - The chat request, for its DeepSeek config, pricing and tags
+/// * `deepseek_client` - Client used on a cache miss
+/// * `messages` - Messages to send to DeepSeek
+///
+/// # Returns
+///
+/// * `Result<(DeepSeekResponse, bool)>` - The response and whether it came from the cache
+///
+/// # Errors
+///
+/// Returns `ApiError::DeepSeekError` if the reasoning is still too short
+/// after every retry, or any error from the DeepSeek call
+async fn cached_deepseek_chat(
+    state: &AppState,
+    request: &ApiRequest,
+    deepseek_client: &dyn ReasoningProvider,
+    messages: Vec<Message>,
+) -> Result<(DeepSeekResponse, bool)> {
+    let config = &state.config;
+    let cache = &state.reasoning_cache;
+    let api_config = &request.deepseek_config;
+    let min_chars = min_reasoning_chars(config, request);
+    let key = cache.key(&messages, api_config, &[deepseek_client.api_token()]);
+    let cached = key
+        .and_then(|key| cache.get(key))
+        .filter(|cached| is_long_enough(&cached.reasoning, min_chars));
+    if let Some(cached) = cached {
+        return Ok((cached.to_response(), true));
+    }
+
+    let retries = config.reasoning.short_reasoning_retries;
+    let mut attempt = 0;
+    // Usage of the reasoning rejected as too short
+    let mut rejected = deepseek::Usage::default();
+    loop {
+        let mut response = deepseek_client.chat(messages.clone(), api_config).await?;
+        let reasoning = reasoning_from(&response).unwrap_or_default();
+        if is_long_enough(reasoning, min_chars) {
+            if let (Some(key), Ok(reasoning)) = (key, reasoning_from(&response)) {
+                cache.insert(key, CachedReasoning {
+                    reasoning: reasoning.to_string(),
+                    model: response.model.clone(),
+                    system_fingerprint: response.system_fingerprint.clone(),
+                });
+            }
+            response.usage.add(&rejected);
+            return Ok((response, false));
+        }
+
+        let min_chars = min_chars.unwrap_or_default();
+        if attempt == retries {
+            let error = short_reasoning_error(reasoning, min_chars);
+            response.usage.add(&rejected);
+            record_failed_spend(state, request, Some(&response), &[]);
+            return Err(error);
+        }
+        rejected.add(&response.usage);
+        attempt += 1;
+        tracing::warn!("DeepSeek reasoning is shorter than {} characters, retrying ({}/{})", min_chars, attempt, retries);
+    }
+}
+
+/// Instruction prepended to the reasoning in `llm_summary` calls.
+const SUMMARY_PROMPT: &str = "Condense the following reasoning. Keep every conclusion, intermediate result and caveat needed to answer the question, and drop restatements and dead ends. Reply with the condensed reasoning only.";
+
+/// Condenses reasoning per `reasoning.summarization` before the answer call.
+///
+/// `llm_summary` asks `reasoning.summary_model` for a summary through the
+/// request's Anthropic headers and base URL; if that call fails, or returns
+/// no text, the reasoning is truncated instead.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the summarization settings
+/// * `anthropic_client` - Client used for `llm_summary`
+/// * `request` - The chat request, for its Anthropic config
+/// * `reasoning` - The raw reasoning content
+///
+/// # Returns
+///
+/// The reasoning to hand to Anthropic, and the summary call's response
+/// when one was made
+async fn summarize_reasoning<'a>(
+    config: &Config,
+    anthropic_client: &dyn AnswerProvider,
+    request: &ApiRequest,
+    reasoning: &'a str,
+) -> (Cow<'a, str>, Option<anthropic::AnthropicResponse>) {
+    let settings = &config.reasoning;
+    if reasoning.chars().count() <= settings.summary_max_chars {
+        return (Cow::Borrowed(reasoning), None);
+    }
+
+    match settings.summarization {
+        ReasoningSummarization::None => (Cow::Borrowed(reasoning), None),
+        ReasoningSummarization::Truncate => (reasoning::truncate(reasoning, settings.summary_max_chars), None),
+        ReasoningSummarization::LlmSummary => {
+            let messages = vec![Message {
+                role: Role::User,
+                content: format!("{}\n\n{}", SUMMARY_PROMPT, reasoning),
+                tool_blocks: Vec::new(),
+            }];
+            let summary_config = ApiConfig {
+                headers: request.anthropic_config.headers.clone(),
+                body: serde_json::json!({
+                    "model": settings.summary_model,
+                    "max_tokens": settings.summary_max_tokens,
+                }),
+                base_url: request.anthropic_config.base_url.clone(),
+            };
+            match anthropic_client.chat(messages, None, &summary_config).await {
+                Ok(response) => {
+                    let summary: String = response
+                        .content
+                        .iter()
+                        .filter(|block| block.content_type == "text")
+                        .map(|block| block.text.as_str())
+                        .collect();
+                    let summary = if summary.trim().is_empty() {
+                        tracing::warn!("Reasoning summary was empty, truncating the reasoning instead");
+                        reasoning::truncate(reasoning, settings.summary_max_chars)
+                    } else {
+                        Cow::Owned(summary)
+                    };
+                    (summary, Some(response))
+                }
+                Err(e) => {
+                    tracing::warn!("Reasoning summary failed, truncating the reasoning instead: {}", e);
+                    (reasoning::truncate(reasoning, settings.summary_max_chars), None)
+                }
+            }
+        }
+    }
+}
+
+/// Calculates the cost of DeepSeek API usage.
+///
+/// Cached input tokens are billed at the cache-hit rate and the remaining
+/// input tokens at the cache-miss rate. Models without an entry in
+/// `pricing.deepseek.models` use the fallback rates.
+///
+/// # Arguments
+///
+/// * `model` - The specific DeepSeek model used
+/// * `input_tokens` - Number of input tokens processed
+/// * `output_tokens` - Number of output tokens generated
+/// * `_reasoning_tokens` - Number of tokens used for reasoning
+/// * `cached_tokens` - Number of tokens retrieved from cache
+/// * `pricing` - The rates to price the usage with
+///
+/// # Returns
+///
+/// The total cost in dollars for the API usage, and whether it was priced
+/// with the fallback rates because the model is unknown
+pub(crate) fn calculate_deepseek_cost(
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    _reasoning_tokens: u32,
+    cached_tokens: u32,
+    pricing: &PricingConfig,
+) -> (f64, bool) {
+    let (pricing, estimated) = pricing.deepseek.for_model(model);
+    if estimated {
+        tracing::debug!("No pricing configured for DeepSeek model {}, using fallback rates", model);
+    }
+
+    let cache_hit_cost = (cached_tokens as f64 / 1_000_000.0) * pricing.input_cache_hit_price;
+    let cache_miss_cost = (input_tokens.saturating_sub(cached_tokens) as f64 / 1_000_000.0) * pricing.input_cache_miss_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
+    
+    (cache_hit_cost + cache_miss_cost + output_cost, estimated)
+}
+
+/// Calculates the cost of Anthropic API usage.
+///
+/// The rates are picked by model family: `claude-3-5-sonnet`,
+/// `claude-3-5-haiku` and `claude-3-opus` ids use their own entries, and
+/// any other model is silently priced as Sonnet.
+///
+/// # Arguments
+///
+/// * `model` - The specific Claude model used
+/// * `input_tokens` - Number of input tokens processed
+/// * `output_tokens` - Number of output tokens generated
+/// * `cache_write_tokens` - Number of tokens written to cache
+/// * `cache_read_tokens` - Number of tokens read from cache
+/// * `pricing` - The rates to price the usage with
+///
+/// # Returns
+///
+/// The total cost in dollars for the API usage
+pub(crate) fn calculate_anthropic_cost(
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_write_tokens: u32,
+    cache_read_tokens: u32,
+    pricing: &PricingConfig,
+) -> f64 {
+    let pricing = if model.contains("claude-3-5-sonnet") {
+        &pricing.anthropic.claude_3_sonnet
+    } else if model.contains("claude-3-5-haiku") {
+        &pricing.anthropic.claude_3_haiku
+    } else if model.contains("claude-3-opus") {
+        &pricing.anthropic.claude_3_opus
+    } else {
+        &pricing.anthropic.claude_3_sonnet // default to sonnet pricing
+    };
+
+    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
+    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
+    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+
+    input_cost + output_cost + cache_write_cost + cache_read_cost
+}
+
+/// Prices a request's usage for reporting and for the spend counters.
+///
+/// Costs are reported with the request's `pricing_override` applied, if
+/// any, while the amount recorded as spent always uses the configured rates.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the configured rates
+/// * `request` - The request, possibly carrying a pricing override
+/// * `price` - Prices the usage with the given rates, returning the reported
+///   values and their total cost
+///
+/// # Returns
+///
+/// * `(T, f64)` - The values priced for reporting, and the actual spend in dollars
+fn price_request<T>(
+    config: &Config,
+    request: &ApiRequest,
+    price: impl Fn(&PricingConfig) -> (T, f64),
+) -> (T, f64) {
+    match &request.pricing_override {
+        Some(pricing_override) => (
+            price(&config.pricing.with_override(pricing_override)).0,
+            price(&config.pricing).1,
+        ),
+        None => price(&config.pricing),
+    }
+}
+
+/// Records the spend of a request that failed after paying for provider calls.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration and cost counters
+/// * `request` - The request, for its pricing override and tags
+/// * `deepseek` - The DeepSeek response, unless the reasoning came from the cache
+/// * `answers` - Anthropic responses that completed
+fn record_failed_spend(
+    state: &AppState,
+    request: &ApiRequest,
+    deepseek: Option<&DeepSeekResponse>,
+    answers: &[&anthropic::AnthropicResponse],
+) {
+    let ((), spent) = price_request(&state.config, request, |pricing| {
+        let (_, deepseek_cost) = deepseek_usage_with_cost(
+            deepseek.map(|response| &response.usage),
+            deepseek.map_or("", |response| response.model.as_str()),
+            pricing,
+        );
+        let anthropic_cost: f64 = answers
+            .iter()
+            .map(|response| calculate_anthropic_cost(
+                &response.model,
+                response.usage.input_tokens,
+                response.usage.output_tokens,
+                response.usage.cache_creation_input_tokens,
+                response.usage.cache_read_input_tokens,
+                pricing,
+            ))
+            .sum();
+        ((), deepseek_cost + anthropic_cost)
+    });
+    state.costs.record(spent, &request.tags);
+}
+
+/// Formats a cost value as a dollar amount string.
+///
+/// # Arguments
+///
+/// * `cost` - The cost value to format
+///
+/// # Returns
+///
+/// A string representing the cost with 3 decimal places and $ prefix
+fn format_cost(cost: f64) -> String {
+    format!("${:.3}", cost)
+}
+
+/// Decides whether a request should be answered with a stream.
+///
+/// An explicit `stream` field in the body wins. Otherwise the request is
+/// streamed when its `Accept` header asks for `text/event-stream`.
+///
+/// # Arguments
+///
+/// * `request` - The parsed request
+/// * `headers` - HTTP request headers
+fn wants_stream(request: &ApiRequest, headers: &axum::http::HeaderMap) -> bool {
+    request.stream.unwrap_or_else(|| accepts_event_stream(headers))
+}
+
+/// Returns whether the `Accept` header asks for `text/event-stream`.
+///
+/// # Arguments
+///
+/// * `headers` - HTTP request headers
+fn accepts_event_stream(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| {
+            accept.split(',').any(|media| {
+                media.split(';').next().unwrap_or_default().trim() == "text/event-stream"
+            })
+        })
+}
+
+/// Delivers an error as a single `error` event in a successful SSE response.
+///
+/// Used for clients that accept `text/event-stream` so errors raised before
+/// a stream starts reach them the same way as errors raised mid-stream.
+///
+/// # Arguments
+///
+/// * `error` - The error to report
+///
+/// # Returns
+///
+/// * `axum::response::Response` - A 200 SSE response carrying the error event
+async fn error_stream(error: ApiError) -> axum::response::Response {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut emitter = StreamEmitter {
+        tx,
+        replay: None,
+        request_id: Uuid::new_v4().to_string(),
+        next_id: 0,
+        log: None,
+        omit_content: false,
+        granularity: StreamGranularity::Token,
+        pending: None,
+        last_flush: Instant::now(),
+        ttft: FirstTokenTimer::new(Instant::now(), None),
+    };
+
+    let (status, _) = error.to_status_and_response();
+    emitter.emit(StreamEvent::Error {
+        message: error.to_string(),
+        code: status.as_u16(),
+    }).await;
+    drop(emitter);
+
+    sse_events(None, rx).into_response()
+}
+
+/// Main handler for chat requests.
+///
+/// Routes requests to either streaming or non-streaming handlers
+/// based on the request's `stream` field or, when omitted, its
+/// `Accept` header. Streaming requests from clients that accept
+/// `text/event-stream` receive errors raised before the stream starts
+/// as an `error` event rather than a JSON error response. Requests with
+/// a `callback_url` are accepted immediately and answered by webhook.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - The API response or an error
+pub async fn handle_chat(
+    state: State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    ApiJson(mut request): ApiJson<ApiRequest>,
+) -> Result<axum::response::Response> {
+    authorize_admin_options(&state.config, &headers, &request)?;
+    gate_base_url_overrides(&state.config, &headers, &mut request);
+
+    if request.callback_url.is_some() {
+        chat_callback(state, headers, Json(request)).await
+    } else if wants_stream(&request, &headers) {
+        let sse_client = accepts_event_stream(&headers);
+        match chat_stream(state, headers, Json(request)).await {
+            Err(e) if sse_client => Ok(error_stream(e).await),
+            result => result,
+        }
+    } else {
+        chat(state, headers, Json(request)).await
+    }
+}
+
+/// Handler for streaming chat over a WebSocket.
+///
+/// The client sends a chat request as the first text frame and receives
+/// the same events as an SSE stream, one JSON text frame each, in the same
+/// order. Errors are sent as an `error` event before the socket closes.
+/// Closing the socket cancels the upstream calls.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP headers of the upgrade request, carrying the API tokens
+/// * `ws` - The WebSocket upgrade
+///
+/// # Returns
+///
+/// * `axum::response::Response` - The upgrade response
+pub async fn handle_ws(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    ws: WebSocketUpgrade,
+) -> axum::response::Response {
+    ws.on_upgrade(move |socket| ws_session(state, headers, socket))
+}
+
+/// Runs a single WebSocket chat session.
+async fn ws_session(state: Arc<AppState>, headers: axum::http::HeaderMap, mut socket: WebSocket) {
+    let request = match socket.recv().await {
+        Some(Ok(WsMessage::Text(text))) => serde_json::from_str::<ApiRequest>(&text).map_err(|e| ApiError::BadRequest {
+            message: format!("Invalid request: {}", e),
+        }),
+        Some(Ok(_)) => Err(ApiError::BadRequest {
+            message: "The first message must be a JSON chat request".to_string(),
+        }),
+        _ => return,
+    };
+
+    let started = match request {
+        Ok(mut request) => match authorize_admin_options(&state.config, &headers, &request) {
+            Ok(()) => {
+                gate_base_url_overrides(&state.config, &headers, &mut request);
+                start_chat_stream(state, headers, request).await
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let mut stream = match started {
+        Ok(stream) => stream,
+        Err(e) => {
+            let (status, _) = e.to_status_and_response();
+            let event = StreamEvent::Error {
+                message: e.to_string(),
+                code: status.as_u16(),
+            };
+            let data = serde_json::to_string(&event).unwrap_or_default();
+            let _ = socket.send(WsMessage::Text(data.into())).await;
+            let _ = socket.send(WsMessage::Close(None)).await;
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            event = stream.events.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                if socket.send(WsMessage::Text(event.data.into())).await.is_err() {
+                    stream.task.abort();
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => {
+                    stream.task.abort();
+                    return;
+                }
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = socket.send(WsMessage::Close(None)).await;
+}
+
+/// Handler for chat requests answered by webhook.
+///
+/// Validates the request and returns 202 Accepted with a request id, then
+/// runs the pipeline in a background task and POSTs the final response,
+/// or the error envelope if it fails, to the request's `callback_url`.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<axum::response::Response>` - A 202 response carrying the request id
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the callback URL is invalid, resolves
+/// to a non-public address or the request also asks to be streamed,
+/// `ApiError::Unauthorized` if its host is not in `webhook.allowed_hosts`
+/// and the admin token is missing, or any error from validating the
+/// request or its API tokens
+pub(crate) async fn chat_callback(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    let callback_url = request.callback_url.clone().unwrap_or_default();
+    if !webhook::is_valid_callback_url(&callback_url) {
+        return Err(ApiError::BadRequest {
+            message: "callback_url must be an absolute http(s) URL".to_string(),
+        });
+    }
+    if request.stream == Some(true) {
+        return Err(ApiError::BadRequest {
+            message: "callback_url cannot be combined with streaming".to_string(),
+        });
+    }
+
+    // The server POSTs model output to the URL, so unlisted hosts are for
+    // operators only, and never internal ones
+    if !webhook::is_allowed_host(&state.config.webhook, &callback_url) {
+        authorize_admin(&state.config, &headers).map_err(|_| ApiError::Unauthorized {
+            message: "callback_url host is not in webhook.allowed_hosts and the admin token is missing".to_string(),
+        })?;
+        webhook::resolve_public(&callback_url)
+            .await
+            .map_err(|message| ApiError::BadRequest { message })?;
+    }
+
+    validate_request(&state.config, &request, true)?;
+    let (deepseek_client, anthropic_client) = build_clients(&state, &headers)?;
+
+    let request_id = Uuid::new_v4().to_string();
+    let task_state = state.clone();
+    let task_request_id = request_id.clone();
+    tokio::spawn(async move {
+        let payload = match run_pipeline(&task_state, deepseek_client.as_ref(), anthropic_client.as_ref(), &request).await {
+            Ok(mut response) => {
+                // Match the id the request was accepted under
+                response.id = task_request_id.clone();
+                log_chat_response(&task_state, &request, &response);
+                serde_json::to_value(response)
+            }
+            Err(e) => serde_json::to_value(e.to_error_response()),
+        }
+        .unwrap_or_default();
+
+        webhook::deliver(
+            &task_state.config.http,
+            &task_state.config.webhook,
+            &callback_url,
+            &task_request_id,
+            &payload,
+        ).await;
+    });
+
+    Ok((
+        axum::http::StatusCode::ACCEPTED,
+        [(REQUEST_ID_HEADER, request_id.clone())],
+        Json(CallbackAccepted { request_id }),
+    ).into_response())
+}
+
+/// Handler for non-streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// combining their responses and tracking usage. Requests carrying an
+/// `Idempotency-Key` header reuse the response of an earlier or in-flight
+/// request with the same key and API tokens.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<axum::response::Response>` - The combined API response, with
+///   `Server-Timing` and `X-Total-Cost` headers, or an error
+pub(crate) async fn chat(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    // Initialize clients
+    let (deepseek_client, anthropic_client) = build_clients(&state, &headers)?;
+
+    let run = || async {
+        let response = run_pipeline(&state, deepseek_client.as_ref(), anthropic_client.as_ref(), &request).await?;
+        log_chat_response(&state, &request, &response);
+        Ok(response)
+    };
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let response = match idempotency_key {
+        Some(key) => {
+            // Token presence was checked when the clients were built; mock mode needs none
+            let (deepseek_token, anthropic_token) = extract_api_tokens(&headers).unwrap_or_default();
+            state.idempotency.get_or_run(key, &[&deepseek_token, &anthropic_token], run).await?
+        }
+        None => run().await?,
+    };
+    let request_id = response.id.clone();
+    let server_timing = response.timings.to_server_timing();
+    let total_cost = response.combined_usage.total_cost.clone();
+    let deepseek_request_id = response.deepseek_request_id.clone();
+    let anthropic_request_id = response.anthropic_request_id.clone();
+    let rate_limit_headers = response
+        .rate_limits
+        .as_ref()
+        .filter(|_| state.config.server.rate_limit_headers)
+        .map(rate_limit_headers)
+        .unwrap_or_default();
+
+    let body = if request.verbose && request.verbose_transfer == VerboseTransfer::Ndjson {
+        ndjson_body(response).into_response()
+    } else {
+        Json(response).into_response()
+    };
+
+    Ok((
+        [
+            (REQUEST_ID_HEADER, request_id),
+            (SERVER_TIMING_HEADER, server_timing),
+            (TOTAL_COST_HEADER, total_cost),
+        ],
+        deepseek_request_id.map(|id| [(DEEPSEEK_REQUEST_ID_HEADER, id)]),
+        anthropic_request_id.map(|id| [(ANTHROPIC_REQUEST_ID_HEADER, id)]),
+        axum::response::AppendHeaders(rate_limit_headers),
+        body,
+    ).into_response())
+}
+
+/// Builds the response headers echoing the providers' rate-limit state.
+///
+/// # Arguments
+///
+/// * `rate_limits` - Rate-limit state reported by the providers
+///
+/// # Returns
+///
+/// * `Vec<(String, String)>` - `X-{Provider}-RateLimit-Remaining-{Requests,Tokens}`
+///   headers for every value the providers reported
+fn rate_limit_headers(rate_limits: &RateLimits) -> Vec<(String, String)> {
+    let providers: [(&str, &Option<RateLimit>); 2] = [
+        ("DeepSeek", &rate_limits.deepseek),
+        ("Anthropic", &rate_limits.anthropic),
+    ];
+    providers
+        .into_iter()
+        .filter_map(|(provider, rate_limit)| rate_limit.as_ref().map(|rate_limit| (provider, rate_limit)))
+        .flat_map(|(provider, rate_limit)| {
+            [
+                ("Requests", rate_limit.requests_remaining),
+                ("Tokens", rate_limit.tokens_remaining),
+            ]
+            .into_iter()
+            .filter_map(move |(kind, remaining)| {
+                remaining.map(|remaining| {
+                    (format!("X-{}-RateLimit-Remaining-{}", provider, kind), remaining.to_string())
+                })
+            })
+        })
+        .collect()
+}
+
+/// Streams a verbose response as newline-delimited JSON.
+///
+/// The first line is the response with its raw upstream bodies removed,
+/// followed by one `{"provider": ..., "status": ..., "headers": ...,
+/// "body": ...}` line per raw body. Each line is serialized only as it is
+/// sent, so the full response is never rendered into one buffer.
+///
+/// # Arguments
+///
+/// * `response` - The verbose response to send
+///
+/// # Returns
+///
+/// * `impl IntoResponse` - An `application/x-ndjson` response
+fn ndjson_body(mut response: ApiResponse) -> impl IntoResponse {
+    let raw_bodies = [
+        response.deepseek_response.take().map(|raw| (Provider::DeepSeek, raw)),
+        response.anthropic_response.take().map(|raw| (Provider::Anthropic, raw)),
+    ];
+
+    let first = std::iter::once(serde_json::to_vec(&response));
+    let rest = raw_bodies
+        .into_iter()
+        .flatten()
+        .map(|(provider, response)| serde_json::to_vec(&RawBodyLine { provider, response }));
+    let lines = futures::stream::iter(first.chain(rest)).map(|line| {
+        line.map(|mut line| {
+            line.push(b'\n');
+            line
+        })
+    });
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(lines),
+    )
+}
+
+/// Handler for batched chat requests.
+///
+/// Runs every request in the batch through the full non-streaming pipeline,
+/// with at most `batch.max_concurrency` requests in flight at once. Batches
+/// larger than `batch.max_items` are rejected. Results are returned in the
+/// same order as the input, with failed items, including those asking for
+/// streaming, a `callback_url` or admin-only options without the admin
+/// token, reported as error objects rather than failing the whole batch.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `requests` - The parsed batch of chat requests
+///
+/// # Returns
+///
+/// * `Result<Json<Vec<BatchItem>>>` - One result per request, in order, or an error
+pub async fn handle_batch(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    ApiJson(requests): ApiJson<Vec<ApiRequest>>,
+) -> Result<Json<Vec<BatchItem>>> {
+    if requests.is_empty() {
+        return Err(ApiError::BadRequest {
+            message: "Batch must contain at least one request".to_string(),
+        });
+    }
+    if requests.len() > state.config.batch.max_items {
+        return Err(ApiError::BadRequest {
+            message: format!(
+                "Batch has {} requests, more than the limit of {}",
+                requests.len(),
+                state.config.batch.max_items
+            ),
+        });
+    }
+
+    // Items that cannot run in a batch fail on their own
+    let requests: Vec<_> = requests
+        .into_iter()
+        .map(|mut request| {
+            authorize_admin_options(&state.config, &headers, &request)?;
+            if request.stream == Some(true) {
+                return Err(ApiError::BadRequest {
+                    message: "stream is not supported in batch requests".to_string(),
+                });
+            }
+            if request.callback_url.is_some() {
+                return Err(ApiError::BadRequest {
+                    message: "callback_url is not supported in batch requests".to_string(),
+                });
+            }
+            gate_base_url_overrides(&state.config, &headers, &mut request);
+            Ok(request)
+        })
+        .collect();
+
+    // Initialize clients shared by every item in the batch
+    let (deepseek_client, anthropic_client) = build_clients(&state, &headers)?;
+
+    let concurrency = state.config.batch.max_concurrency.max(1);
+    let state = &state;
+    let deepseek_client = deepseek_client.as_ref();
+    let anthropic_client = anthropic_client.as_ref();
+    let results = futures::stream::iter(requests)
+        .map(|request| async move {
+            let request = request?;
+            let response = run_pipeline(state, deepseek_client, anthropic_client, &request).await?;
+            log_chat_response(state, &request, &response);
+            Ok::<_, ApiError>(response)
+        })
+        .buffered(concurrency)
+        .map(|result| match result {
+            Ok(response) => BatchItem::Success(Box::new(response)),
+            Err(e) => BatchItem::Error(e.to_error_response()),
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(Json(results))
+}
+
+/// Records a completed non-streaming chat request in the request log.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the request log
+/// * `request` - The chat request
+/// * `response` - Its response
+fn log_chat_response(state: &AppState, request: &ApiRequest, response: &ApiResponse) {
+    if !state.request_log.is_enabled() {
+        return;
+    }
+    state.request_log.record(&RequestLogEntry {
+        timestamp: Utc::now(),
+        request_id: response.id.clone(),
+        endpoint: "chat",
+        stream: false,
+        models: response.finish.models.clone(),
+        stop_reason: response.finish.stop_reason.clone(),
+        usage: Some(response.combined_usage.clone()),
+        tags: request.tags.clone(),
+        messages: state.request_log.includes_content().then(|| request.messages.clone()),
+    });
+}
+
+/// Runs a single request through the DeepSeek and Anthropic pipeline.
+///
+/// Validates the request, obtains reasoning from DeepSeek, feeds it to
+/// Anthropic for the final answer and assembles the combined response
+/// with usage, cost and timing information. In parallel pipeline mode both
+/// providers are called concurrently and Anthropic never sees the reasoning.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration, moderation and cost counters
+/// * `deepseek_client` - Client used for the reasoning stage
+/// * `anthropic_client` - Client used for the answer stage
+/// * `request` - The chat request to process
+///
+/// # Returns
+///
+/// * `Result<ApiResponse>` - The combined API response or an error
+pub(crate) async fn run_pipeline(
+    state: &AppState,
+    deepseek_client: &dyn ReasoningProvider,
+    anthropic_client: &dyn AnswerProvider,
+    request: &ApiRequest,
+) -> Result<ApiResponse> {
+    let config = &state.config;
+    let request_start = Instant::now();
+
+    validate_request(config, request, true)?;
+    state.moderator.check(&state.http, &request.messages).await?;
+
+    // Apply server-side limits and defaults
+    let mut request = request.clone();
+    let mut adjustments = adjust_request(config, deepseek_client, Some(anthropic_client), &mut request);
+    let request = &request;
+    let validator = request.response_schema.as_ref().map(schema::compile).transpose()?;
+
+    // Get messages with system prompt, trimmed to the history limit
+    let PreparedMessages { deepseek: messages, anthropic: anthropic_history, history_truncated } =
+        prepare_messages(state, request, &mut adjustments);
+
+    let system_prompt = anthropic_system_prompt(config, request);
+
+    // Provider request bodies, for `debug_echo`
+    let mut debug = request.debug_echo.then(|| DebugEcho {
+        request: request.redacted(),
+        deepseek_request: deepseek_client.request_body(&messages, &request.deepseek_config),
+        anthropic_request: None,
+    });
+
+    // Call both providers, in sequence or concurrently
+    let mut summary_response = None;
+    let ((deepseek_response, deepseek_cached), deepseek_ms, mut answers, anthropic_ms) = match request.pipeline_mode {
+        PipelineMode::Sequential => {
+            let deepseek_start = Instant::now();
+            let deepseek_response = cached_deepseek_chat(state, request, deepseek_client, messages).await?;
+            let deepseek_ms = deepseek_start.elapsed().as_millis() as u64;
+
+            // Add the reasoning, condensed if configured, to the messages for Anthropic
+            let (handoff_reasoning, summary) =
+                summarize_reasoning(config, anthropic_client, request, reasoning_from(&deepseek_response.0)?).await;
+            summary_response = summary;
+            let mut anthropic_messages = anthropic_history;
+            reasoning::inject(
+                &config.reasoning,
+                request.reasoning_injection,
+                &mut anthropic_messages,
+                &handoff_reasoning,
+            );
+            if let Some(prefill) = &request.assistant_prefill {
+                reasoning::prefill(&mut anthropic_messages, prefill);
+            }
+            if let Some(debug) = &mut debug {
+                debug.anthropic_request = anthropic_client.request_body(
+                    &anthropic_messages,
+                    system_prompt.as_deref(),
+                    &request.anthropic_config,
+                );
+            }
+
+            let anthropic_start = Instant::now();
+            let anthropic_response = answer_candidates(
+                request.n.unwrap_or(1),
+                config,
+                anthropic_client,
+                anthropic_messages,
+                system_prompt,
+                &request.anthropic_config,
+                validator.as_ref(),
+                request.assistant_prefill.as_deref(),
+            ).await;
+            let anthropic_ms = anthropic_start.elapsed().as_millis() as u64;
+            let deepseek_spent = (!deepseek_response.1).then_some(&deepseek_response.0);
+            let anthropic_response =
+                finished_candidates(state, request, deepseek_spent, summary_response.as_ref(), anthropic_response)?;
+
+            (deepseek_response, deepseek_ms, anthropic_response, anthropic_ms)
+        }
+        PipelineMode::Parallel => {
+            let mut anthropic_messages = anthropic_history;
+            if let Some(prefill) = &request.assistant_prefill {
+                reasoning::prefill(&mut anthropic_messages, prefill);
+            }
+            if let Some(debug) = &mut debug {
+                debug.anthropic_request = anthropic_client.request_body(
+                    &anthropic_messages,
+                    system_prompt.as_deref(),
+                    &request.anthropic_config,
+                );
+            }
+
+            let start = Instant::now();
+            let ((deepseek_response, deepseek_ms), (anthropic_response, anthropic_ms)) = tokio::join!(
+                async {
+                    let response = cached_deepseek_chat(state, request, deepseek_client, messages).await;
+                    (response, start.elapsed().as_millis() as u64)
+                },
+                async {
+                    let response = answer_candidates(
+                        request.n.unwrap_or(1),
+                        config,
+                        anthropic_client,
+                        anthropic_messages,
+                        system_prompt,
+                        &request.anthropic_config,
+                        validator.as_ref(),
+                        request.assistant_prefill.as_deref(),
+                    ).await;
+                    (response, start.elapsed().as_millis() as u64)
+                },
+            );
+
+            let deepseek_response = match deepseek_response {
+                Ok(response) => response,
+                Err(e) => {
+                    // The answers that finished are billed even though the reasoning failed
+                    let finished: Vec<_> = anthropic_response
+                        .iter()
+                        .filter_map(|result| result.as_ref().ok().map(|(response, _, _)| response))
+                        .collect();
+                    if !finished.is_empty() {
+                        record_failed_spend(state, request, None, &finished);
+                    }
+                    return Err(e);
+                }
+            };
+            let deepseek_spent = (!deepseek_response.1).then_some(&deepseek_response.0);
+            let anthropic_response = finished_candidates(state, request, deepseek_spent, None, anthropic_response)?;
+            (deepseek_response, deepseek_ms, anthropic_response, anthropic_ms)
+        }
+    };
+
+    // The first answer is the response's own; the rest are only candidates
+    let (anthropic_response, continuations, schema_valid) = answers.remove(0);
+
+    // Store response metadata
+    let deepseek_status: u16 = 200;
+    let deepseek_headers = HashMap::new(); // Headers not available when using high-level chat method
+
+    // Format the reasoning for the client
+    let display = DisplayFormatter::new(&config.reasoning);
+    let thinking_content = display.format(reasoning_from(&deepseek_response)?);
+
+    // Store response metadata
+    let anthropic_status: u16 = 200;
+    let anthropic_headers = HashMap::new(); // Headers not available when using high-level chat method
+
+    // Calculate usage costs
+    let ((mut deepseek_usage, deepseek_cost, anthropic_cost, candidate_costs), spent) = price_request(config, request, |pricing| {
+        let (deepseek_usage, deepseek_cost) =
+            deepseek_usage_with_cost(Some(&deepseek_response.usage), &deepseek_response.model, pricing);
+        let price = |response: &anthropic::AnthropicResponse| calculate_anthropic_cost(
+            &response.model,
+            response.usage.input_tokens,
+            response.usage.output_tokens,
+            response.usage.cache_creation_input_tokens,
+            response.usage.cache_read_input_tokens,
+            pricing,
+        );
+        let candidate_costs: Vec<f64> = std::iter::once(&anthropic_response)
+            .chain(answers.iter().map(|(response, _, _)| response))
+            .map(price)
+            .collect();
+        // The reasoning summary call is billed with the answers
+        let anthropic_cost = candidate_costs.iter().sum::<f64>() + summary_response.as_ref().map_or(0.0, price);
+        ((deepseek_usage, deepseek_cost, anthropic_cost, candidate_costs), deepseek_cost + anthropic_cost)
+    });
+    deepseek_usage.cached = deepseek_cached;
+    state.costs.record(spent, &request.tags);
+
+    let mut anthropic_usage = anthropic_response.usage.clone();
+    for response in answers.iter().map(|(response, _, _)| response).chain(summary_response.as_ref()) {
+        anthropic_usage.add(&response.usage);
+    }
+
+    // Combine thinking content with Anthropic's response
+    let mut content = Vec::new();
+    
+    // Add thinking block first
+    content.push(ContentBlock::text(thinking_content));
+    
+    // Add Anthropic's response blocks
+    let prefill = request.assistant_prefill.as_deref();
+    content.extend(answer_blocks(config, &display, &anthropic_response, prefill));
+
+    // Which blocks are reasoning, for `split_content`
+    let is_reasoning: Vec<bool> = std::iter::once(true)
+        .chain(anthropic_response.content.iter().map(|block| block.content_type == anthropic::THINKING))
+        .collect();
+
+    // Every answer with its own usage, when more than one was requested
+    let candidates = (!answers.is_empty()).then(|| {
+        std::iter::once((&anthropic_response, schema_valid))
+            .chain(answers.iter().map(|(response, _, schema_valid)| (response, *schema_valid)))
+            .zip(&candidate_costs)
+            .enumerate()
+            .map(|(index, ((response, schema_valid), cost))| Candidate {
+                index,
+                content: answer_blocks(config, &display, response, prefill),
+                stop_reason: response.stop_reason.clone(),
+                schema_valid,
+                usage: AnthropicUsage {
+                    total_cost: format_cost(*cost),
+                    ..AnthropicUsage::from_anthropic(response.usage.clone())
+                },
+            })
+            .collect()
+    });
+
+    let finish = Finish {
+        stop_reason: anthropic_response.stop_reason.clone(),
+        reasoning_truncated: deepseek_response
+            .choices
+            .first()
+            .and_then(|choice| choice.finish_reason.as_deref())
+            .map(|reason| reason == deepseek::FINISH_REASON_LENGTH),
+        history_truncated,
+        pricing_estimated: (!deepseek_cached).then_some(deepseek_usage.pricing_estimated),
+        cached: Some(deepseek_cached),
+        models: FinishModels {
+            deepseek: Some(deepseek_response.model.clone()),
+            anthropic: Some(anthropic_response.model.clone()),
+        },
+        request_id: None,
+        continuations: (config.providers.anthropic.auto_continue > 0).then_some(continuations),
+        schema_valid,
+    };
+
+    let rate_limits = RateLimits {
+        deepseek: deepseek_response.rate_limit.clone(),
+        anthropic: anthropic_response.rate_limit.clone(),
+    };
+    if let Some(ratio) = config.server.rate_limit_warning_ratio {
+        for (provider, rate_limit) in [("DeepSeek", &rate_limits.deepseek), ("Anthropic", &rate_limits.anthropic)] {
+            if let Some(rate_limit) = rate_limit.as_ref().filter(|rate_limit| rate_limit.is_near_limit(ratio)) {
+                tracing::warn!("{} is close to its rate limit: {:?}", provider, rate_limit);
+            }
+        }
+    }
+
+    // Build response with captured headers
+    let mut response = ApiResponse {
+        id: Uuid::new_v4().to_string(),
+        created: Utc::now(),
+        content,
+        deepseek_response: request.verbose.then(|| ExternalApiResponse {
+            status: deepseek_status,
+            headers: deepseek_headers,
+            body: serde_json::to_value(&deepseek_response).unwrap_or_default(),
+        }),
+        anthropic_response: request.verbose.then(|| ExternalApiResponse {
+            status: anthropic_status,
+            headers: anthropic_headers,
+            body: serde_json::to_value(&anthropic_response).unwrap_or_default(),
+        }),
+        history_truncated,
+        truncated: false,
+        refused: anthropic_response.is_refusal(),
+        adjustments,
+        deepseek_system_fingerprint: request
+            .include_provenance
+            .then(|| deepseek_response.system_fingerprint.clone()),
+        anthropic_model: request.include_provenance.then(|| anthropic_response.model.clone()),
+        anthropic_id: request.include_provenance.then(|| anthropic_response.id.clone()),
+        seed: seed_info(request, true),
+        deepseek_request_id: deepseek_response.request_id.clone(),
+        anthropic_request_id: anthropic_response.request_id.clone(),
+        rate_limits: (rate_limits.deepseek.is_some() || rate_limits.anthropic.is_some()).then_some(rate_limits),
+        debug: debug.map(Box::new),
+        candidates,
+        reasoning_content: None,
+        answer_content: None,
+        combined_usage: CombinedUsage {
+            total_cost: format_cost(deepseek_cost + anthropic_cost),
+            deepseek_usage,
+            anthropic_usage: AnthropicUsage {
+                input_tokens: anthropic_usage.input_tokens,
+                output_tokens: anthropic_usage.output_tokens,
+                cached_write_tokens: anthropic_usage.cache_creation_input_tokens,
+                cached_read_tokens: anthropic_usage.cache_read_input_tokens,
+                total_tokens: anthropic_usage.input_tokens + anthropic_usage.output_tokens,
+                total_cost: format_cost(anthropic_cost),
+            },
+        },
+        timings: Timings {
+            deepseek_ms,
+            anthropic_ms,
+            total_ms: request_start.elapsed().as_millis() as u64,
+            ..Timings::default()
+        },
+        finish,
+    };
+    if request.content_mode == ContentMode::None {
+        response.omit_content();
+    }
+    response.enforce_limits(config.limits.max_response_bytes, config.limits.max_content_blocks);
+    if request.split_content {
+        response.split_content(&is_reasoning);
+    }
+
+    Ok(response)
+}
+
+/// Converts Claude's answer blocks for the client.
+///
+/// Claude's own thinking is formatted like DeepSeek's, reasoning tags
+/// leaked into the answer are removed when configured, and the first
+/// answer text is joined to the assistant prefill it continues.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the reasoning settings
+/// * `display` - Formatter for Claude's thinking
+/// * `response` - Claude's response
+/// * `prefill` - The request's assistant prefill
+///
+/// # Returns
+///
+/// The client-facing blocks, in order
+fn answer_blocks(
+    config: &Config,
+    display: &DisplayFormatter,
+    response: &anthropic::AnthropicResponse,
+    prefill: Option<&str>,
+) -> Vec<ContentBlock> {
+    let mut prefill = prefill.map(str::trim_end);
+    response.content.iter().cloned()
+        .map(|block| match block.content_type.as_str() {
+            anthropic::THINKING => ContentBlock::text(display.format(&block.text)),
+            "text" => {
+                let mut text = if config.reasoning.strip_leaked_reasoning {
+                    reasoning::strip_leaked_tags(&config.reasoning, &block.text)
+                } else {
+                    block.text
+                };
+                if let Some(prefill) = prefill.take() {
+                    text.insert_str(0, prefill);
+                }
+                ContentBlock {
+                    content_type: block.content_type,
+                    text,
+                    tool_call: block.tool_call,
+                }
+            }
+            _ => ContentBlock::from_anthropic(block),
+        })
+        .collect()
+}
+
+/// Handler for streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// streaming their responses as Server-Sent Events, or as JSON lines for
+/// `stream_format: ndjson`. Each SSE event carries a sequence id, and the
+/// request id is returned in the `X-Request-Id` header so an interrupted
+/// client can resume via `handle_stream_resume`.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - A stream of events or an error
+///
+/// # Errors
+///
+/// Returns any error from `start_chat_stream`
+pub(crate) async fn chat_stream(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    let retry_ms = state.config.server.sse_retry_ms;
+    let format = request.stream_format;
+    let stream = start_chat_stream(state, headers, request).await?;
+    Ok((
+        [(REQUEST_ID_HEADER, stream.request_id)],
+        stream_body(format, retry_ms, stream.events),
+    ).into_response())
+}
+
+/// A streaming chat request running in a background task.
+pub(crate) struct EventStream {
+    pub request_id: String,
+    /// Events in the order they are sent, independent of the transport
+    pub events: tokio::sync::mpsc::Receiver<BufferedEvent>,
+    /// The task producing the events; aborting it cancels the upstream calls
+    pub task: tokio::task::JoinHandle<()>,
+}
+
+/// Validates a streaming chat request and starts it in the background.
+///
+/// Shared by the SSE and WebSocket transports. The stream holds one of
+/// the caller's `limits.max_streams_per_client` slots until its task
+/// finishes.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<EventStream>` - The running stream
+///
+/// # Errors
+///
+/// Returns `ApiError::TooManyRequests` if the caller already has the
+/// maximum number of streams open, or any error from validating the
+/// request or its API tokens
+pub(crate) async fn start_chat_stream(
+    state: Arc<AppState>,
+    headers: axum::http::HeaderMap,
+    mut request: ApiRequest,
+) -> Result<EventStream> {
+    let request_start = Instant::now();
+
+    validate_request(&state.config, &request, true)?;
+    if request.pipeline_mode == PipelineMode::Parallel {
+        return Err(ApiError::BadRequest {
+            message: "pipeline_mode 'parallel' is not supported for streaming requests".to_string(),
+        });
+    }
+    if request.debug_echo {
+        return Err(ApiError::BadRequest {
+            message: "debug_echo is not supported for streaming requests".to_string(),
+        });
+    }
+    if request.n.is_some_and(|n| n > 1) {
+        return Err(ApiError::BadRequest {
+            message: "n above 1 is not supported for streaming requests".to_string(),
+        });
+    }
+    // Streamed answers cannot be retried, only flagged
+    let validator = request.response_schema.as_ref().map(schema::compile).transpose()?;
+    state.moderator.check(&state.http, &request.messages).await?;
+
+    // Initialize clients
+    let (deepseek_client, anthropic_client) = build_clients(&state, &headers)?;
+
+    // Token presence was checked when the clients were built; mock mode needs none
+    let (deepseek_token, anthropic_token) = extract_api_tokens(&headers).unwrap_or_default();
+    let stream_permit = state.streams.acquire(&[&deepseek_token, &anthropic_token])?;
+
+    // Apply server-side limits and defaults
+    let mut adjustments = adjust_request(&state.config, deepseek_client.as_ref(), Some(anthropic_client.as_ref()), &mut request);
+
+    // Get messages with system prompt, trimmed to the history limit
+    let PreparedMessages { deepseek: messages, anthropic: anthropic_history, history_truncated } =
+        prepare_messages(&state, &request, &mut adjustments);
+    let seed = seed_info(&request, true);
+
+    // Reuse cached reasoning instead of calling DeepSeek when possible
+    let cache_key = state.reasoning_cache.key(&messages, &request.deepseek_config, &[deepseek_client.api_token()]);
+    let min_chars = min_reasoning_chars(&state.config, &request);
+    let cached_reasoning = cache_key
+        .and_then(|key| state.reasoning_cache.get(key))
+        .filter(|cached| is_long_enough(&cached.reasoning, min_chars));
+
+    // Create channel for stream events
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+    let request_id = Uuid::new_v4().to_string();
+    let mut emitter = StreamEmitter {
+        tx,
+        replay: state.replay.create(&request_id),
+        request_id: request_id.clone(),
+        next_id: 0,
+        log: stream_log_entry(&state, &request, &request_id, "chat"),
+        omit_content: request.content_mode == ContentMode::None,
+        granularity: request.stream_granularity,
+        pending: None,
+        last_flush: Instant::now(),
+        ttft: FirstTokenTimer::new(request_start, Some(state.ttft.clone())),
+    };
+
+    // Spawn task to handle streaming
+    let config = state.config.clone();
+    let costs = state.costs.clone();
+    let request_clone = request.clone();
+    let stream_request_id = request_id.clone();
+    let task_guard = state.stream_tasks.track();
+    let task = tokio::spawn(async move {
+        // Held until the stream ends, however the task exits
+        let _stream_permit = stream_permit;
+        let _task_guard = task_guard;
+        let stream_start = Instant::now();
+
+        // Outcome summary, completed as the stream runs
+        let mut finish = Finish {
+            history_truncated,
+            request_id: Some(stream_request_id),
+            ..Finish::default()
+        };
+        // Usage so far, kept outside `run` so a timed-out stream is still billed
+        let mut spend = StreamSpend::default();
+
+        let run = async {
+            let deepseek_start = Instant::now();
+
+            // Open the DeepSeek stream up front so its fingerprint can go in the start event
+            let mut deepseek_stream = match request_clone.stream_mode {
+                StreamMode::Full if cached_reasoning.is_none() => Some(
+                    deepseek_client
+                        .chat_stream(messages.clone(), &request_clone.deepseek_config)
+                        .peekable(),
+                ),
+                StreamMode::Full | StreamMode::AnswerOnly => None,
+            };
+            let deepseek_system_fingerprint = match (deepseek_stream.as_mut(), &cached_reasoning) {
+                _ if !request_clone.include_provenance => None,
+                (_, Some(cached)) => Some(cached.system_fingerprint.clone()),
+                (Some(stream), None) => Pin::new(stream)
+                    .peek()
+                    .await
+                    .and_then(|chunk| chunk.as_ref().ok())
+                    .map(|chunk| chunk.system_fingerprint.clone()),
+                (None, None) => None,
+            };
+
+            // Start event
+            emitter.emit(StreamEvent::Start {
+                created: Utc::now(),
+                history_truncated,
+                adjustments,
+                deepseek_system_fingerprint,
+                seed,
+                timings: Timings {
+                    total_ms: request_start.elapsed().as_millis() as u64,
+                    ..Timings::default()
+                },
+            }).await;
+
+            // Send initial thinking tag
+            let display = DisplayFormatter::new(&config.reasoning);
+            let open_tag = display.open();
+            if !open_tag.is_empty() {
+                emitter.emit(StreamEvent::Content {
+                    content: vec![ContentBlock::text(open_tag)],
+                }).await;
+            }
+
+            // Stream from DeepSeek, fetch the reasoning in one call, or replay it from the cache
+            let streamed = match (cached_reasoning, deepseek_stream) {
+                (Some(cached), _) => {
+                    emitter.emit_token(StreamEvent::Content {
+                        content: vec![ContentBlock::text(display.delta(&cached.reasoning).into_owned())],
+                    }).await;
+                    Some(StreamedReasoning {
+                        reasoning: cached.reasoning,
+                        usage: None,
+                        model: cached.model,
+                        system_fingerprint: cached.system_fingerprint,
+                        finish_reason: None,
+                        cached: true,
+                    })
+                }
+                (None, Some(stream)) => stream_reasoning(&mut emitter, stream, &display).await,
+                (None, None) => {
+                    fetch_reasoning(&mut emitter, deepseek_client.as_ref(), messages.clone(), &request_clone.deepseek_config, &display).await
+                }
+            };
+            let Some(streamed) = streamed else {
+                return;
+            };
+            // The reasoning was already sent, so it cannot be retried
+            if !is_long_enough(&streamed.reasoning, min_chars) {
+                let e = short_reasoning_error(&streamed.reasoning, min_chars.unwrap_or_default());
+                let (status, _) = e.to_status_and_response();
+                emitter.emit(StreamEvent::Error {
+                    message: e.to_string(),
+                    code: status.as_u16(),
+                }).await;
+                return;
+            }
+            if let (Some(key), false) = (cache_key, streamed.cached) {
+                state.reasoning_cache.insert(key, CachedReasoning {
+                    reasoning: streamed.reasoning.clone(),
+                    model: streamed.model.clone(),
+                    system_fingerprint: streamed.system_fingerprint,
+                });
+            }
+            let StreamedReasoning {
+                reasoning: complete_reasoning,
+                usage: deepseek_usage,
+                model: deepseek_model,
+                finish_reason: deepseek_finish_reason,
+                cached: deepseek_cached,
+                ..
+            } = streamed;
+            spend.deepseek_usage = deepseek_usage;
+            spend.deepseek_ms = deepseek_start.elapsed().as_millis() as u64;
+
+            // Outcome summary, completed as the answer streams in
+            finish.reasoning_truncated = deepseek_finish_reason
+                .map(|reason| reason == deepseek::FINISH_REASON_LENGTH);
+            finish.cached = Some(deepseek_cached);
+            finish.models.deepseek = (!deepseek_model.is_empty()).then_some(deepseek_model);
+
+            // Send closing thinking tag
+            let close_tag = display.close();
+            if !close_tag.is_empty() {
+                emitter.emit(StreamEvent::Content {
+                    content: vec![ContentBlock::text(close_tag)],
+                }).await;
+            }
+
+            // Signal the switch from reasoning to answering
+            emitter.emit(StreamEvent::PhaseChange {
+                from: Phase::Reasoning,
+                to: Phase::Answering,
+            }).await;
+
+            // Add complete thinking content to messages for Anthropic
+            let mut anthropic_messages = anthropic_history;
+            reasoning::inject(
+                &config.reasoning,
+                request_clone.reasoning_injection,
+                &mut anthropic_messages,
+                &complete_reasoning,
+            );
+
+            // Claude's answer continues the prefill, so stream it first
+            if let Some(prefill) = &request_clone.assistant_prefill {
+                reasoning::prefill(&mut anthropic_messages, prefill);
+                emitter.emit(StreamEvent::Content {
+                    content: vec![ContentBlock::text(prefill.trim_end())],
+                }).await;
+            }
+
+            // Stream from Anthropic
+            spend.anthropic_start = Some(Instant::now());
+            let system_prompt = anthropic_system_prompt(&config, &request_clone);
+            let (mut anthropic_stream, discarded) = anthropic_chat_stream(
+                &config,
+                anthropic_client.as_ref(),
+                anthropic_messages.clone(),
+                system_prompt.clone(),
+                &request_clone.anthropic_config,
+            ).await;
+            spend.continued.add(&discarded);
+
+            let max_continuations = config.providers.anthropic.auto_continue;
+            let mut continuations = 0;
+            // The answer so far, prefilled into continuations and checked against the schema
+            let mut answer_text = String::new();
+
+            let mut refused = false;
+            // Text of each open content block, by index, for `emit_complete_blocks`
+            let mut open_blocks: HashMap<usize, ContentBlock> = HashMap::new();
+            // Indices of open Claude thinking blocks
+            let mut thinking_blocks: HashSet<usize> = HashSet::new();
+            // Answer text held back by `strip_leaked_reasoning`, by block index
+            let mut leaked_filters: HashMap<usize, LeakedTagFilter> = HashMap::new();
+            // Open tool_use blocks and their partial JSON input, by index
+            let mut tool_calls: HashMap<usize, (ToolCall, String)> = HashMap::new();
+            loop {
+                while let Some(chunk) = emitter.next(&mut anthropic_stream).await {
+                    match chunk {
+                        Ok(event) => match event {
+                            crate::clients::anthropic::StreamEvent::MessageStart { message } => {
+                                finish.models.anthropic = Some(message.model.clone());
+                                spend.current = Some(message.usage.clone());
+
+                                // Only send content event if there's actual content to send
+                                if !message.content.is_empty() {
+                                    emitter.emit(StreamEvent::Content {
+                                        content: message.content.into_iter()
+                                            .map(ContentBlock::from_anthropic)
+                                            .collect()
+                                    }).await;
+                                }
+                            }
+                            crate::clients::anthropic::StreamEvent::ContentBlockStart { index, content_block } => {
+                                if content_block.content_type == anthropic::REFUSAL {
+                                    refused = true;
+                                }
+                                // Claude's extended thinking is shown like the DeepSeek reasoning
+                                if content_block.content_type == anthropic::THINKING {
+                                    thinking_blocks.insert(index);
+                                    let open_tag = display.open();
+                                    if !open_tag.is_empty() {
+                                        emitter.emit(StreamEvent::Content {
+                                            content: vec![ContentBlock::text(open_tag)],
+                                        }).await;
+                                    }
+                                }
+                                // Tool calls are sent whole once their input is complete
+                                if let Some(tool_call) = content_block.tool_call {
+                                    tool_calls.insert(index, (tool_call, String::new()));
+                                    continue;
+                                }
+                                if request_clone.emit_complete_blocks {
+                                    open_blocks.insert(index, ContentBlock::from_anthropic(content_block));
+                                }
+                            }
+                            crate::clients::anthropic::StreamEvent::ContentBlockDelta { index, delta } => {
+                                if delta.delta_type == anthropic::SIGNATURE_DELTA {
+                                    continue;
+                                }
+                                if let Some((_, input)) = tool_calls.get_mut(&index) {
+                                    input.push_str(&delta.text);
+                                    continue;
+                                }
+                                if let Some(block) = open_blocks.get_mut(&index) {
+                                    block.text.push_str(&delta.text);
+                                }
+                                if (max_continuations > 0 || validator.is_some()) && !thinking_blocks.contains(&index) {
+                                    answer_text.push_str(&delta.text);
+                                }
+
+                                // Send content update
+                                let content = if thinking_blocks.contains(&index) {
+                                    ContentBlock {
+                                        content_type: "text_delta".to_string(),
+                                        text: display.delta(&delta.text).into_owned(),
+                                        tool_call: None,
+                                    }
+                                } else if config.reasoning.strip_leaked_reasoning {
+                                    let text = leaked_filters
+                                        .entry(index)
+                                        .or_insert_with(|| LeakedTagFilter::new(&config.reasoning))
+                                        .push(&delta.text);
+                                    if text.is_empty() {
+                                        continue;
+                                    }
+                                    ContentBlock {
+                                        content_type: delta.delta_type,
+                                        text,
+                                        tool_call: None,
+                                    }
+                                } else {
+                                    ContentBlock {
+                                        content_type: delta.delta_type,
+                                        text: delta.text,
+                                        tool_call: None,
+                                    }
+                                };
+                                emitter.emit_token(StreamEvent::Content {
+                                    content: vec![content],
+                                }).await;
+                            }
+                            // Record the stop reason and send final usage stats if available
+                            crate::clients::anthropic::StreamEvent::MessageDelta { delta, usage } => {
+                                if delta.stop_reason.as_deref() == Some(anthropic::REFUSAL) {
+                                    refused = true;
+                                }
+                                if delta.stop_reason.is_some() {
+                                    finish.stop_reason = delta.stop_reason;
+                                }
+                                let Some(usage) = usage else {
+                                    continue;
+                                };
+                                spend.current = Some(usage);
+                                // A cut-off answer about to be continued is priced with its continuation
+                                if continuations < max_continuations
+                                    && finish.stop_reason.as_deref() == Some(anthropic::STOP_MAX_TOKENS)
+                                {
+                                    spend.carry_over();
+                                    continue;
+                                }
+                                emitter.emit(spend.settle(&config, &request_clone, &costs, &mut finish, request_start, &emitter.ttft)).await;
+                            }
+                            crate::clients::anthropic::StreamEvent::ContentBlockStop { index } => {
+                                if let Some((mut tool_call, input)) = tool_calls.remove(&index) {
+                                    // No input deltas keeps the input from the block start
+                                    if !input.is_empty() {
+                                        tool_call.input = serde_json::from_str(&input).unwrap_or_else(|e| {
+                                            tracing::warn!("Tool call {} has invalid JSON input: {}", tool_call.id, e);
+                                            serde_json::Value::String(input)
+                                        });
+                                    }
+                                    if request_clone.emit_complete_blocks {
+                                        emitter.emit(StreamEvent::ContentBlockComplete {
+                                            index,
+                                            content: ContentBlock {
+                                                content_type: "tool_use".to_string(),
+                                                text: String::new(),
+                                                tool_call: Some(tool_call.clone()),
+                                            },
+                                        }).await;
+                                    }
+                                    emitter.emit(StreamEvent::ToolUse { index, tool_call }).await;
+                                    continue;
+                                }
+                                if thinking_blocks.remove(&index) {
+                                    let close_tag = display.close();
+                                    if !close_tag.is_empty() {
+                                        emitter.emit(StreamEvent::Content {
+                                            content: vec![ContentBlock::text(close_tag)],
+                                        }).await;
+                                    }
+                                }
+                                if let Some(held) = leaked_filters.remove(&index).map(|mut filter| filter.finish()) {
+                                    if !held.is_empty() {
+                                        emitter.emit(StreamEvent::Content {
+                                            content: vec![ContentBlock {
+                                                content_type: "text_delta".to_string(),
+                                                text: held,
+                                                tool_call: None,
+                                            }],
+                                        }).await;
+                                    }
+                                }
+                                if let Some(mut content) = open_blocks.remove(&index) {
+                                    if config.reasoning.strip_leaked_reasoning && content.content_type == "text" {
+                                        content.text = reasoning::strip_leaked_tags(&config.reasoning, &content.text);
+                                    }
+                                    emitter.emit(StreamEvent::ContentBlockComplete { index, content }).await;
+                                }
+                            }
+                            crate::clients::anthropic::StreamEvent::Ping => {
+                                tracing::trace!("Received Anthropic keep-alive ping");
+                            }
+                            _ => {} // Handle other events if needed
+                        },
+                        Err(e) => {
+                            emitter.emit(StreamEvent::Error {
+                                message: e.to_string(),
                                 code: 500,
-                            })
-                            .unwrap_or_default(),
-                        )))
-                        .await;
+                            }).await;
+                            return;
+                        }
+                    }
+                }
+
+                // Continue an answer cut off at max_tokens, prefilled with the answer so far
+                if continuations >= max_continuations
+                    || finish.stop_reason.as_deref() != Some(anthropic::STOP_MAX_TOKENS)
+                {
+                    break;
+                }
+                continuations += 1;
+                tracing::debug!("Anthropic answer hit max_tokens, continuing ({}/{})", continuations, max_continuations);
+                let (stream, discarded) = anthropic_chat_stream(
+                    &config,
+                    anthropic_client.as_ref(),
+                    continuation_messages(&anthropic_messages, &answer_text),
+                    system_prompt.clone(),
+                    &request_clone.anthropic_config,
+                ).await;
+                anthropic_stream = stream;
+                spend.continued.add(&discarded);
+            }
+            if max_continuations > 0 {
+                finish.continuations = Some(continuations);
+            }
+            if let Some(validator) = &validator {
+                let prefill = request_clone.assistant_prefill.as_deref().unwrap_or_default().trim_end();
+                let errors = schema::check(validator, &format!("{}{}", prefill, answer_text));
+                if !errors.is_empty() {
+                    tracing::warn!("Streamed answer does not match the response schema: {}", errors.join("; "));
+                }
+                finish.schema_valid = Some(errors.is_empty());
+            }
+
+            // Send done event
+            emitter.emit(StreamEvent::Done { refused, finish: finish.clone() }).await;
+        };
+
+        // Stop at the client's deadline with a clean finish rather than an error
+        let completed = match request_clone.max_duration_ms {
+            Some(max_duration_ms) => tokio::time::timeout(Duration::from_millis(max_duration_ms), run).await.is_ok(),
+            None => {
+                run.await;
+                true
+            }
+        };
+        if completed {
+            // A stream that ended in an error still bills what it spent
+            if !spend.settled && spend.is_spent() {
+                spend.settle(&config, &request_clone, &costs, &mut finish, request_start, &emitter.ttft);
+            }
+        } else {
+            // Bill what was spent before the deadline, unless the final usage was already sent
+            if !spend.settled {
+                emitter.emit(spend.settle(&config, &request_clone, &costs, &mut finish, request_start, &emitter.ttft)).await;
+            }
+            finish.stop_reason = Some(STOP_CANCELLED_BY_TIMEOUT.to_string());
+            emitter.emit(StreamEvent::CancelledByTimeout {
+                elapsed_ms: stream_start.elapsed().as_millis() as u64,
+            }).await;
+            emitter.emit(StreamEvent::Done { refused: false, finish }).await;
+        }
+    });
+
+    Ok(EventStream {
+        request_id,
+        events: rx,
+        task,
+    })
+}
+
+/// Main handler for reasoning-only requests.
+///
+/// Runs only the DeepSeek stage and returns its reasoning, without
+/// calling Anthropic. Routes to the streaming or non-streaming variant
+/// based on the request configuration. Only the DeepSeek token is required.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - The reasoning response or an error
+pub async fn handle_reason(
+    state: State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    ApiJson(mut request): ApiJson<ApiRequest>,
+) -> Result<axum::response::Response> {
+    authorize_admin_options(&state.config, &headers, &request)?;
+    gate_base_url_overrides(&state.config, &headers, &mut request);
+
+    if wants_stream(&request, &headers) {
+        reason_stream(state, headers, Json(request)).await
+    } else {
+        let json_response = reason(state, headers, Json(request)).await?;
+        Ok(json_response.into_response())
+    }
+}
+
+/// Handler for non-streaming reasoning-only requests.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Json<ReasoningResponse>>` - The reasoning and DeepSeek usage or an error
+pub(crate) async fn reason(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(mut request): Json<ApiRequest>,
+) -> Result<Json<ReasoningResponse>> {
+    let request_start = Instant::now();
+
+    validate_request(&state.config, &request, false)?;
+    state.moderator.check(&state.http, &request.messages).await?;
+
+    let deepseek_client = build_deepseek_client(&state, &headers)?;
+    let mut adjustments = adjust_request(&state.config, deepseek_client.as_ref(), None, &mut request);
+    let PreparedMessages { deepseek: messages, history_truncated, .. } =
+        prepare_messages(&state, &request, &mut adjustments);
+
+    let deepseek_response = deepseek_client.chat(messages, &request.deepseek_config).await?;
+    let deepseek_ms = request_start.elapsed().as_millis() as u64;
+
+    let reasoning_content = reasoning_from(&deepseek_response)?.to_string();
+
+    let ((deepseek_usage, deepseek_cost), spent) = price_request(&state.config, &request, |pricing| {
+        let priced = deepseek_usage_with_cost(Some(&deepseek_response.usage), &deepseek_response.model, pricing);
+        let cost = priced.1;
+        (priced, cost)
+    });
+    state.costs.record(spent, &request.tags);
+    if state.request_log.is_enabled() {
+        state.request_log.record(&RequestLogEntry {
+            timestamp: Utc::now(),
+            request_id: Uuid::new_v4().to_string(),
+            endpoint: "reason",
+            stream: false,
+            models: FinishModels {
+                deepseek: Some(deepseek_response.model.clone()),
+                anthropic: None,
+            },
+            stop_reason: None,
+            usage: Some(CombinedUsage {
+                total_cost: format_cost(deepseek_cost),
+                deepseek_usage: deepseek_usage.clone(),
+                anthropic_usage: AnthropicUsage::default(),
+            }),
+            tags: request.tags.clone(),
+            messages: state.request_log.includes_content().then(|| request.messages.clone()),
+        });
+    }
+
+    let include_content = request.content_mode == ContentMode::Full;
+    Ok(Json(ReasoningResponse {
+        created: Utc::now(),
+        content: if include_content { vec![ContentBlock::text(reasoning_content)] } else { Vec::new() },
+        deepseek_response: (request.verbose && include_content).then(|| ExternalApiResponse {
+            status: 200,
+            headers: HashMap::new(),
+            body: serde_json::to_value(&deepseek_response).unwrap_or_default(),
+        }),
+        history_truncated,
+        adjustments,
+        deepseek_request_id: deepseek_response.request_id.clone(),
+        deepseek_usage,
+        timings: Timings {
+            deepseek_ms,
+            anthropic_ms: 0,
+            total_ms: request_start.elapsed().as_millis() as u64,
+            ..Timings::default()
+        },
+    }))
+}
+
+/// Handler for streaming reasoning-only requests.
+///
+/// Streams the DeepSeek reasoning as content deltas followed by a usage
+/// event whose Anthropic usage is zero.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - A stream of Server-Sent Events or an error
+///
+/// # Errors
+///
+/// Returns `ApiError::TooManyRequests` if the caller already has the
+/// maximum number of streams open, or any error from validating the
+/// request or its API token
+pub(crate) async fn reason_stream(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(mut request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    let request_start = Instant::now();
+
+    validate_request(&state.config, &request, false)?;
+    state.moderator.check(&state.http, &request.messages).await?;
+
+    let deepseek_client = build_deepseek_client(&state, &headers)?;
+
+    // Token presence was checked when the client was built; mock mode needs none
+    let deepseek_token = extract_api_token(&headers, "X-DeepSeek-API-Token", "DeepSeek").unwrap_or_default();
+    let stream_permit = state.streams.acquire(&[&deepseek_token])?;
+
+    let mut adjustments = adjust_request(&state.config, deepseek_client.as_ref(), None, &mut request);
+    let PreparedMessages { deepseek: messages, history_truncated, .. } =
+        prepare_messages(&state, &request, &mut adjustments);
+    let seed = seed_info(&request, false);
+    let format = request.stream_format;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+    let request_id = Uuid::new_v4().to_string();
+    let mut emitter = StreamEmitter {
+        tx,
+        replay: state.replay.create(&request_id),
+        request_id: request_id.clone(),
+        next_id: 0,
+        log: stream_log_entry(&state, &request, &request_id, "reason"),
+        omit_content: request.content_mode == ContentMode::None,
+        granularity: request.stream_granularity,
+        pending: None,
+        last_flush: Instant::now(),
+        ttft: FirstTokenTimer::new(request_start, None),
+    };
+
+    let config = state.config.clone();
+    let costs = state.costs.clone();
+    let stream_request_id = request_id.clone();
+    let task_guard = state.stream_tasks.track();
+    tokio::spawn(async move {
+        // Held until the stream ends, however the task exits
+        let _stream_permit = stream_permit;
+        let _task_guard = task_guard;
+        emitter.emit(StreamEvent::Start {
+            created: Utc::now(),
+            history_truncated,
+            adjustments,
+            deepseek_system_fingerprint: None,
+            seed,
+            timings: Timings {
+                total_ms: request_start.elapsed().as_millis() as u64,
+                ..Timings::default()
+            },
+        }).await;
+
+        let Some(StreamedReasoning { usage, model, finish_reason, .. }) =
+            stream_reasoning(
+                &mut emitter,
+                deepseek_client.chat_stream(messages, &request.deepseek_config),
+                &DisplayFormatter::raw(),
+            ).await
+        else {
+            return;
+        };
+        let deepseek_ms = request_start.elapsed().as_millis() as u64;
+
+        let ((deepseek_usage, deepseek_cost), spent) = price_request(&config, &request, |pricing| {
+            let priced = deepseek_usage_with_cost(usage.as_ref(), &model, pricing);
+            let cost = priced.1;
+            (priced, cost)
+        });
+        costs.record(spent, &request.tags);
+        let finish = Finish {
+            reasoning_truncated: finish_reason.map(|reason| reason == deepseek::FINISH_REASON_LENGTH),
+            history_truncated,
+            pricing_estimated: Some(deepseek_usage.pricing_estimated),
+            models: FinishModels {
+                deepseek: (!model.is_empty()).then_some(model),
+                anthropic: None,
+            },
+            request_id: Some(stream_request_id),
+            ..Finish::default()
+        };
+        emitter.emit(StreamEvent::Usage {
+            usage: CombinedUsage {
+                total_cost: format_cost(deepseek_cost),
+                deepseek_usage,
+                anthropic_usage: AnthropicUsage::default(),
+            },
+            timings: Timings {
+                deepseek_ms,
+                anthropic_ms: 0,
+                total_ms: request_start.elapsed().as_millis() as u64,
+                reasoning_ttft_ms: emitter.ttft.reasoning_ms(),
+                answer_ttft_ms: None,
+            },
+        }).await;
+
+        emitter.emit(StreamEvent::Done { refused: false, finish }).await;
+    });
+
+    Ok((
+        [(REQUEST_ID_HEADER, request_id)],
+        stream_body(format, state.config.server.sse_retry_ms, rx),
+    ).into_response())
+}
+
+/// Handler for resuming an interrupted stream.
+///
+/// Replays the events a client missed, based on the sequence id in the
+/// `Last-Event-ID` header, and then follows the stream live if it is
+/// still being generated. Without the header the whole stream is replayed.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the replay buffers
+/// * `request_id` - The id returned in the original stream's `X-Request-Id` header
+/// * `headers` - HTTP request headers
+///
+/// # Returns
+///
+/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
+///
+/// # Errors
+///
+/// Returns `ApiError::NotFound` if the stream is unknown or has expired
+pub async fn handle_stream_resume(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<SseResponse> {
+    let buffer = state.replay.get(&request_id).ok_or_else(|| ApiError::NotFound {
+        message: format!("Stream {} not found or expired", request_id),
+    })?;
+
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let (missed, live) = buffer.subscribe(last_event_id);
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+    let task_guard = state.stream_tasks.track();
+    tokio::spawn(async move {
+        let _task_guard = task_guard;
+        for event in missed {
+            if tx.send(Ok(event.to_sse())).await.is_err() {
+                return;
+            }
+        }
+
+        // Follow the stream until it finishes; a lagging client can simply reconnect
+        if let Some(mut live) = live {
+            while let Ok(event) = live.recv().await {
+                if tx.send(Ok(event.to_sse())).await.is_err() {
                     return;
                 }
             }
         }
+    });
+
+    Ok(SseResponse::new(ReceiverStream::new(rx)))
+}
+
+/// Checks the admin bearer token on a request.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the admin token
+/// * `headers` - HTTP request headers
+///
+/// # Errors
+///
+/// Returns `ApiError::NotFound` if no admin token is configured, or
+/// `ApiError::Unauthorized` if the request does not carry it
+fn authorize_admin(config: &Config, headers: &axum::http::HeaderMap) -> Result<()> {
+    let Some(admin_token) = config.server.admin_token.as_deref() else {
+        return Err(ApiError::NotFound {
+            message: "Admin endpoints are disabled".to_string(),
+        });
+    };
+
+    let provided = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(admin_token) {
+        return Err(ApiError::Unauthorized {
+            message: "Invalid or missing admin token".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Drops per-request provider base URLs the caller may not use.
+///
+/// A `base_url` in `deepseek_config` or `anthropic_config` sends the
+/// caller's API tokens and prompt to another host, so it is only honored
+/// for requests carrying the admin token; for any other request it is
+/// ignored.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the admin token
+/// * `headers` - HTTP request headers
+/// * `request` - The request, whose unauthorized overrides are removed
+fn gate_base_url_overrides(
+    config: &Config,
+    headers: &axum::http::HeaderMap,
+    request: &mut ApiRequest,
+) {
+    let overrides = [&mut request.deepseek_config.base_url, &mut request.anthropic_config.base_url];
+    if overrides.iter().all(|base_url| base_url.is_none()) || authorize_admin(config, headers).is_ok() {
+        return;
+    }
+    for base_url in overrides {
+        if let Some(ignored) = base_url.take() {
+            tracing::warn!("Ignoring base_url override {} from a request without the admin token", ignored);
+        }
+    }
+}
+
+/// Checks that a request may use the options reserved for operators.
+///
+/// A `pricing_override` changes the reported costs and `debug_echo`
+/// reveals the server's configuration, so both require the admin token.
+///
+/// # Arguments
+///
+/// * `config` - Configuration containing the admin token
+/// * `headers` - HTTP request headers
+/// * `request` - The request, possibly carrying a pricing override or `debug_echo`
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if no admin token is configured, or
+/// `ApiError::Unauthorized` if the request does not carry it
+fn authorize_admin_options(
+    config: &Config,
+    headers: &axum::http::HeaderMap,
+    request: &ApiRequest,
+) -> Result<()> {
+    let option = if request.pricing_override.is_some() {
+        "pricing_override"
+    } else if request.debug_echo {
+        "debug_echo"
+    } else {
+        return Ok(());
+    };
+    if config.server.admin_token.is_none() {
+        return Err(ApiError::BadRequest {
+            message: format!("{} requires server.admin_token to be configured", option),
+        });
+    }
+
+    authorize_admin(config, headers)
+}
+
+/// Handler for readiness probes.
+///
+/// Reports ready once the startup warmup has finished, or immediately
+/// when warmup is disabled.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the readiness flag
+///
+/// # Returns
+///
+/// * `Result<Json<ReadyStatus>>` - The readiness status
+///
+/// # Errors
+///
+/// Returns `ApiError::ServiceUnavailable` while the warmup is still running
+pub async fn handle_ready(State(state): State<Arc<AppState>>) -> Result<Json<ReadyStatus>> {
+    if !state.ready.load(Ordering::Acquire) {
+        return Err(ApiError::ServiceUnavailable {
+            message: "Provider connections are still warming up".to_string(),
+        });
+    }
+
+    Ok(Json(ReadyStatus { status: "ready" }))
+}
+
+/// Handler for Prometheus scrapes.
+///
+/// Exposes the number of running streaming tasks as a gauge, the time to
+/// first token of chat streams as histograms, and the cost and request
+/// count of tagged requests per tag value, in the Prometheus text format.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the task gauge, TTFT histograms and cost counters
+///
+/// # Returns
+///
+/// * `impl IntoResponse` - The metrics in the Prometheus text format
+pub async fn handle_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut body = format!(
+        "# HELP deepreasoning_active_stream_tasks Streaming tasks currently running\n\
+         # TYPE deepreasoning_active_stream_tasks gauge\n\
+         deepreasoning_active_stream_tasks {}\n",
+        state.stream_tasks.active()
+    );
+    body.push_str(&state.ttft.render());
+
+    // Request tags become labels; their number is bounded by the cost counters
+    let by_tag = state.costs.snapshot().by_tag;
+    if !by_tag.is_empty() {
+        body.push_str(
+            "# HELP deepreasoning_tagged_cost_dollars Cost of requests by tag since the last reset\n\
+             # TYPE deepreasoning_tagged_cost_dollars counter\n",
+        );
+        for tag in &by_tag {
+            body.push_str(&format!(
+                "deepreasoning_tagged_cost_dollars{{tag=\"{}\",value=\"{}\"}} {}\n",
+                tag.tag,
+                escape_label(&tag.value),
+                tag.total_cost
+            ));
+        }
+        body.push_str(
+            "# HELP deepreasoning_tagged_requests Completed requests by tag since the last reset\n\
+             # TYPE deepreasoning_tagged_requests counter\n",
+        );
+        for tag in &by_tag {
+            body.push_str(&format!(
+                "deepreasoning_tagged_requests{{tag=\"{}\",value=\"{}\"}} {}\n",
+                tag.tag,
+                escape_label(&tag.value),
+                tag.requests
+            ));
+        }
+    }
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Handler for reading the cumulative cost counters.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the counters
+/// * `headers` - HTTP request headers carrying the admin token
+///
+/// # Returns
+///
+/// * `Result<Json<CostSummary>>` - The cost and request count since the last reset
+///
+/// # Errors
+///
+/// Returns an error if the request is not authorized
+pub async fn handle_admin_cost(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<CostSummary>> {
+    authorize_admin(&state.config, &headers)?;
+
+    Ok(Json(cost_summary(state.costs.snapshot())))
+}
+
+/// Formats a snapshot of the cost counters for the admin endpoints.
+fn cost_summary(snapshot: CostSnapshot) -> CostSummary {
+    CostSummary {
+        total_cost: format_cost(snapshot.total_cost),
+        requests: snapshot.requests,
+        by_tag: snapshot
+            .by_tag
+            .into_iter()
+            .map(|tag| TaggedCost {
+                tag: tag.tag,
+                value: tag.value,
+                total_cost: format_cost(tag.total_cost),
+                requests: tag.requests,
+            })
+            .collect(),
+    }
+}
+
+/// Handler for resetting the cumulative cost counters.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the counters
+/// * `headers` - HTTP request headers carrying the admin token
+///
+/// # Returns
+///
+/// * `Result<Json<CostSummary>>` - The totals accumulated up to the reset
+///
+/// # Errors
+///
+/// Returns an error if the request is not authorized
+pub async fn handle_admin_cost_reset(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<CostSummary>> {
+    authorize_admin(&state.config, &headers)?;
+
+    let snapshot = state.costs.reset();
+    tracing::info!("Cost counters reset at {} over {} requests", format_cost(snapshot.total_cost), snapshot.requests);
+    Ok(Json(cost_summary(snapshot)))
+}
+
+/// Handler for token counting requests.
+///
+/// Counts the tokens in the given text with the bundled tokenizer closest
+/// to the requested provider or model. Counts are approximate and no API
+/// tokens are required.
+///
+/// # Arguments
+///
+/// * `request` - The text and provider/model hints
+///
+/// # Returns
+///
+/// * `Json<TokenizeResponse>` - The approximate token count
+pub async fn handle_tokenize(ApiJson(request): ApiJson<TokenizeRequest>) -> Json<TokenizeResponse> {
+    let provider = tokenizer::resolve_provider(request.provider, request.model.as_deref());
+
+    Json(TokenizeResponse {
+        tokens: tokenizer::count_tokens(provider, &request.text),
+        provider,
+        tokenizer: tokenizer::encoding_name(provider),
+    })
+}
+
+/// Usage a chat stream has spent so far.
+///
+/// Lives outside the stream's main future so the spend can still be
+/// priced and recorded when `max_duration_ms` drops that future.
+#[derive(Default)]
+struct StreamSpend {
+    deepseek_usage: Option<crate::clients::deepseek::Usage>,
+    deepseek_ms: u64,
+    anthropic_start: Option<Instant>,
+    /// Usage of answers cut off at `max_tokens` and continued
+    continued: anthropic::Usage,
+    /// Latest usage reported for the Anthropic call in flight
+    current: Option<anthropic::Usage>,
+    /// Whether the spend was priced and recorded
+    settled: bool,
+}
+
+impl StreamSpend {
+    /// Returns whether any provider call has reported usage.
+    fn is_spent(&self) -> bool {
+        self.deepseek_usage.is_some() || self.current.is_some() || self.continued.input_tokens > 0
+    }
+
+    /// Moves the usage of a cut-off call into the continued total.
+    fn carry_over(&mut self) {
+        if let Some(usage) = self.current.take() {
+            self.continued.add(&usage);
+        }
+    }
+
+    /// Prices the spend, records it against the cost counters and returns
+    /// the `usage` event reporting it.
+    ///
+    /// Anthropic usage is priced by the model reported in `message_start`
+    /// and DeepSeek usage by the model DeepSeek reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration containing the pricing
+    /// * `request` - The request, for its pricing override and tags
+    /// * `costs` - Cumulative cost counters
+    /// * `finish` - Outcome summary, updated with whether pricing was estimated
+    /// * `request_start` - When the request arrived
+    /// * `ttft` - Time-to-first-token timer of the stream
+    fn settle(
+        &mut self,
+        config: &Config,
+        request: &ApiRequest,
+        costs: &CostCounters,
+        finish: &mut Finish,
+        request_start: Instant,
+        ttft: &FirstTokenTimer,
+    ) -> StreamEvent {
+        let mut usage = self.current.take().unwrap_or_default();
+        usage.add(&self.continued);
+        let anthropic_usage = AnthropicUsage::from_anthropic(usage);
+        let ((mut deepseek_usage, deepseek_cost, anthropic_cost), spent) =
+            price_request(config, request, |pricing| {
+                let anthropic_model = finish.models.anthropic.as_deref().unwrap_or_else(|| {
+                    anthropic::requested_model(&request.anthropic_config.body)
+                });
+                let anthropic_cost = calculate_anthropic_cost(
+                    anthropic_model,
+                    anthropic_usage.input_tokens,
+                    anthropic_usage.output_tokens,
+                    anthropic_usage.cached_write_tokens,
+                    anthropic_usage.cached_read_tokens,
+                    pricing,
+                );
+                let (deepseek_usage, deepseek_cost) = deepseek_usage_with_cost(
+                    self.deepseek_usage.as_ref(),
+                    finish.models.deepseek.as_deref().unwrap_or_default(),
+                    pricing,
+                );
+                ((deepseek_usage, deepseek_cost, anthropic_cost), deepseek_cost + anthropic_cost)
+            });
+        let deepseek_cached = finish.cached.unwrap_or(false);
+        deepseek_usage.cached = deepseek_cached;
+        finish.pricing_estimated = (!deepseek_cached).then_some(deepseek_usage.pricing_estimated);
+        costs.record(spent, &request.tags);
+        self.settled = true;
+
+        StreamEvent::Usage {
+            usage: CombinedUsage {
+                total_cost: format_cost(deepseek_cost + anthropic_cost),
+                deepseek_usage,
+                anthropic_usage: AnthropicUsage {
+                    total_cost: format_cost(anthropic_cost),
+                    ..anthropic_usage
+                },
+            },
+            timings: Timings {
+                deepseek_ms: self.deepseek_ms,
+                anthropic_ms: self.anthropic_start.map_or(0, |start| start.elapsed().as_millis() as u64),
+                total_ms: request_start.elapsed().as_millis() as u64,
+                reasoning_ttft_ms: ttft.reasoning_ms(),
+                answer_ttft_ms: ttft.answer_ms(),
+            },
+        }
+    }
+}
+
+/// Reasoning collected while streaming the DeepSeek stage.
+struct StreamedReasoning {
+    reasoning: String,
+    usage: Option<crate::clients::deepseek::Usage>,
+    model: String,
+    system_fingerprint: String,
+    /// Why DeepSeek stopped, if it said
+    finish_reason: Option<String>,
+    /// Whether the reasoning was reused from the reasoning cache
+    cached: bool,
+}
+
+/// Streams the DeepSeek reasoning stage to the client.
+///
+/// Emits every reasoning delta as a content event, along with periodic
+/// `reasoning_progress` events and a final one carrying the reasoning
+/// token count. Models that produce no `reasoning_content`, such as
+/// `deepseek-chat`, have their `content` deltas treated as the reasoning;
+/// for reasoner models the trailing `content` answer is ignored.
+///
+/// # Arguments
+///
+/// * `emitter` - Emitter for the client's event stream
+/// * `deepseek_stream` - The DeepSeek response stream
+/// * `display` - Formatter applied to each delta shown to the client
+///
+/// # Returns
+///
+/// * `Option<StreamedReasoning>` - The complete reasoning and usage, or `None`
+///   if the stream failed (an error event has already been emitted)
+async fn stream_reasoning(
+    emitter: &mut StreamEmitter,
+    mut deepseek_stream: impl Stream<Item = Result<deepseek::StreamResponse>> + Unpin,
+    display: &DisplayFormatter<'_>,
+) -> Option<StreamedReasoning> {
+    let mut deepseek_usage = None;
+    let mut complete_reasoning = String::new();
+    let mut model = String::new();
+    let mut system_fingerprint = String::new();
+    let mut finish_reason = None;
+    let mut reasoning_deltas: u32 = 0;
+    let mut saw_reasoning_content = false;
+    
+    while let Some(chunk) = emitter.next(&mut deepseek_stream).await {
+        match chunk {
+            Ok(response) => {
+                if model.is_empty() {
+                    model = response.model.clone();
+                    system_fingerprint = response.system_fingerprint.clone();
+                }
+                if let Some(choice) = response.choices.first() {
+                    if choice.finish_reason.is_some() {
+                        finish_reason = choice.finish_reason.clone();
+                    }
+
+                    // Handle delta reasoning_content for streaming. Chunks without
+                    // reasoning are not a stop signal: the final usage chunk arrives
+                    // after the last reasoning delta and must still be processed.
+                    // Non-reasoner models only send content, which stands in for
+                    // the reasoning.
+                    let delta = match (&choice.delta.reasoning_content, &choice.delta.content) {
+                        (Some(reasoning), _) if !reasoning.is_empty() => {
+                            saw_reasoning_content = true;
+                            Some(reasoning)
+                        }
+                        (_, Some(content)) if !saw_reasoning_content => Some(content),
+                        _ => None,
+                    };
+                    if let Some(reasoning) = delta {
+                        if !reasoning.is_empty() {
+                            // Stream the reasoning content as a delta
+                            emitter.emit_token(StreamEvent::Content {
+                                content: vec![ContentBlock {
+                                    content_type: "text_delta".to_string(),
+                                    text: display.delta(reasoning).into_owned(),
+                                    tool_call: None,
+                                }],
+                            }).await;
+                            
+                            // Accumulate complete reasoning for later use
+                            complete_reasoning.push_str(reasoning);
+
+                            // Each streamed delta carries roughly one token
+                            reasoning_deltas += 1;
+                            if reasoning_deltas.is_multiple_of(REASONING_PROGRESS_INTERVAL) {
+                                emitter.emit(StreamEvent::ReasoningProgress {
+                                    estimated_reasoning_tokens: reasoning_deltas,
+                                }).await;
+                            }
+                        }
+                    }
+                }
+                
+                // Store usage information if present
+                if let Some(usage) = response.usage {
+                    deepseek_usage = Some(usage);
+                }
+            }
+            Err(e) => {
+                emitter.emit(StreamEvent::Error {
+                    message: e.to_string(),
+                    code: 500,
+                }).await;
+                return None;
+            }
+        }
+    }
 
-        // Send closing thinking tag
-        let _ = tx
-            .send(Ok(Event::default().event("content").data(
-                serde_json::to_string(&StreamEvent::Content {
-                    content: vec![ContentBlock {
-                        content_type: "text".to_string(),
-                        text: "\n</thinking>".to_string(),
-                    }],
-                })
-                .unwrap_or_default(),
-            )))
-            .await;
+    // Report the final reasoning token count, preferring DeepSeek's own figure
+    emitter.emit(StreamEvent::ReasoningProgress {
+        estimated_reasoning_tokens: deepseek_usage
+            .as_ref()
+            .map(|usage| usage.completion_tokens_details.reasoning_tokens)
+            .unwrap_or(reasoning_deltas),
+    }).await;
 
-        // Add complete thinking content to messages for Anthropic
-        let mut anthropic_messages = messages;
-        anthropic_messages.push(Message {
-            role: Role::Assistant,
-            content: format!("<thinking>\n{}\n</thinking>", complete_reasoning),
-        });
+    Some(StreamedReasoning {
+        reasoning: complete_reasoning,
+        usage: deepseek_usage,
+        model,
+        system_fingerprint,
+        finish_reason,
+        cached: false,
+    })
+}
 
-        // Stream from Anthropic
-        let mut anthropic_stream = anthropic_client.chat_stream(
-            anthropic_messages,
-            request_clone.get_system_prompt().map(String::from),
-            &request_clone.anthropic_config,
-        );
+/// Fetches the complete DeepSeek reasoning without streaming it.
+///
+/// Emits the whole reasoning as a single content event followed by a
+/// `reasoning_progress` event with the reasoning token count.
+///
+/// # Arguments
+///
+/// * `emitter` - Emitter for the client's event stream
+/// * `deepseek_client` - Client used for the reasoning stage
+/// * `messages` - Messages to send to DeepSeek
+/// * `config` - DeepSeek request configuration
+/// * `display` - Formatter applied to the reasoning shown to the client
+///
+/// # Returns
+///
+/// * `Option<StreamedReasoning>` - The complete reasoning and usage, or `None`
+///   if the request failed (an error event has already been emitted)
+async fn fetch_reasoning(
+    emitter: &mut StreamEmitter,
+    deepseek_client: &dyn ReasoningProvider,
+    messages: Vec<Message>,
+    config: &ApiConfig,
+    display: &DisplayFormatter<'_>,
+) -> Option<StreamedReasoning> {
+    let response = deepseek_client
+        .chat(messages, config)
+        .await
+        .and_then(|response| Ok((reasoning_from(&response)?.to_string(), response)));
 
-        while let Some(chunk) = anthropic_stream.next().await {
-            match chunk {
-                Ok(event) => match event {
-                    crate::clients::anthropic::StreamEvent::MessageStart { message } => {
-                        // Only send content event if there's actual content to send
-                        if !message.content.is_empty() {
-                            let _ = tx
-                                .send(Ok(Event::default().event("content").data(
-                                    serde_json::to_string(&StreamEvent::Content { 
-                                        content: message.content.into_iter()
-                                            .map(ContentBlock::from_anthropic)
-                                            .collect()
-                                    })
-                                    .unwrap_or_default(),
-                                )))
-                                .await;
-                        }
+    let (reasoning, response) = match response {
+        Ok(response) => response,
+        Err(e) => {
+            emitter.emit(StreamEvent::Error {
+                message: e.to_string(),
+                code: 500,
+            }).await;
+            return None;
+        }
+    };
+
+    emitter.emit_token(StreamEvent::Content {
+        content: vec![ContentBlock::text(display.delta(&reasoning).into_owned())],
+    }).await;
+    emitter.emit(StreamEvent::ReasoningProgress {
+        estimated_reasoning_tokens: response.usage.completion_tokens_details.reasoning_tokens,
+    }).await;
+
+    Some(StreamedReasoning {
+        reasoning,
+        finish_reason: response.choices.first().and_then(|choice| choice.finish_reason.clone()),
+        usage: Some(response.usage),
+        model: response.model,
+        system_fingerprint: response.system_fingerprint,
+        cached: false,
+    })
+}
+
+/// Sends stream events to a client and records them for replay.
+///
+/// Assigns every event a sequence id, used as the SSE `id` field, so a
+/// reconnecting client can tell the server which events it already has.
+///
+/// With a coarser `stream_granularity`, single-block content events are
+/// held back and joined with the deltas that follow. The gathered text is
+/// sent once it ends a sentence, or once the `chunk_ms` interval has
+/// passed, and always before any other event. Upstream streams are read
+/// through [`StreamEmitter::next`] so the interval is kept even while the
+/// upstream is silent.
+struct StreamEmitter {
+    tx: tokio::sync::mpsc::Sender<BufferedEvent>,
+    replay: Option<Arc<ReplayBuffer>>,
+    /// Stamped on every event as `id`
+    request_id: String,
+    next_id: u64,
+    /// Request log entry, completed from the `usage` and `done` events
+    log: Option<(RequestLog, RequestLogEntry)>,
+    /// Drop content events, for `content_mode: none` requests
+    omit_content: bool,
+    granularity: StreamGranularity,
+    /// Content held back by `granularity`
+    pending: Option<ContentBlock>,
+    /// When content was last sent
+    last_flush: Instant,
+    /// Time to the first token of each phase
+    ttft: FirstTokenTimer,
+}
+
+impl StreamEmitter {
+    /// Emits a single event to the client.
+    ///
+    /// Send failures are ignored: the client may have disconnected, but the
+    /// stream keeps running so its events remain available for replay.
+    async fn emit(&mut self, event: StreamEvent) {
+        if self.omit_content
+            && matches!(
+                event,
+                StreamEvent::Content { .. } | StreamEvent::ContentBlockComplete { .. } | StreamEvent::ToolUse { .. }
+            )
+        {
+            return;
+        }
+
+        match event {
+            StreamEvent::Content { mut content }
+                if content.len() == 1 && self.granularity != StreamGranularity::Token =>
+            {
+                let block = content.remove(0);
+                match &mut self.pending {
+                    Some(pending) if pending.content_type == block.content_type => {
+                        pending.text.push_str(&block.text);
                     }
-                    crate::clients::anthropic::StreamEvent::ContentBlockDelta { delta, .. } => {
-                        // Send content update
-                        let _ = tx
-                            .send(Ok(Event::default().event("content").data(
-                                serde_json::to_string(&StreamEvent::Content {
-                                    content: vec![ContentBlock {
-                                        content_type: delta.delta_type,
-                                        text: delta.text,
-                                    }],
-                                })
-                                .unwrap_or_default(),
-                            )))
-                            .await;
+                    _ => {
+                        self.flush().await;
+                        self.pending = Some(block);
                     }
-                    crate::clients::anthropic::StreamEvent::MessageDelta { usage, .. } => {
-                        // Send final usage stats if available
-                        if let Some(usage) = usage {
-                            let anthropic_usage = AnthropicUsage::from_anthropic(usage);
-                            let anthropic_cost = calculate_anthropic_cost(
-                                "claude-3-5-sonnet-20241022", // Default model
-                                anthropic_usage.input_tokens,
-                                anthropic_usage.output_tokens,
-                                anthropic_usage.cached_write_tokens,
-                                anthropic_usage.cached_read_tokens,
-                                &config,
-                            );
-
-                            // Calculate DeepSeek costs if usage is available
-                            let (deepseek_usage, deepseek_cost) = if let Some(usage) = deepseek_usage.as_ref() {
-                                let cost = calculate_deepseek_cost(
-                                    usage.prompt_tokens,
-                                    usage.completion_tokens,
-                                    usage.completion_tokens_details.reasoning_tokens,
-                                    usage.prompt_tokens_details.cached_tokens,
-                                    &config,
-                                );
-                                
-                                (DeepSeekUsage {
-                                    input_tokens: usage.prompt_tokens,
-                                    output_tokens: usage.completion_tokens,
-                                    reasoning_tokens: usage.completion_tokens_details.reasoning_tokens,
-                                    cached_input_tokens: usage.prompt_tokens_details.cached_tokens,
-                                    total_tokens: usage.total_tokens,
-                                    total_cost: format_cost(cost),
-                                }, cost)
-                            } else {
-                                (DeepSeekUsage {
-                                    input_tokens: 0,
-                                    output_tokens: 0,
-                                    reasoning_tokens: 0,
-                                    cached_input_tokens: 0,
-                                    total_tokens: 0,
-                                    total_cost: "$0.00".to_string(),
-                                }, 0.0)
-                            };
-
-                            let _ = tx
-                                .send(Ok(Event::default().event("usage").data(
-                                    serde_json::to_string(&StreamEvent::Usage {
-                                        usage: CombinedUsage {
-                                            total_cost: format_cost(deepseek_cost + anthropic_cost),
-                                            deepseek_usage,
-                                            anthropic_usage: AnthropicUsage {
-                                                input_tokens: anthropic_usage.input_tokens,
-                                                output_tokens: anthropic_usage.output_tokens,
-                                                cached_write_tokens: anthropic_usage.cached_write_tokens,
-                                                cached_read_tokens: anthropic_usage.cached_read_tokens,
-                                                total_tokens: anthropic_usage.total_tokens,
-                                                total_cost: format_cost(anthropic_cost),
-                                            },
-                                        },
-                                    })
-                                    .unwrap_or_default(),
-                                )))
-                                .await;
-                        }
+                }
+                self.flush_ready().await;
+            }
+            event => {
+                self.flush().await;
+                self.send(event).await;
+            }
+        }
+    }
+
+    /// Waits for the next item of an upstream stream.
+    ///
+    /// With `chunk_ms` granularity, held-back content is sent as soon as
+    /// its interval passes, rather than when the next delta arrives.
+    async fn next<S: Stream + Unpin>(&mut self, stream: &mut S) -> Option<S::Item> {
+        loop {
+            let deadline = match (&self.pending, self.granularity) {
+                (Some(_), StreamGranularity::ChunkMs(interval_ms)) => {
+                    self.last_flush + Duration::from_millis(interval_ms)
+                }
+                _ => return stream.next().await,
+            };
+            tokio::select! {
+                item = stream.next() => return item,
+                _ = tokio::time::sleep_until(deadline.into()) => self.flush().await,
+            }
+        }
+    }
+
+    /// Emits an event carrying generated text, for time to first token.
+    async fn emit_token(&mut self, event: StreamEvent) {
+        self.ttft.token();
+        self.emit(event).await;
+    }
+
+    /// Sends the held-back content the granularity allows to go out now.
+    async fn flush_ready(&mut self) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+
+        match self.granularity {
+            StreamGranularity::Token => self.flush().await,
+            StreamGranularity::Sentence => {
+                if let Some(end) = sentence_end(&pending.text) {
+                    let rest = pending.text.split_off(end);
+                    let content_type = pending.content_type.clone();
+                    self.flush().await;
+                    if !rest.is_empty() {
+                        self.pending = Some(ContentBlock { content_type, text: rest, tool_call: None });
                     }
-                    _ => {} // Handle other events if needed
+                }
+            }
+            StreamGranularity::ChunkMs(interval_ms) => {
+                if self.last_flush.elapsed().as_millis() >= u128::from(interval_ms) {
+                    self.flush().await;
+                }
+            }
+        }
+    }
+
+    /// Sends all held-back content.
+    async fn flush(&mut self) {
+        if let Some(block) = self.pending.take() {
+            self.last_flush = Instant::now();
+            self.send(StreamEvent::Content { content: vec![block] }).await;
+        }
+    }
+
+    /// Sends an event and records it for replay.
+    ///
+    /// The request log entry is written once the `done` event is sent.
+    async fn send(&mut self, event: StreamEvent) {
+        if let Some((_, entry)) = &mut self.log {
+            match &event {
+                StreamEvent::Usage { usage, .. } => entry.usage = Some(usage.clone()),
+                StreamEvent::Done { finish, .. } => {
+                    entry.models = finish.models.clone();
+                    entry.stop_reason = finish.stop_reason.clone();
+                }
+                _ => {}
+            }
+        }
+        match &event {
+            StreamEvent::Content { .. } => self.ttft.sent(),
+            StreamEvent::PhaseChange { to, .. } => self.ttft.phase(*to),
+            _ => {}
+        }
+        let done = matches!(event, StreamEvent::Done { .. });
+
+        self.next_id += 1;
+        let buffered = BufferedEvent {
+            id: self.next_id,
+            name: event.event_name(),
+            data: serde_json::to_string(&IdentifiedEvent {
+                id: &self.request_id,
+                event: &event,
+            })
+            .unwrap_or_default(),
+        };
+
+        let _ = self.tx.send(buffered.clone()).await;
+
+        if let Some(replay) = &self.replay {
+            replay.push(buffered);
+        }
+
+        if done {
+            if let Some((request_log, mut entry)) = self.log.take() {
+                entry.timestamp = Utc::now();
+                request_log.record(&entry);
+            }
+        }
+    }
+}
+
+/// Starts the request log entry for a streaming request.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the request log
+/// * `request` - The streaming request
+/// * `request_id` - The stream's request id
+/// * `endpoint` - The endpoint serving the stream
+///
+/// # Returns
+///
+/// * `Option<(RequestLog, RequestLogEntry)>` - The log and the entry to complete, or
+///   None if the request log is disabled
+fn stream_log_entry(
+    state: &AppState,
+    request: &ApiRequest,
+    request_id: &str,
+    endpoint: &'static str,
+) -> Option<(RequestLog, RequestLogEntry)> {
+    state.request_log.is_enabled().then(|| {
+        (state.request_log.clone(), RequestLogEntry {
+            timestamp: Utc::now(),
+            request_id: request_id.to_string(),
+            endpoint,
+            stream: true,
+            models: FinishModels::default(),
+            stop_reason: None,
+            usage: None,
+            tags: request.tags.clone(),
+            messages: state.request_log.includes_content().then(|| request.messages.clone()),
+        })
+    })
+}
+
+/// Sends a stream's events as Server-Sent Events.
+///
+/// # Arguments
+///
+/// * `retry_ms` - Reconnection delay sent as an initial `retry:` field, from `server.sse_retry_ms`
+/// * `events` - The stream's events
+///
+/// # Returns
+///
+/// * The SSE response body
+fn sse_events(
+    retry_ms: Option<u64>,
+    events: tokio::sync::mpsc::Receiver<BufferedEvent>,
+) -> axum::response::sse::Sse<impl Stream<Item = SseResult>> {
+    let hint = retry_ms.map(|retry_ms| Ok(axum::response::sse::Event::default().retry(Duration::from_millis(retry_ms))));
+    let events = ReceiverStream::new(events).map(|event| Ok(event.to_sse()));
+    axum::response::sse::Sse::new(futures::stream::iter(hint).chain(events))
+}
+
+/// Sends a stream's events as newline-delimited JSON.
+///
+/// Each line is an event's JSON, exactly as carried in the SSE `data:`
+/// field; its `type` field takes the place of the SSE event name.
+///
+/// # Arguments
+///
+/// * `events` - The stream's events
+///
+/// # Returns
+///
+/// * `impl IntoResponse` - A chunked `application/x-ndjson` response
+fn ndjson_events(events: tokio::sync::mpsc::Receiver<BufferedEvent>) -> impl IntoResponse {
+    let lines = ReceiverStream::new(events).map(|event| {
+        let mut line = event.data.into_bytes();
+        line.push(b'\n');
+        Ok::<_, std::convert::Infallible>(line)
+    });
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(lines),
+    )
+}
+
+/// Sends a stream's events in the format the request asked for.
+///
+/// # Arguments
+///
+/// * `format` - The request's `stream_format`
+/// * `retry_ms` - Reconnection delay for SSE clients, from `server.sse_retry_ms`
+/// * `events` - The stream's events
+///
+/// # Returns
+///
+/// * `axum::response::Response` - The streaming response body
+fn stream_body(
+    format: StreamFormat,
+    retry_ms: Option<u64>,
+    events: tokio::sync::mpsc::Receiver<BufferedEvent>,
+) -> axum::response::Response {
+    match format {
+        StreamFormat::Sse => sse_events(retry_ms, events).into_response(),
+        StreamFormat::Ndjson => ndjson_events(events).into_response(),
+    }
+}
+
+/// Finds the end of the last complete sentence or line in streamed text.
+///
+/// # Arguments
+///
+/// * `text` - The text gathered so far
+///
+/// # Returns
+///
+/// * `Option<usize>` - The byte offset just past the whitespace ending the
+///   sentence, or None if no sentence has ended yet
+fn sentence_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (1..bytes.len())
+        .rev()
+        .find(|&i| {
+            bytes[i] == b'\n'
+                || (bytes[i].is_ascii_whitespace() && matches!(bytes[i - 1], b'.' | b'!' | b'?'))
+        })
+        .map(|i| i + 1)
+}
+
+impl Drop for StreamEmitter {
+    fn drop(&mut self) {
+        if let Some(replay) = &self.replay {
+            replay.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AnthropicPricing, DeepSeekModelPricing, DeepSeekPricing, ModelPricing};
+
+    /// Rates chosen so every expected cost below is exact.
+    fn pricing() -> PricingConfig {
+        PricingConfig {
+            deepseek: DeepSeekPricing {
+                input_cache_hit_price: 1.0,
+                input_cache_miss_price: 2.0,
+                output_price: 4.0,
+                models: HashMap::from([
+                    (
+                        "deepseek-chat".to_string(),
+                        DeepSeekModelPricing {
+                            input_cache_hit_price: 0.5,
+                            input_cache_miss_price: 1.0,
+                            output_price: 2.0,
+                        },
+                    ),
+                    (
+                        "deepseek-reasoner".to_string(),
+                        DeepSeekModelPricing {
+                            input_cache_hit_price: 0.25,
+                            input_cache_miss_price: 0.5,
+                            output_price: 8.0,
+                        },
+                    ),
+                ]),
+            },
+            anthropic: AnthropicPricing {
+                claude_3_sonnet: ModelPricing {
+                    input_price: 3.0,
+                    output_price: 15.0,
+                    cache_write_price: 4.0,
+                    cache_read_price: 0.5,
                 },
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(
-                            serde_json::to_string(&StreamEvent::Error {
-                                message: e.to_string(),
-                                code: 500,
-                            })
-                            .unwrap_or_default(),
-                        )))
-                        .await;
-                    return;
+                claude_3_haiku: ModelPricing {
+                    input_price: 1.0,
+                    output_price: 4.0,
+                    cache_write_price: 1.25,
+                    cache_read_price: 0.125,
+                },
+                claude_3_opus: ModelPricing {
+                    input_price: 15.0,
+                    output_price: 75.0,
+                    cache_write_price: 20.0,
+                    cache_read_price: 1.5,
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn deepseek_chat_bills_cache_hits_and_misses_separately() {
+        let cost = calculate_deepseek_cost("deepseek-chat", 3_000_000, 1_000_000, 0, 1_000_000, &pricing());
+        // 1M hits at 0.5, 2M misses at 1.0, 1M output at 2.0
+        assert_eq!(cost, (4.5, false));
+    }
+
+    #[test]
+    fn deepseek_reasoner_uses_its_own_rates() {
+        let cost = calculate_deepseek_cost("deepseek-reasoner", 2_000_000, 500_000, 400_000, 0, &pricing());
+        // Reasoning tokens are part of the output and not billed again
+        assert_eq!(cost, (5.0, false));
+    }
+
+    #[test]
+    fn deepseek_fully_cached_input_is_billed_at_the_hit_rate() {
+        let cost = calculate_deepseek_cost("deepseek-chat", 1_000_000, 0, 0, 1_000_000, &pricing());
+        assert_eq!(cost, (0.5, false));
+    }
+
+    #[test]
+    fn deepseek_cached_count_above_the_prompt_does_not_underflow() {
+        let cost = calculate_deepseek_cost("deepseek-chat", 500_000, 0, 0, 1_000_000, &pricing());
+        // Only the cache hits are billed, with no negative miss count
+        assert_eq!(cost, (0.5, false));
+
+        let cost = calculate_deepseek_cost("deepseek-chat", 0, 1_000_000, 0, 1_000_000, &pricing());
+        assert_eq!(cost, (2.5, false));
+    }
+
+    #[test]
+    fn deepseek_unknown_model_uses_fallback_rates_and_is_estimated() {
+        let cost = calculate_deepseek_cost("deepseek-future", 1_000_000, 250_000, 0, 500_000, &pricing());
+        // 0.5M hits at 1.0, 0.5M misses at 2.0, 0.25M output at 4.0
+        assert_eq!(cost, (2.5, true));
+    }
+
+    #[test]
+    fn anthropic_prices_each_model_family() {
+        let pricing = pricing();
+        let price = |model| calculate_anthropic_cost(model, 1_000_000, 1_000_000, 1_000_000, 1_000_000, &pricing);
+        assert_eq!(price("claude-3-5-sonnet-20241022"), 22.5);
+        assert_eq!(price("claude-3-5-haiku-20241022"), 6.375);
+        assert_eq!(price("claude-3-opus-20240229"), 111.5);
+    }
+
+    #[test]
+    fn anthropic_unknown_model_is_priced_as_sonnet() {
+        let cost = calculate_anthropic_cost("claude-2.1", 2_000_000, 100_000, 0, 0, &pricing());
+        assert_eq!(cost, 7.5);
+    }
+
+    #[test]
+    fn anthropic_cache_tokens_use_their_own_rates() {
+        let cost = calculate_anthropic_cost("claude-3-5-haiku-20241022", 0, 0, 2_000_000, 4_000_000, &pricing());
+        assert_eq!(cost, 3.0);
+    }
+
+    /// A DeepSeek response whose message has the given fields.
+    fn deepseek_response(message: serde_json::Value) -> DeepSeekResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "r1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "deepseek-reasoner",
+            "choices": [{ "index": 0, "message": message, "logprobs": null, "finish_reason": "stop" }],
+            "system_fingerprint": "fp",
+        }))
+        .unwrap()
+    }
+
+    /// Message fields that count as absent: missing, null, empty and blank.
+    fn absent_variants(field: &str) -> Vec<serde_json::Map<String, serde_json::Value>> {
+        [None, Some(serde_json::Value::Null), Some("".into()), Some(" \n\t ".into())]
+            .into_iter()
+            .map(|value| {
+                let mut message = serde_json::Map::from_iter([("role".to_string(), "assistant".into())]);
+                if let Some(value) = value {
+                    message.insert(field.to_string(), value);
+                }
+                message
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reasoning_from_prefers_reasoning_content() {
+        let response = deepseek_response(serde_json::json!({
+            "role": "assistant",
+            "reasoning_content": "thought",
+            "content": "answer",
+        }));
+        assert_eq!(reasoning_from(&response).unwrap(), "thought");
+    }
+
+    #[test]
+    fn reasoning_from_falls_back_to_content_when_reasoning_content_is_absent() {
+        for mut message in absent_variants("reasoning_content") {
+            message.insert("content".to_string(), "answer".into());
+            let response = deepseek_response(message.clone().into());
+            assert_eq!(reasoning_from(&response).unwrap(), "answer", "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn reasoning_from_ignores_absent_content() {
+        for mut message in absent_variants("content") {
+            message.insert("reasoning_content".to_string(), "thought".into());
+            let response = deepseek_response(message.clone().into());
+            assert_eq!(reasoning_from(&response).unwrap(), "thought", "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn reasoning_from_rejects_responses_with_neither_field() {
+        for reasoning in absent_variants("reasoning_content") {
+            for content in absent_variants("content") {
+                let mut message = reasoning.clone();
+                message.extend(content.into_iter().filter(|(key, _)| key == "content"));
+                let response = deepseek_response(message.clone().into());
+                match reasoning_from(&response) {
+                    Err(ApiError::DeepSeekError { type_, .. }) => assert_eq!(type_, "missing_content", "{:?}", message),
+                    other => panic!("unexpected result for {:?}: {:?}", message, other),
                 }
             }
         }
+    }
+
+    /// Serves the given responses in order, repeating the last, and counts requests.
+    async fn serve_anthropic(
+        responses: Vec<(&'static str, &'static str, String)>,
+    ) -> (AnthropicClient, Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let index = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let (status, content_type, body) = &responses[index.min(responses.len() - 1)];
+                let mut request = [0; 8192];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let client = AnthropicClient::new("token".to_string()).with_base_url(Some(format!("http://{}", addr)));
+        (client, accepted)
+    }
 
-        // Send done event
-        let _ = tx
-            .send(Ok(Event::default().event("done").data(
-                serde_json::to_string(&StreamEvent::Done)
-                    .unwrap_or_default(),
-            )))
+    fn anthropic_message(text: &str, output_tokens: u32) -> String {
+        serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": if text.is_empty() { serde_json::json!([]) } else { serde_json::json!([{ "type": "text", "text": text }]) },
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 10, "output_tokens": output_tokens },
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn anthropic_stream_http_errors_are_not_retried_as_empty() {
+        let body = r#"{"type":"error","error":{"type":"invalid_request_error","message":"bad"}}"#;
+        let (client, accepted) = serve_anthropic(vec![("400 Bad Request", "application/json", body.to_string())]).await;
+        let mut config = Config::default();
+        config.providers.anthropic.empty_content_retries = 2;
+
+        let events: Vec<_> = anthropic_chat_stream(&config, &client, Vec::new(), None, &ApiConfig::default())
+            .await
+            .0
+            .collect()
             .await;
-    });
 
-    // Convert receiver into stream
-    let stream = ReceiverStream::new(rx);
-    Ok(SseResponse::new(stream))
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            Err(ApiError::AnthropicError { message, type_, .. }) => {
+                assert_eq!(type_, "api_error");
+                assert!(message.contains("invalid_request_error"), "{}", message);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn empty_answers_are_billed_with_the_retry() {
+        let (client, accepted) = serve_anthropic(vec![
+            ("200 OK", "application/json", anthropic_message("", 1)),
+            ("200 OK", "application/json", anthropic_message("answer", 5)),
+        ])
+        .await;
+        let mut config = Config::default();
+        config.providers.anthropic.empty_content_retries = 1;
+
+        let response = anthropic_chat(&config, &client, Vec::new(), None, &ApiConfig::default()).await.unwrap();
+
+        assert_eq!(response.answer_text(), "answer");
+        assert_eq!((response.usage.input_tokens, response.usage.output_tokens), (20, 6));
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn empty_streams_are_billed_with_the_retry() {
+        let frame = |event: serde_json::Value| format!("event: {}\ndata: {}\n\n", event["type"].as_str().unwrap(), event);
+        let message_start = frame(serde_json::json!({
+            "type": "message_start",
+            "message": serde_json::from_str::<serde_json::Value>(&anthropic_message("", 0)).unwrap(),
+        }));
+        let stop = |output_tokens: u32| {
+            frame(serde_json::json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": "end_turn", "stop_sequence": null },
+                "usage": { "input_tokens": 10, "output_tokens": output_tokens },
+            })) + &frame(serde_json::json!({ "type": "message_stop" }))
+        };
+        let answer = frame(serde_json::json!({
+            "type": "content_block_start",
+            "index": 0,
+            "content_block": { "type": "text", "text": "" },
+        })) + &frame(serde_json::json!({
+            "type": "content_block_delta",
+            "index": 0,
+            "delta": { "type": "text_delta", "text": "answer" },
+        }));
+        let (client, _) = serve_anthropic(vec![
+            ("200 OK", "text/event-stream", format!("{}{}", message_start, stop(1))),
+            ("200 OK", "text/event-stream", format!("{}{}{}", message_start, answer, stop(5))),
+        ])
+        .await;
+        let mut config = Config::default();
+        config.providers.anthropic.empty_content_retries = 1;
+
+        let (stream, discarded) = anthropic_chat_stream(&config, &client, Vec::new(), None, &ApiConfig::default()).await;
+        let events: Vec<_> = stream.collect().await;
+
+        assert_eq!((discarded.input_tokens, discarded.output_tokens), (10, 1));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Ok(anthropic::StreamEvent::ContentBlockDelta { delta, .. }) if delta.text == "answer"
+        )));
+    }
+
+    /// Application state for pipeline tests, with mock-friendly defaults.
+    async fn test_state(config: Config) -> AppState {
+        AppState {
+            http: reqwest::Client::new(),
+            replay: ReplayStore::new(Duration::from_secs(60)),
+            idempotency: IdempotencyStore::new(Duration::from_secs(60)),
+            costs: Arc::new(CostCounters::default()),
+            moderator: Moderator::new(&config.moderation).unwrap(),
+            reasoning_cache: ReasoningCache::new(Duration::ZERO),
+            streams: StreamLimiter::new(None),
+            stream_tasks: TaskGauge::default(),
+            ttft: Arc::new(TtftMetrics::default()),
+            request_log: RequestLog::new(&config.request_log).await.unwrap(),
+            transforms: TransformPipeline::from_config(&config.prompt).unwrap(),
+            ready: AtomicBool::new(true),
+            config,
+        }
+    }
+
+    fn chat_request() -> ApiRequest {
+        serde_json::from_value(serde_json::json!({ "messages": [{ "role": "user", "content": "hi" }] })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejected_short_reasoning_is_billed() {
+        let mut config = Config::default();
+        config.reasoning.min_reasoning_chars = Some(100_000);
+        config.reasoning.short_reasoning_retries = 1;
+        let state = test_state(config).await;
+        let request = chat_request();
+
+        let error = cached_deepseek_chat(&state, &request, &MockDeepSeekClient, request.messages.clone())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ApiError::DeepSeekError { ref type_, .. } if type_ == "short_reasoning"), "{:?}", error);
+        let one_call = MockDeepSeekClient.chat(request.messages.clone(), &request.deepseek_config).await.unwrap();
+        let (_, one_call_cost) = deepseek_usage_with_cost(Some(&one_call.usage), &one_call.model, &state.config.pricing);
+        let snapshot = state.costs.snapshot();
+        assert_eq!(snapshot.requests, 1);
+        assert!((snapshot.total_cost - 2.0 * one_call_cost).abs() < 1e-6, "{} vs {}", snapshot.total_cost, one_call_cost);
+    }
+
+    #[tokio::test]
+    async fn finished_candidates_are_billed_when_one_fails() {
+        let state = test_state(Config::default()).await;
+        let request = chat_request();
+        let answer: anthropic::AnthropicResponse = serde_json::from_str(&anthropic_message("Hello", 20)).unwrap();
+        let results = vec![
+            Ok((answer.clone(), 0, None)),
+            Err(ApiError::Internal { message: "candidate failed".to_string() }),
+        ];
+
+        assert!(finished_candidates(&state, &request, None, None, results).is_err());
+        let snapshot = state.costs.snapshot();
+        assert_eq!(snapshot.requests, 1);
+        let answer_cost = calculate_anthropic_cost(&answer.model, 10, 20, 0, 0, &state.config.pricing);
+        assert!((snapshot.total_cost - answer_cost).abs() < 1e-9, "{} vs {}", snapshot.total_cost, answer_cost);
+
+        let results = vec![Ok((answer, 0, None))];
+        assert_eq!(finished_candidates(&state, &request, None, None, results).unwrap().len(), 1);
+        assert_eq!(state.costs.snapshot().requests, 1);
+    }
 }
@@ -0,0 +1,88 @@
+//! Idempotency-key deduplication for non-streaming chat requests.
+//!
+//! A client that sends an `Idempotency-Key` header gets the result of the
+//! first request with that key for as long as it is cached. Requests that
+//! arrive while the first is still running wait for it instead of calling
+//! the models again. Failed requests are not cached, so a retry after an
+//! error runs the pipeline again.
+//!
+//! Entries are scoped to the caller's API tokens so two clients using the
+//! same key never see each other's responses, and are pruned lazily after
+//! a configurable TTL.
+
+use crate::{error::Result, models::ApiResponse};
+use dashmap::DashMap;
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+
+/// A cached response, or the slot a running request will fill.
+#[derive(Debug)]
+struct IdempotencyEntry {
+    response: Arc<OnceCell<ApiResponse>>,
+    created_at: Instant,
+}
+
+/// Responses for recent idempotent requests, keyed by scoped idempotency key.
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    entries: DashMap<u64, IdempotencyEntry>,
+    ttl: Duration,
+}
+
+impl IdempotencyStore {
+    /// Creates a store that keeps responses for `ttl`.
+    ///
+    /// A zero `ttl` disables deduplication entirely.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Returns the response for `key`, running `run` only if no request with
+    /// the same key has completed or is in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The client's idempotency key
+    /// * `scope` - Values identifying the caller, such as its API tokens
+    /// * `run` - Produces the response when it is not already cached
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ApiResponse>` - The original or newly produced response
+    pub async fn get_or_run<F, Fut>(&self, key: &str, scope: &[&str], run: F) -> Result<ApiResponse>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ApiResponse>>,
+    {
+        if self.ttl.is_zero() {
+            return run().await;
+        }
+
+        self.entries.retain(|_, entry| entry.created_at.elapsed() <= self.ttl);
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        scope.hash(&mut hasher);
+
+        let response = self
+            .entries
+            .entry(hasher.finish())
+            .or_insert_with(|| IdempotencyEntry {
+                response: Arc::new(OnceCell::new()),
+                created_at: Instant::now(),
+            })
+            .response
+            .clone();
+
+        response.get_or_try_init(run).await.cloned()
+    }
+}
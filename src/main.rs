@@ -12,15 +12,39 @@
 
 mod clients;
 mod config;
+mod costs;
 mod error;
+mod extract;
 mod handlers;
+mod idempotency;
 mod models;
+mod moderation;
+mod reasoning;
+mod reasoning_cache;
+mod replay;
+mod request_log;
+mod schema;
+mod stream_limit;
+mod tasks;
+mod tokenizer;
+mod transform;
+mod ttft;
+mod webhook;
 
-use crate::{config::Config, handlers::AppState};
-use axum::routing::{post, Router};
-use std::{net::SocketAddr, sync::Arc};
+use crate::{
+    config::{Config, UnknownKeys}, costs::CostCounters, handlers::AppState, idempotency::IdempotencyStore,
+    moderation::Moderator, reasoning_cache::ReasoningCache, replay::ReplayStore, request_log::RequestLog,
+    stream_limit::StreamLimiter, tasks::TaskGauge, transform::TransformPipeline, ttft::TtftMetrics,
+};
+use axum::routing::{get, post, Router};
+use std::{
+    net::SocketAddr,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::Duration,
+};
 use tower_http::{
     cors::{Any, CorsLayer},
+    decompression::RequestDecompressionLayer,
     trace::TraceLayer,
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -28,7 +52,14 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 /// Application entry point.
 ///
 /// Sets up logging, loads configuration, and starts the HTTP server
-/// with the configured routes and middleware.
+/// with the configured routes and middleware. When `server.warmup` is set,
+/// provider connections are opened in the background and `/ready` fails
+/// until they are. With `--check-config` the
+/// configuration is only validated and the process exits without serving;
+/// with `--selftest` one small request is run through the pipeline and the
+/// process exits with its result; with `--mock` both providers are replaced
+/// by canned responses. With `--strict-config` unknown keys in
+/// `config.toml` stop startup instead of being logged.
 ///
 /// # Returns
 ///
@@ -38,10 +69,18 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 ///
 /// Returns an error if:
 /// - Logging setup fails
+/// - `config.toml` has unknown keys in strict mode
+/// - The outbound HTTP client cannot be built from the configuration
+/// - A moderation blocklist or PII pattern is not a valid regular expression
+/// - The request log file cannot be opened
 /// - Server address binding fails
 /// - Server encounters a fatal error while running
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    if std::env::args().skip(1).any(|arg| arg == "--check-config") {
+        std::process::exit(check_config());
+    }
+
     // Initialize logging
     tracing_subscriber::registry()
         .with(
@@ -51,16 +90,82 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Load configuration
-    let config = Config::load().unwrap_or_else(|_| {
-        tracing::warn!("Failed to load config.toml, using default configuration");
-        Config::default()
-    });
+    // Load configuration; in strict mode unknown keys are fatal
+    let strict = std::env::args().skip(1).any(|arg| arg == "--strict-config");
+    let mut config = match Config::load(strict) {
+        Ok(config) => config,
+        Err(e) if e.is::<UnknownKeys>() => return Err(e),
+        Err(_) => {
+            tracing::warn!("Failed to load config.toml, using default configuration");
+            Config::default()
+        }
+    };
+    if std::env::args().skip(1).any(|arg| arg == "--mock") {
+        config.providers.mock_enabled = true;
+    }
+    if config.providers.mock_enabled {
+        tracing::warn!("Mock providers enabled: responses are canned and no upstream calls are made");
+    }
+    for problem in config.validate() {
+        tracing::warn!("Invalid configuration: {}", problem);
+    }
 
     // Create application state
     // Clone config for AppState
     let config_clone = config.clone();
-    let state = Arc::new(AppState { config: config_clone });
+    let http = clients::build_http_client(&config.http)?;
+    let state = Arc::new(AppState {
+        http,
+        replay: ReplayStore::new(Duration::from_secs(config.server.stream_replay_ttl_secs)),
+        idempotency: IdempotencyStore::new(Duration::from_secs(config.server.idempotency_ttl_secs)),
+        costs: Arc::new(CostCounters::default()),
+        moderator: Moderator::new(&config.moderation)?,
+        reasoning_cache: ReasoningCache::new(if config.cache.reasoning {
+            Duration::from_secs(config.cache.reasoning_ttl_secs)
+        } else {
+            Duration::ZERO
+        }),
+        streams: StreamLimiter::new(config.limits.max_streams_per_client),
+        stream_tasks: TaskGauge::default(),
+        ttft: Arc::new(TtftMetrics::default()),
+        request_log: RequestLog::new(&config.request_log).await?,
+        transforms: TransformPipeline::from_config(&config.prompt)?,
+        ready: AtomicBool::new(false),
+        config: config_clone,
+    });
+
+    if std::env::args().skip(1).any(|arg| arg == "--selftest") {
+        std::process::exit(selftest(&state).await);
+    }
+
+    // Prime provider connections before reporting ready
+    if config.server.warmup && !config.providers.mock_enabled {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let providers = &state.config.providers;
+            clients::warmup(
+                &state.http,
+                providers.deepseek.base_url.as_deref(),
+                providers.anthropic.base_url.as_deref(),
+            )
+            .await;
+            state.ready.store(true, Ordering::Release);
+        });
+    } else {
+        state.ready.store(true, Ordering::Release);
+    }
+
+    // Periodically log the streaming task gauge
+    if let Some(interval_secs) = config.server.task_log_interval_secs.filter(|secs| *secs > 0) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+            loop {
+                interval.tick().await;
+                tracing::info!("Active stream tasks: {}", state.stream_tasks.active());
+            }
+        });
+    }
 
     // Set up CORS
     let cors = CorsLayer::new()
@@ -71,6 +176,16 @@ async fn main() -> anyhow::Result<()> {
     // Build router
     let app = Router::new()
         .route("/", post(handlers::handle_chat))
+        .route("/ws", get(handlers::handle_ws))
+        .route("/batch", post(handlers::handle_batch))
+        .route("/reason", post(handlers::handle_reason))
+        .route("/tokenize", post(handlers::handle_tokenize))
+        .route("/stream/{request_id}", get(handlers::handle_stream_resume))
+        .route("/ready", get(handlers::handle_ready))
+        .route("/metrics", get(handlers::handle_metrics))
+        .route("/admin/cost", get(handlers::handle_admin_cost))
+        .route("/admin/cost/reset", post(handlers::handle_admin_cost_reset))
+        .layer(RequestDecompressionLayer::new())
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .with_state(state);
@@ -91,3 +206,115 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Prompt sent by `--selftest`.
+const SELFTEST_PROMPT: &str = "What is 2 + 2? Answer with the number only.";
+
+/// Runs one small request through the full pipeline without starting the server.
+///
+/// The provider API keys are read from the `DEEPSEEK_API_TOKEN` and
+/// `ANTHROPIC_API_TOKEN` environment variables (not needed in mock mode).
+/// The check passes when DeepSeek returns reasoning, Anthropic returns an
+/// answer and both were priced with configured rates. Prints a short
+/// summary to stdout, or the failure to stderr.
+///
+/// # Arguments
+///
+/// * `state` - Application state built from the loaded configuration
+///
+/// # Returns
+///
+/// * `i32` - The process exit code: 0 if the self-test passed, 1 otherwise
+async fn selftest(state: &AppState) -> i32 {
+    let mut headers = axum::http::HeaderMap::new();
+    for (header, var) in [
+        ("X-DeepSeek-API-Token", "DEEPSEEK_API_TOKEN"),
+        ("X-Anthropic-API-Token", "ANTHROPIC_API_TOKEN"),
+    ] {
+        if let Some(value) = std::env::var(var).ok().and_then(|token| token.parse().ok()) {
+            headers.insert(header, value);
+        }
+    }
+
+    let request = serde_json::from_value(serde_json::json!({
+        "messages": [{ "role": "user", "content": SELFTEST_PROMPT }],
+        "split_content": true,
+    }))
+    .expect("self-test request is valid");
+
+    let result = match handlers::build_clients(state, &headers) {
+        Ok((deepseek_client, anthropic_client)) => {
+            handlers::run_pipeline(state, deepseek_client.as_ref(), anthropic_client.as_ref(), &request).await
+        }
+        Err(e) => Err(e),
+    };
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("selftest: FAIL: {}", e);
+            return 1;
+        }
+    };
+
+    let has_text = |blocks: &Option<Vec<models::ContentBlock>>| {
+        blocks.iter().flatten().any(|block| !block.text.trim().is_empty())
+    };
+    let mut problems = Vec::new();
+    if !has_text(&response.reasoning_content) {
+        problems.push("DeepSeek returned no reasoning");
+    }
+    if !has_text(&response.answer_content) {
+        problems.push("Anthropic returned no answer");
+    }
+    if response.finish.pricing_estimated == Some(true) {
+        problems.push("DeepSeek model has no pricing entry");
+    }
+
+    if problems.is_empty() {
+        println!(
+            "selftest: PASS ({} + {}, {} ms, {})",
+            response.finish.models.deepseek.as_deref().unwrap_or("deepseek"),
+            response.finish.models.anthropic.as_deref().unwrap_or("anthropic"),
+            response.timings.total_ms,
+            response.combined_usage.total_cost,
+        );
+        0
+    } else {
+        for problem in &problems {
+            eprintln!("selftest: FAIL: {}", problem);
+        }
+        1
+    }
+}
+
+/// Loads and validates `config.toml` without starting the server.
+///
+/// Prints every problem found to stderr.
+///
+/// # Returns
+///
+/// * `i32` - The process exit code: 0 if the configuration is valid, 1 otherwise
+fn check_config() -> i32 {
+    let config = match Config::load(false) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("config.toml: {}", e);
+            return 1;
+        }
+    };
+
+    let mut problems = config.validate();
+    if let Err(e) = clients::build_http_client(&config.http) {
+        problems.push(format!("http: {}", e));
+    }
+
+    if problems.is_empty() {
+        println!("config.toml is valid");
+        0
+    } else {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        1
+    }
+}
@@ -3,46 +3,374 @@
 //! This module defines the structures used to represent incoming API requests,
 //! including chat messages, configuration options, and request parameters.
 
-use serde::{Deserialize, Serialize};
+use crate::{config::PricingOverride, transform::TransformPipeline};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 
+/// Maximum number of `tags` on a request.
+pub const MAX_TAGS: usize = 8;
+
+/// Maximum length of a tag name, in characters.
+pub const MAX_TAG_NAME_CHARS: usize = 64;
+
+/// Maximum length of a tag value, in characters.
+pub const MAX_TAG_VALUE_CHARS: usize = 128;
+
 /// Primary request structure for chat API endpoints.
 ///
 /// This structure represents a complete chat request, including messages,
 /// system prompts, and configuration options for both DeepSeek and Anthropic APIs.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiRequest {
+    /// Whether to stream the response; when omitted, the `Accept` header decides
     #[serde(default)]
-    pub stream: bool,
+    pub stream: Option<bool>,
     
     #[serde(default)]
     pub verbose: bool,
+
+    /// Body format of verbose non-streaming responses
+    #[serde(default)]
+    pub verbose_transfer: VerboseTransfer,
     
-    pub system: Option<String>,
+    /// System prompt, as a string or as fragments joined with
+    /// `prompt.system_separator`
+    pub system: Option<SystemPrompt>,
     pub messages: Vec<Message>,
+
+    /// System prompt for DeepSeek only, overriding the shared one
+    #[serde(default)]
+    pub deepseek_system: Option<String>,
+
+    /// System prompt for Anthropic only, overriding the shared one
+    #[serde(default)]
+    pub anthropic_system: Option<String>,
+
+    /// Language Claude must answer in, overriding `prompt.answer_language`
+    #[serde(default)]
+    pub answer_language: Option<String>,
     
     #[serde(default)]
     pub deepseek_config: ApiConfig,
     
     #[serde(default)]
     pub anthropic_config: ApiConfig,
+
+    /// Keep only the most recent N user/assistant messages
+    #[serde(default)]
+    pub max_history_messages: Option<usize>,
+
+    /// How each stage is fetched when `stream` is true
+    #[serde(default)]
+    pub stream_mode: StreamMode,
+
+    /// Whether the DeepSeek and Anthropic calls run in sequence or concurrently
+    #[serde(default)]
+    pub pipeline_mode: PipelineMode,
+
+    /// How the reasoning is passed to Anthropic
+    #[serde(default)]
+    pub reasoning_injection: ReasoningInjection,
+
+    /// Start of Claude's answer, which Claude continues. Sent as the final
+    /// assistant turn; under `reasoning_injection: assistant_message` it is
+    /// appended to the reasoning message instead. The returned answer
+    /// begins with the prefill
+    #[serde(default)]
+    pub assistant_prefill: Option<String>,
+
+    /// JSON Schema the final answer must match. Invalid answers are
+    /// requested again up to `providers.anthropic.schema_retries` times and
+    /// otherwise returned with `schema_valid: false`
+    #[serde(default)]
+    pub response_schema: Option<serde_json::Value>,
+
+    /// Include provider provenance (fingerprint, model, message id) in the response
+    #[serde(default)]
+    pub include_provenance: bool,
+
+    /// Run the request in the background and POST the result to this URL
+    #[serde(default)]
+    pub callback_url: Option<String>,
+
+    /// Sampling seed for providers that support deterministic sampling
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Also emit each completed answer block as a `content_block` stream event
+    #[serde(default)]
+    pub emit_complete_blocks: bool,
+
+    /// Whether generated content is returned, or only usage and timings
+    #[serde(default)]
+    pub content_mode: ContentMode,
+
+    /// Return the reasoning and the answer in separate `reasoning_content`
+    /// and `answer_content` arrays instead of `content` (non-streaming only)
+    #[serde(default)]
+    pub split_content: bool,
+
+    /// How streamed content deltas are coalesced into events
+    #[serde(default)]
+    pub stream_granularity: StreamGranularity,
+
+    /// Wire format of streaming responses
+    #[serde(default)]
+    pub stream_format: StreamFormat,
+
+    /// Time after which a streaming chat request is finished early with a
+    /// `cancelled_by_timeout` event instead of an error
+    #[serde(default)]
+    pub max_duration_ms: Option<u64>,
+
+    /// Minimum reasoning length in characters, overriding `reasoning.min_reasoning_chars`
+    #[serde(default)]
+    pub min_reasoning_chars: Option<usize>,
+
+    /// Alternate rates to report this request's costs with (requires the admin token)
+    #[serde(default)]
+    pub pricing_override: Option<PricingOverride>,
+
+    /// Return the resolved request and the assembled provider request
+    /// bodies as `debug` (non-streaming chat only; requires the admin token)
+    #[serde(default)]
+    pub debug_echo: bool,
+
+    /// Number of Anthropic answers to generate from the same reasoning,
+    /// returned as `candidates` (non-streaming only; bounded by `limits.max_n`)
+    #[serde(default)]
+    pub n: Option<u32>,
+
+    /// Cost attribution labels, e.g. a tenant or project; recorded in the
+    /// request log and the cost counters, never sent upstream
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+}
+
+/// Scheduling of the two provider calls.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineMode {
+    /// Call DeepSeek, then Anthropic with the reasoning
+    #[default]
+    Sequential,
+    /// Call both providers concurrently; requires `reasoning_injection: none`
+    Parallel,
+}
+
+/// How the DeepSeek reasoning is handed to Anthropic.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningInjection {
+    /// Appended as an assistant message after the conversation
+    #[default]
+    AssistantMessage,
+    /// Prepended to the final user turn, so Claude reads it as context the user supplied
+    UserMessage,
+    /// Not sent to Anthropic at all
+    None,
+}
+
+/// Controls which stages of a streaming request are streamed upstream.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// Stream both the reasoning and the answer token by token
+    #[default]
+    Full,
+    /// Fetch the complete reasoning in one call, then stream only the answer
+    AnswerOnly,
+}
+
+/// Controls whether generated content is returned to the client.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentMode {
+    /// Return the reasoning and the answer
+    #[default]
+    Full,
+    /// Run the full pipeline but return only usage, timings and status,
+    /// e.g. for synthetic monitoring probes
+    None,
+}
+
+/// Body format of non-streaming responses carrying raw upstream bodies.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerboseTransfer {
+    /// A single JSON object
+    #[default]
+    Json,
+    /// Newline-delimited JSON: the response without raw bodies, then one
+    /// line per raw body, each serialized only when it is sent
+    Ndjson,
+}
+
+/// Wire format of streaming responses.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamFormat {
+    /// Server-Sent Events, resumable with `Last-Event-ID`
+    #[default]
+    Sse,
+    /// One JSON event per line of a chunked `application/x-ndjson` body,
+    /// for consumers without SSE support
+    Ndjson,
+}
+
+/// How streamed content deltas are coalesced before they are sent.
+///
+/// Serialized as `"token"`, `"sentence"` or `{"chunk_ms": 250}`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamGranularity {
+    /// Send every delta as soon as it arrives
+    #[default]
+    Token,
+    /// Send text once it ends a sentence or a line
+    Sentence,
+    /// Send the text gathered since the last event at most this often
+    ChunkMs(u64),
 }
 
 /// A single message in a chat conversation.
 ///
 /// Represents one message in the conversation history, including
-/// its role (system, user, or assistant) and content.
+/// its role (system, user, or assistant) and content. Content is given
+/// either as a string or as an array of content blocks: text blocks are
+/// joined into `content`, while `tool_use` and `tool_result` blocks are
+/// kept in `tool_blocks`.
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "MessageInput")]
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// Tool calls and tool results, sent as blocks to Anthropic only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_blocks: Vec<ToolBlock>,
+}
+
+impl Message {
+    /// Returns whether the message has neither text nor tool blocks.
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty() && self.tool_blocks.is_empty()
+    }
+
+    /// Writes the message's tool blocks into its text.
+    ///
+    /// Used for DeepSeek, which only accepts text content, so the reasoning
+    /// still sees which tools were called and what they returned.
+    ///
+    /// # Returns
+    ///
+    /// * `Message` - The message with its tool blocks appended as text
+    pub fn with_tools_as_text(mut self) -> Self {
+        for block in std::mem::take(&mut self.tool_blocks) {
+            let line = match block {
+                ToolBlock::ToolUse(call) => {
+                    format!("[Tool call {} ({}): {}]", call.name, call.id, call.input)
+                }
+                ToolBlock::ToolResult(result) => {
+                    let output = match &result.content {
+                        serde_json::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    format!("[Tool result for {}: {}]", result.tool_use_id, output)
+                }
+            };
+            if !self.content.is_empty() {
+                self.content.push_str("\n\n");
+            }
+            self.content.push_str(&line);
+        }
+        self
+    }
+}
+
+/// A message as sent by clients, before its content blocks are sorted.
+#[derive(Deserialize)]
+struct MessageInput {
+    role: Role,
+    content: MessageContent,
+    #[serde(default)]
+    tool_blocks: Vec<ToolBlock>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentPart>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    #[serde(untagged)]
+    Tool(ToolBlock),
+}
+
+impl From<MessageInput> for Message {
+    fn from(input: MessageInput) -> Self {
+        let mut tool_blocks = input.tool_blocks;
+        let content = match input.content {
+            MessageContent::Text(text) => text,
+            MessageContent::Blocks(parts) => {
+                let mut texts = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => texts.push(text),
+                        ContentPart::Tool(block) => tool_blocks.push(block),
+                    }
+                }
+                texts.join("\n\n")
+            }
+        };
+
+        Self {
+            role: input.role,
+            content,
+            tool_blocks,
+        }
+    }
+}
+
+/// A tool call or tool result in a message's content.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolBlock {
+    /// A call Claude requested in an earlier answer
+    ToolUse(ToolCall),
+    /// The client's output for a tool call
+    ToolResult(ToolResult),
+}
+
+/// A tool call requested by Claude.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+/// The output of a tool call, returned by the client.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ToolResult {
+    pub tool_use_id: String,
+    /// A string or an array of content blocks
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub content: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_error: Option<bool>,
 }
 
 /// Possible roles for a message in a chat conversation.
 ///
 /// Each message must be associated with one of these roles to
-/// properly structure the conversation flow.
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+/// properly structure the conversation flow. Roles are serialized
+/// in lowercase but accepted in any casing on input.
+#[derive(Debug, Clone, Serialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
@@ -50,6 +378,72 @@ pub enum Role {
     Assistant,
 }
 
+impl Role {
+    const NAMES: &'static [&'static str] = &["system", "user", "assistant"];
+}
+
+/// Deserializes roles case-insensitively.
+///
+/// `"User"`, `"USER"` and `"user"` all map to `Role::User`; only
+/// genuinely unknown role names are rejected.
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        match value.to_ascii_lowercase().as_str() {
+            "system" => Ok(Role::System),
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            _ => Err(de::Error::unknown_variant(&value, Role::NAMES)),
+        }
+    }
+}
+
+/// A root system prompt, given either as one string or as fragments.
+///
+/// Fragments, such as a policy, task instructions and formatting rules,
+/// are joined in order with the configured separator before use.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SystemPrompt {
+    Text(String),
+    Fragments(Vec<String>),
+}
+
+/// How a request with a system prompt both at the root and in its
+/// messages is handled.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateSystemPolicy {
+    /// Reject the request
+    #[default]
+    Reject,
+    /// Use the root `system` field and drop the system messages
+    PreferRoot,
+    /// Use the system messages and ignore the root `system` field
+    PreferMessage,
+    /// Join the root prompt and the system messages, unless they are identical
+    Concatenate,
+}
+
+/// How messages with empty content are handled.
+///
+/// Anthropic rejects empty messages, which otherwise surfaces as an
+/// opaque upstream 400.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyMessagePolicy {
+    /// Reject the request
+    #[default]
+    Reject,
+    /// Remove the empty messages
+    Drop,
+    /// Replace the empty content with a single space
+    Space,
+}
+
 /// Configuration options for external API requests.
 ///
 /// Contains headers and body parameters that will be passed
@@ -61,9 +455,25 @@ pub struct ApiConfig {
     
     #[serde(default)]
     pub body: serde_json::Value,
+
+    /// Base URL overriding the provider's for this request; honored only
+    /// for requests carrying the admin token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 impl ApiRequest {
+    /// Returns a copy for `debug_echo` with every client-supplied header value redacted.
+    pub fn redacted(&self) -> Self {
+        let mut request = self.clone();
+        for config in [&mut request.deepseek_config, &mut request.anthropic_config] {
+            for value in config.headers.values_mut() {
+                *value = "[REDACTED]".to_string();
+            }
+        }
+        request
+    }
+
     /// Validates that system prompts are not duplicated.
     ///
     /// Checks that a system prompt is not provided in both the root level
@@ -79,45 +489,392 @@ impl ApiRequest {
         !(self.system.is_some() && system_in_messages)
     }
 
+    /// Returns whether the root system prompt was given as fragments that
+    /// are all empty.
+    pub fn has_empty_system_fragments(&self) -> bool {
+        match &self.system {
+            Some(SystemPrompt::Fragments(fragments)) => fragments.iter().all(|fragment| fragment.trim().is_empty()),
+            _ => false,
+        }
+    }
+
+    /// Joins a root system prompt given as fragments into a single prompt.
+    ///
+    /// Empty fragments are skipped. Must run before the system prompt is
+    /// read, since the getters only see a joined prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `separator` - Text placed between consecutive fragments
+    pub fn join_system_fragments(&mut self, separator: &str) {
+        if let Some(SystemPrompt::Fragments(fragments)) = &self.system {
+            let joined = fragments
+                .iter()
+                .filter(|fragment| !fragment.trim().is_empty())
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(separator);
+            self.system = Some(SystemPrompt::Text(joined));
+        }
+    }
+
+    /// Returns the root system prompt, once joined.
+    fn system_text(&self) -> Option<&str> {
+        match &self.system {
+            Some(SystemPrompt::Text(text)) => Some(text),
+            Some(SystemPrompt::Fragments(_)) | None => None,
+        }
+    }
+
+    /// Resolves a system prompt given both at the root and in the messages.
+    ///
+    /// Afterwards the resolved prompt is in the root `system` field and the
+    /// messages contain no system messages. Requests without a duplicate,
+    /// and any request under the `reject` policy, are left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How the two prompts are combined
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(String, String)>` - The original root prompt and the resolved
+    ///   one, or None if nothing was changed
+    pub fn resolve_duplicate_system(&mut self, policy: DuplicateSystemPolicy) -> Option<(String, String)> {
+        if self.validate_system_prompt() || policy == DuplicateSystemPolicy::Reject {
+            return None;
+        }
+        let root = self.system_text()?.to_string();
+
+        let from_messages = self
+            .messages
+            .iter()
+            .filter(|msg| matches!(msg.role, Role::System))
+            .map(|msg| msg.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let resolved = match policy {
+            DuplicateSystemPolicy::Reject | DuplicateSystemPolicy::PreferRoot => root.clone(),
+            DuplicateSystemPolicy::PreferMessage => from_messages,
+            DuplicateSystemPolicy::Concatenate if root == from_messages => root.clone(),
+            DuplicateSystemPolicy::Concatenate => format!("{}\n\n{}", root, from_messages),
+        };
+
+        self.messages.retain(|msg| !matches!(msg.role, Role::System));
+        self.system = Some(SystemPrompt::Text(resolved.clone()));
+        Some((root, resolved))
+    }
+
+    /// Describes the first problem with the request's `tags`, if any.
+    ///
+    /// Tags end up as metrics labels, so their number and size are bounded
+    /// and names are limited to letters, digits and underscores.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The problem, or None if the tags are valid
+    pub fn invalid_tags(&self) -> Option<String> {
+        if self.tags.len() > MAX_TAGS {
+            return Some(format!("at most {} tags are allowed", MAX_TAGS));
+        }
+        self.tags.iter().find_map(|(name, value)| {
+            if name.is_empty() || name.chars().count() > MAX_TAG_NAME_CHARS {
+                Some(format!("tag names must have 1 to {} characters", MAX_TAG_NAME_CHARS))
+            } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                Some(format!("tag name '{}' may only contain letters, digits and underscores", name))
+            } else if value.chars().count() > MAX_TAG_VALUE_CHARS {
+                Some(format!("tag '{}' is longer than {} characters", name, MAX_TAG_VALUE_CHARS))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the index of the first message with empty content.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The index in `messages`, or None if every message has content
+    pub fn empty_message_index(&self) -> Option<usize> {
+        self.messages.iter().position(Message::is_empty)
+    }
+
+    /// Drops or fills in messages with empty content.
+    ///
+    /// Requests under the `reject` policy are left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How empty messages are handled
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<usize>` - The original indices of the messages that were changed
+    pub fn resolve_empty_messages(&mut self, policy: EmptyMessagePolicy) -> Vec<usize> {
+        let empty: Vec<usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.is_empty())
+            .map(|(index, _)| index)
+            .collect();
+
+        match policy {
+            EmptyMessagePolicy::Reject => return Vec::new(),
+            EmptyMessagePolicy::Drop => self.messages.retain(|msg| !msg.is_empty()),
+            EmptyMessagePolicy::Space => {
+                for &index in &empty {
+                    self.messages[index].content = " ".to_string();
+                }
+            }
+        }
+        empty
+    }
+
     /// Returns messages with the system prompt in the correct position.
     ///
-    /// Ensures the system prompt (if present) is the first message,
+    /// Ensures the DeepSeek system prompt (if present) is the first message,
     /// followed by the conversation messages in order.
     ///
+    /// # Arguments
+    ///
+    /// * `default_system` - System prompt used when the request provides none
+    /// * `transforms` - Transforms applied to the positioned messages
+    ///
     /// # Returns
     ///
     /// * `Vec<Message>` - Messages with system prompt correctly positioned
-    pub fn get_messages_with_system(&self) -> Vec<Message> {
+    pub fn get_messages_with_system(&self, default_system: Option<&str>, transforms: &TransformPipeline) -> Vec<Message> {
         let mut messages = Vec::new();
 
         // Add system message first
-        if let Some(system) = &self.system {
+        if let Some(system) = self.get_system_prompt_for(Provider::DeepSeek, default_system) {
             messages.push(Message {
                 role: Role::System,
-                content: system.clone(),
+                content: system.to_string(),
+                tool_blocks: Vec::new(),
             });
         }
 
         // Add remaining messages
         messages.extend(self.messages.iter().filter(|msg| !matches!(msg.role, Role::System)).cloned());
 
+        transforms.apply(&mut messages);
+
         messages
     }
 
+    /// Resolves the history limit for this request.
+    ///
+    /// The request may tighten the configured limit but never loosen it.
+    ///
+    /// # Arguments
+    ///
+    /// * `configured` - The server-wide limit from the configuration
+    ///
+    /// # Returns
+    ///
+    /// * `Option<usize>` - The effective limit, or None if history is unbounded
+    pub fn effective_max_history(&self, configured: Option<usize>) -> Option<usize> {
+        match (self.max_history_messages, configured) {
+            (Some(requested), Some(configured)) => Some(requested.min(configured)),
+            (requested, configured) => requested.or(configured),
+        }
+    }
+
+    /// Returns the seed DeepSeek will sample with.
+    ///
+    /// The top-level `seed` takes precedence over a `seed` in the DeepSeek
+    /// config body.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The seed, or None if the request is unseeded
+    pub fn effective_seed(&self) -> Option<u64> {
+        self.seed.or_else(|| {
+            self.deepseek_config
+                .body
+                .get("seed")
+                .and_then(serde_json::Value::as_u64)
+        })
+    }
+
     /// Retrieves the system prompt if one is present.
     ///
     /// Checks both the root level system field and the messages array
-    /// for a system prompt.
+    /// for a system prompt, falling back to `default_system` only when
+    /// the request provides neither.
+    ///
+    /// # Arguments
+    ///
+    /// * `default_system` - System prompt used when the request provides none
     ///
     /// # Returns
     ///
     /// * `Option<&str>` - The system prompt if found, None otherwise
-    pub fn get_system_prompt(&self) -> Option<&str> {
-        self.system.as_deref().or_else(|| {
+    pub fn get_system_prompt<'a>(&'a self, default_system: Option<&'a str>) -> Option<&'a str> {
+        self.system_text().or_else(|| {
             self.messages
                 .iter()
                 .find(|msg| matches!(msg.role, Role::System))
                 .map(|msg| msg.content.as_str())
-        })
+        }).or(default_system)
+    }
+
+    /// Retrieves the system prompt a specific provider should receive.
+    ///
+    /// A provider-specific prompt (`deepseek_system` or `anthropic_system`)
+    /// takes precedence over the shared system prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - The provider the prompt is sent to
+    /// * `default_system` - System prompt used when the request provides none
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The system prompt if found, None otherwise
+    pub fn get_system_prompt_for<'a>(
+        &'a self,
+        provider: Provider,
+        default_system: Option<&'a str>,
+    ) -> Option<&'a str> {
+        let specific = match provider {
+            Provider::DeepSeek => self.deepseek_system.as_deref(),
+            Provider::Anthropic => self.anthropic_system.as_deref(),
+        };
+        specific.or_else(|| self.get_system_prompt(default_system))
+    }
+}
+
+/// Trims conversation history to the most recent messages.
+///
+/// System messages are always kept. Of the remaining messages only the
+/// last `max_messages` are kept, and any leading assistant messages left
+/// over are dropped as well so the trimmed history still starts with a
+/// user turn, as Anthropic requires.
+///
+/// # Arguments
+///
+/// * `messages` - The assembled messages, system prompt first
+/// * `max_messages` - Maximum number of user/assistant messages to keep
+///
+/// # Returns
+///
+/// * `(Vec<Message>, bool)` - The trimmed messages and whether anything was dropped
+pub fn trim_history(messages: Vec<Message>, max_messages: usize) -> (Vec<Message>, bool) {
+    let (system, conversation): (Vec<Message>, Vec<Message>) = messages
+        .into_iter()
+        .partition(|msg| msg.role == Role::System);
+
+    // Always keep at least the latest turn
+    let keep_from = conversation.len().saturating_sub(max_messages.max(1));
+    let mut kept: Vec<Message> = conversation[keep_from..].to_vec();
+    while kept.len() > 1 && kept[0].role != Role::User {
+        kept.remove(0);
+    }
+
+    let truncated = kept.len() < conversation.len();
+    let mut trimmed = system;
+    trimmed.extend(kept);
+
+    (trimmed, truncated)
+}
+
+/// Normalizes whitespace in message content without changing its meaning.
+///
+/// Converts `\r\n` line endings to `\n`, trims trailing whitespace from the
+/// end of the message and collapses runs of blank lines into a single blank
+/// line. Lines inside fenced code blocks are left exactly as written.
+///
+/// # Arguments
+///
+/// * `content` - The message content
+///
+/// # Returns
+///
+/// * `String` - The normalized content
+pub fn normalize_content(content: &str) -> String {
+    let content = content.replace("\r\n", "\n");
+    let mut normalized = String::with_capacity(content.len());
+    let mut in_fence = false;
+    let mut previous_blank = false;
+
+    for line in content.split('\n') {
+        let blank = line.trim().is_empty();
+        if !in_fence && blank && previous_blank {
+            continue;
+        }
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        previous_blank = blank && !in_fence;
+
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+
+    normalized.trim_end().to_string()
+}
+
+/// Request body for the token counting endpoint.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenizeRequest {
+    pub text: String,
+
+    /// Provider whose tokenizer to approximate
+    #[serde(default)]
+    pub provider: Option<Provider>,
+
+    /// Model id, used to infer the provider when none is given
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Upstream providers used by the pipeline.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    DeepSeek,
+    Anthropic,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ApiError, extract::ApiJson};
+    use axum::{body::Body, extract::FromRequest, http::{header, Request, StatusCode}};
+
+    #[test]
+    fn roles_are_case_insensitive() {
+        let messages: Vec<Message> = serde_json::from_value(serde_json::json!([
+            { "role": "System", "content": "a" },
+            { "role": "User", "content": "b" },
+            { "role": "ASSISTANT", "content": "c" },
+            { "role": "uSeR", "content": "d" },
+        ]))
+        .unwrap();
+
+        let roles: Vec<Role> = messages.into_iter().map(|message| message.role).collect();
+        assert_eq!(roles, [Role::System, Role::User, Role::Assistant, Role::User]);
+    }
+
+    #[tokio::test]
+    async fn unknown_role_is_a_bad_request_naming_the_role() {
+        let body = serde_json::json!({ "messages": [{ "role": "Moderator", "content": "hi" }] });
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let error = ApiJson::<ApiRequest>::from_request(request, &()).await.unwrap_err();
+
+        let (status, _) = error.to_status_and_response();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        match error {
+            ApiError::BadRequest { message } => assert!(message.contains("unknown variant `Moderator`"), "{}", message),
+            other => panic!("unexpected error: {:?}", other),
+        }
     }
 }
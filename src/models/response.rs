@@ -3,6 +3,7 @@
 //! This module defines the structures used to represent API responses,
 //! including chat completions, usage statistics, and streaming events.
 
+use crate::{error::ErrorResponse, models::{ApiRequest, Provider, ToolCall}};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -13,8 +14,19 @@ use std::collections::HashMap;
 /// content blocks, usage statistics, and optional raw API responses.
 #[derive(Debug, Serialize, Clone)]
 pub struct ApiResponse {
+    /// Request id, also sent as `X-Request-Id`
+    pub id: String,
     pub created: DateTime<Utc>,
+    /// Reasoning and answer blocks in order; empty for `split_content` requests
     pub content: Vec<ContentBlock>,
+
+    /// The reasoning blocks, for `split_content` requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<Vec<ContentBlock>>,
+
+    /// The answer blocks, for `split_content` requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_content: Option<Vec<ContentBlock>>,
     
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deepseek_response: Option<ExternalApiResponse>,
@@ -22,7 +34,166 @@ pub struct ApiResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub anthropic_response: Option<ExternalApiResponse>,
     
+    pub history_truncated: bool,
+    /// Whether the response was cut down to fit the configured size limits
+    pub truncated: bool,
+    /// Whether Claude refused to answer
+    pub refused: bool,
+    /// Server-side changes made to the request
+    pub adjustments: Vec<Adjustment>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_system_fingerprint: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_model: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<SeedInfo>,
+
+    /// DeepSeek's id for the upstream request, for support escalations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_request_id: Option<String>,
+
+    /// Anthropic's id for the upstream request, for support escalations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_request_id: Option<String>,
+
+    /// Rate-limit state reported by the providers, for client-side pacing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limits: Option<RateLimits>,
+
+    /// The resolved request and provider request bodies, for `debug_echo` requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<Box<DebugEcho>>,
+
+    /// Every answer, for requests with `n` above one; the first is also in `content`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidates: Option<Vec<Candidate>>,
+
     pub combined_usage: CombinedUsage,
+    pub timings: Timings,
+    pub finish: Finish,
+}
+
+/// One of the answers generated for a request with `n` above one.
+///
+/// Each candidate is a separate Anthropic call on the same reasoning, so
+/// each carries its own usage; `combined_usage` holds their sum.
+#[derive(Debug, Serialize, Clone)]
+pub struct Candidate {
+    pub index: usize,
+    /// The answer blocks, formatted like the answer in `content`
+    pub content: Vec<ContentBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    /// Whether the answer matches `response_schema`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_valid: Option<bool>,
+    pub usage: AnthropicUsage,
+}
+
+/// Outcome of a request, gathered in one place.
+///
+/// Returned as `finish` on non-streaming responses and on the final `done`
+/// stream event. Fields that do not apply to a request are omitted:
+///
+/// - `stop_reason` - Why Anthropic stopped answering, or `cancelled_by_timeout` for a
+///   stream cut off by `max_duration_ms`; absent for reasoning-only requests
+/// - `reasoning_truncated` - Whether DeepSeek hit its token limit while reasoning
+/// - `history_truncated` - Whether older messages were dropped to fit the history limit
+/// - `pricing_estimated` - Whether the DeepSeek cost uses fallback rates
+/// - `cached` - Whether the reasoning came from the reasoning cache
+/// - `models` - The model each provider ran with
+/// - `request_id` - The id a stream can be resumed with; streams only
+/// - `continuations` - Follow-up calls made to finish an answer cut off at
+///   `max_tokens`; present when `auto_continue` is enabled
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Finish {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_truncated: Option<bool>,
+    pub history_truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pricing_estimated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached: Option<bool>,
+    pub models: FinishModels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuations: Option<u32>,
+    /// Whether the answer matched the request's `response_schema`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_valid: Option<bool>,
+}
+
+/// How the server interpreted a request, returned for `debug_echo`.
+#[derive(Debug, Serialize, Clone)]
+pub struct DebugEcho {
+    /// The request after defaults, clamps and prompt changes, with header values redacted
+    pub request: ApiRequest,
+    /// Body sent to DeepSeek
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_request: Option<serde_json::Value>,
+    /// Body of the first call to Anthropic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_request: Option<serde_json::Value>,
+}
+
+/// Models that ran for a request, by provider.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct FinishModels {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic: Option<String>,
+}
+
+/// The seed applied to a request, echoed so reproducible runs can be audited.
+#[derive(Debug, Serialize, Clone)]
+pub struct SeedInfo {
+    pub value: u64,
+    /// Providers that do not support seeding and ran unseeded
+    pub unsupported_providers: Vec<Provider>,
+}
+
+/// Response structure for the reasoning-only endpoint.
+///
+/// Contains the DeepSeek reasoning together with its usage statistics,
+/// without any Anthropic output.
+#[derive(Debug, Serialize, Clone)]
+pub struct ReasoningResponse {
+    pub created: DateTime<Utc>,
+    pub content: Vec<ContentBlock>,
+    
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_response: Option<ExternalApiResponse>,
+    
+    pub history_truncated: bool,
+    pub adjustments: Vec<Adjustment>,
+
+    /// DeepSeek's id for the upstream request, for support escalations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_request_id: Option<String>,
+
+    pub deepseek_usage: DeepSeekUsage,
+    pub timings: Timings,
+}
+
+/// Result of a single item in a batch request.
+///
+/// Successful items carry the full response, failed items carry
+/// the same error envelope returned by the single-request endpoint.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchItem {
+    Success(Box<ApiResponse>),
+    Error(ErrorResponse),
 }
 
 /// A block of content in a response.
@@ -34,6 +205,9 @@ pub struct ContentBlock {
     #[serde(rename = "type")]
     pub content_type: String,
     pub text: String,
+    /// The call requested by a `tool_use` block, for the client to run
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub tool_call: Option<ToolCall>,
 }
 
 /// Raw response from an external API.
@@ -47,6 +221,14 @@ pub struct ExternalApiResponse {
     pub body: serde_json::Value,
 }
 
+/// A raw upstream response sent as its own line of an NDJSON response.
+#[derive(Debug, Serialize)]
+pub struct RawBodyLine {
+    pub provider: Provider,
+    #[serde(flatten)]
+    pub response: ExternalApiResponse,
+}
+
 /// Combined usage statistics from both AI models.
 ///
 /// Aggregates token usage and cost information from both
@@ -70,6 +252,10 @@ pub struct DeepSeekUsage {
     pub cached_input_tokens: u32,
     pub total_tokens: u32,
     pub total_cost: String,
+    /// Whether the cost uses fallback rates because the model has no pricing entry
+    pub pricing_estimated: bool,
+    /// Whether the reasoning was reused from the reasoning cache without calling DeepSeek
+    pub cached: bool,
 }
 
 /// Usage statistics for Anthropic API calls.
@@ -86,18 +272,163 @@ pub struct AnthropicUsage {
     pub total_cost: String,
 }
 
+/// Rate-limit state of both providers after a request.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct RateLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek: Option<RateLimit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic: Option<RateLimit>,
+}
+
+/// Rate-limit state of one provider, read from its response headers.
+///
+/// Where a provider limits several token kinds separately, the one with
+/// the fewest tokens remaining is reported. Reset times are passed through
+/// as the provider sent them.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct RateLimit {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_remaining: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_reset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_remaining: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_reset: Option<String>,
+}
+
+impl RateLimit {
+    /// Returns whether fewer than `ratio` of the requests or tokens are left.
+    ///
+    /// # Arguments
+    ///
+    /// * `ratio` - Fraction of the limit below which the provider counts as close to it
+    pub fn is_near_limit(&self, ratio: f64) -> bool {
+        [
+            (self.requests_remaining, self.requests_limit),
+            (self.tokens_remaining, self.tokens_limit),
+        ]
+        .into_iter()
+        .any(|pair| match pair {
+            (Some(remaining), Some(limit)) if limit > 0 => (remaining as f64) < limit as f64 * ratio,
+            _ => false,
+        })
+    }
+}
+
+/// A server-side change made to a client's request.
+///
+/// Records a clamped limit, truncated history or injected default,
+/// with the value the client asked for and the value actually used.
+#[derive(Debug, Serialize, Clone)]
+pub struct Adjustment {
+    pub field: String,
+    pub original: serde_json::Value,
+    pub effective: serde_json::Value,
+}
+
+/// Response structure for the token counting endpoint.
+///
+/// Counts are approximate for both providers; `tokenizer` names the
+/// encoding that produced them.
+#[derive(Debug, Serialize, Clone)]
+pub struct TokenizeResponse {
+    pub tokens: usize,
+    pub provider: Provider,
+    pub tokenizer: &'static str,
+}
+
+/// Readiness reported by the `/ready` endpoint.
+#[derive(Debug, Serialize, Clone)]
+pub struct ReadyStatus {
+    pub status: &'static str,
+}
+
+/// Cumulative cost totals reported by the admin endpoints.
+#[derive(Debug, Serialize, Clone)]
+pub struct CostSummary {
+    pub total_cost: String,
+    pub requests: u64,
+    /// Totals per request tag value
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub by_tag: Vec<TaggedCost>,
+}
+
+/// Cumulative cost of the requests carrying one tag value.
+#[derive(Debug, Serialize, Clone)]
+pub struct TaggedCost {
+    pub tag: String,
+    pub value: String,
+    pub total_cost: String,
+    pub requests: u64,
+}
+
+/// Acknowledgement for a request whose result is delivered to a `callback_url`.
+#[derive(Debug, Serialize, Clone)]
+pub struct CallbackAccepted {
+    pub request_id: String,
+}
+
+/// Latency breakdown for a single request.
+///
+/// Reports how long each stage of the pipeline took, in milliseconds,
+/// so the slower of the reasoning and answer stages is easy to spot.
+/// Streams also report how long each phase took to send its first token.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Timings {
+    pub deepseek_ms: u64,
+    pub anthropic_ms: u64,
+    pub total_ms: u64,
+    /// Time from request receipt to the first streamed reasoning token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_ttft_ms: Option<u64>,
+    /// Time from request receipt to the first streamed answer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_ttft_ms: Option<u64>,
+}
+
+impl Timings {
+    /// Formats the timings as a `Server-Timing` header value.
+    ///
+    /// # Returns
+    ///
+    /// A header value such as `deepseek;dur=1200, anthropic;dur=800, total;dur=2010`
+    pub fn to_server_timing(&self) -> String {
+        format!(
+            "deepseek;dur={}, anthropic;dur={}, total;dur={}",
+            self.deepseek_ms, self.anthropic_ms, self.total_ms
+        )
+    }
+}
+
 // Streaming event types
 /// Events emitted during streaming responses.
 ///
 /// Represents different types of events that can occur
 /// during a streaming response, including content updates
-/// and usage statistics.
+/// and usage statistics. Events are sent wrapped in an
+/// [`IdentifiedEvent`] carrying the stream's request id.
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum StreamEvent {
     #[serde(rename = "start")]
     Start {
         created: DateTime<Utc>,
+        history_truncated: bool,
+        adjustments: Vec<Adjustment>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        deepseek_system_fingerprint: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seed: Option<SeedInfo>,
+        /// Time from request receipt to the start of the stream, as
+        /// `total_ms`; no stage has finished yet, so `deepseek_ms` and
+        /// `anthropic_ms` are zero until the `usage` event
+        timings: Timings,
     },
     
     #[serde(rename = "content")]
@@ -105,19 +436,93 @@ pub enum StreamEvent {
         content: Vec<ContentBlock>,
     },
     
+    /// A tool call Claude requested, sent once its input is complete
+    #[serde(rename = "tool_use")]
+    ToolUse {
+        index: usize,
+        tool_call: ToolCall,
+    },
+
+    /// A finished Anthropic content block with all of its deltas joined
+    #[serde(rename = "content_block")]
+    ContentBlockComplete {
+        index: usize,
+        content: ContentBlock,
+    },
+    
+    #[serde(rename = "reasoning_progress")]
+    ReasoningProgress {
+        estimated_reasoning_tokens: u32,
+    },
+    
+    #[serde(rename = "phase_change")]
+    PhaseChange {
+        from: Phase,
+        to: Phase,
+    },
+    
     #[serde(rename = "usage")]
     Usage {
         usage: CombinedUsage,
+        timings: Timings,
     },
     
     #[serde(rename = "done")]
-    Done,
+    Done {
+        refused: bool,
+        finish: Finish,
+    },
     
     #[serde(rename = "error")]
     Error {
         message: String,
         code: u16,
     },
+
+    /// The request's `max_duration_ms` passed; followed by `done`
+    #[serde(rename = "cancelled_by_timeout")]
+    CancelledByTimeout {
+        elapsed_ms: u64,
+    },
+}
+
+/// A stream event tagged with the request it belongs to.
+///
+/// The id is the stream's `X-Request-Id`, so clients multiplexing several
+/// streams can attribute every event to its request.
+#[derive(Debug, Serialize)]
+pub struct IdentifiedEvent<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub event: &'a StreamEvent,
+}
+
+/// Stages of a streaming response, reported by `StreamEvent::PhaseChange`.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// DeepSeek is producing the reasoning
+    Reasoning,
+    /// Anthropic is producing the final answer
+    Answering,
+}
+
+impl StreamEvent {
+    /// Returns the SSE event name used when sending this event.
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            StreamEvent::Start { .. } => "start",
+            StreamEvent::Content { .. } => "content",
+            StreamEvent::ContentBlockComplete { .. } => "content_block",
+            StreamEvent::ToolUse { .. } => "tool_use",
+            StreamEvent::ReasoningProgress { .. } => "reasoning_progress",
+            StreamEvent::PhaseChange { .. } => "phase_change",
+            StreamEvent::Usage { .. } => "usage",
+            StreamEvent::Done { .. } => "done",
+            StreamEvent::Error { .. } => "error",
+            StreamEvent::CancelledByTimeout { .. } => "cancelled_by_timeout",
+        }
+    }
 }
 
 impl ContentBlock {
@@ -134,6 +539,7 @@ impl ContentBlock {
         Self {
             content_type: "text".to_string(),
             text: text.into(),
+            tool_call: None,
         }
     }
 
@@ -150,6 +556,7 @@ impl ContentBlock {
         Self {
             content_type: block.content_type,
             text: block.text,
+            tool_call: block.tool_call,
         }
     }
 }
@@ -167,29 +574,156 @@ impl ApiResponse {
     #[allow(dead_code)]
     pub fn new(content: impl Into<String>) -> Self {
         Self {
+            id: uuid::Uuid::new_v4().to_string(),
             created: Utc::now(),
             content: vec![ContentBlock::text(content)],
             deepseek_response: None,
             anthropic_response: None,
+            history_truncated: false,
+            truncated: false,
+            refused: false,
+            adjustments: Vec::new(),
+            deepseek_system_fingerprint: None,
+            anthropic_model: None,
+            anthropic_id: None,
+            seed: None,
+            deepseek_request_id: None,
+            anthropic_request_id: None,
+            rate_limits: None,
+            debug: None,
+            candidates: None,
+            reasoning_content: None,
+            answer_content: None,
             combined_usage: CombinedUsage {
                 total_cost: "$0.00".to_string(),
-                deepseek_usage: DeepSeekUsage {
-                    input_tokens: 0,
-                    output_tokens: 0,
-                    reasoning_tokens: 0,
-                    cached_input_tokens: 0,
-                    total_tokens: 0,
-                    total_cost: "$0.00".to_string(),
-                },
-                anthropic_usage: AnthropicUsage {
-                    input_tokens: 0,
-                    output_tokens: 0,
-                    cached_write_tokens: 0,
-                    cached_read_tokens: 0,
-                    total_tokens: 0,
-                    total_cost: "$0.00".to_string(),
-                },
+                deepseek_usage: DeepSeekUsage::default(),
+                anthropic_usage: AnthropicUsage::default(),
             },
+            timings: Timings::default(),
+            finish: Finish::default(),
+        }
+    }
+
+    /// Removes all generated content, keeping usage, timings and status.
+    ///
+    /// Drops the content blocks and the raw upstream bodies, which also
+    /// carry the generated text.
+    pub fn omit_content(&mut self) {
+        self.content.clear();
+        self.deepseek_response = None;
+        self.anthropic_response = None;
+        for candidate in self.candidates.iter_mut().flatten() {
+            candidate.content.clear();
+        }
+    }
+
+    /// Moves the content blocks into separate reasoning and answer arrays.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_reasoning` - Whether each content block, by position, is reasoning
+    pub fn split_content(&mut self, is_reasoning: &[bool]) {
+        let (reasoning, answer): (Vec<_>, Vec<_>) = std::mem::take(&mut self.content)
+            .into_iter()
+            .zip(is_reasoning.iter().copied())
+            .partition(|(_, is_reasoning)| *is_reasoning);
+        self.reasoning_content = Some(reasoning.into_iter().map(|(block, _)| block).collect());
+        self.answer_content = Some(answer.into_iter().map(|(block, _)| block).collect());
+    }
+
+    /// Truncates the response to fit the given limits.
+    ///
+    /// Content blocks beyond `max_blocks` are dropped, and text is cut once
+    /// the total content size reaches `max_bytes`. Raw upstream bodies that
+    /// do not fit in the remaining byte budget are replaced with `null`, and
+    /// candidates that do not fit lose their content. Sets `truncated` if
+    /// anything was removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes` - Maximum total size of content text and raw bodies
+    /// * `max_blocks` - Maximum number of content blocks
+    pub fn enforce_limits(&mut self, max_bytes: Option<usize>, max_blocks: Option<usize>) {
+        if let Some(max_blocks) = max_blocks {
+            if self.content.len() > max_blocks {
+                self.content.truncate(max_blocks);
+                self.truncated = true;
+            }
+        }
+
+        let Some(mut remaining) = max_bytes else {
+            return;
+        };
+
+        let mut kept_blocks = 0;
+        for block in &mut self.content {
+            if remaining == 0 {
+                break;
+            }
+            if block.text.len() > remaining {
+                let mut cut = remaining;
+                while !block.text.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                block.text.truncate(cut);
+                self.truncated = true;
+            }
+            remaining -= block.text.len();
+            kept_blocks += 1;
+        }
+        if kept_blocks < self.content.len() {
+            self.content.truncate(kept_blocks);
+            self.truncated = true;
+        }
+
+        for raw in [&mut self.deepseek_response, &mut self.anthropic_response].into_iter().flatten() {
+            let size = serde_json::to_string(&raw.body).map(|body| body.len()).unwrap_or(0);
+            if size > remaining {
+                raw.body = serde_json::Value::Null;
+                self.truncated = true;
+            } else {
+                remaining -= size;
+            }
+        }
+
+        for candidate in self.candidates.iter_mut().flatten() {
+            let size: usize = candidate.content.iter().map(|block| block.text.len()).sum();
+            if size > remaining {
+                candidate.content.clear();
+                self.truncated = true;
+            } else {
+                remaining -= size;
+            }
+        }
+    }
+}
+
+/// Provides empty usage statistics for a stage that did not run.
+impl Default for DeepSeekUsage {
+    fn default() -> Self {
+        Self {
+            input_tokens: 0,
+            output_tokens: 0,
+            reasoning_tokens: 0,
+            cached_input_tokens: 0,
+            total_tokens: 0,
+            total_cost: "$0.00".to_string(),
+            pricing_estimated: false,
+            cached: false,
+        }
+    }
+}
+
+/// Provides empty usage statistics for a stage that did not run.
+impl Default for AnthropicUsage {
+    fn default() -> Self {
+        Self {
+            input_tokens: 0,
+            output_tokens: 0,
+            cached_write_tokens: 0,
+            cached_read_tokens: 0,
+            total_tokens: 0,
+            total_cost: "$0.00".to_string(),
         }
     }
 }
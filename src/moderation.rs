@@ -0,0 +1,137 @@
+//! Screening of incoming messages before they reach the providers.
+//!
+//! When enabled, every user message is checked against a local blocklist of
+//! regular expressions and, optionally, an external moderation endpoint.
+//! Flagged requests are rejected before any provider call so no tokens are
+//! spent on them.
+
+use crate::{
+    config::ModerationConfig,
+    error::{ApiError, Result},
+    models::{Message, Role},
+};
+use regex::Regex;
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Verdict returned by an external moderation endpoint.
+///
+/// Both a top-level `flagged` field and an OpenAI-style `results` array
+/// are understood.
+#[derive(Debug, Deserialize)]
+struct ModerationVerdict {
+    #[serde(default)]
+    flagged: bool,
+    #[serde(default)]
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModerationResult {
+    #[serde(default)]
+    flagged: bool,
+}
+
+/// Checks messages against the configured moderation rules.
+#[derive(Debug)]
+pub struct Moderator {
+    enabled: bool,
+    patterns: Vec<Regex>,
+    endpoint: Option<String>,
+    endpoint_token: Option<String>,
+    timeout: Duration,
+}
+
+impl Moderator {
+    /// Builds a moderator from the configuration, compiling the blocklist.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The moderation configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a blocked pattern is not a valid regular expression
+    pub fn new(config: &ModerationConfig) -> anyhow::Result<Self> {
+        let patterns = config
+            .blocked_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            enabled: config.enabled,
+            patterns,
+            endpoint: config.endpoint.clone(),
+            endpoint_token: config.endpoint_token.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+        })
+    }
+
+    /// Screens the user messages of a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `http` - The shared outbound HTTP client
+    /// * `messages` - The request's messages; only user messages are checked
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::ContentFlagged` if a message matches the blocklist
+    /// or is flagged by the endpoint, or `ApiError::Internal` if the endpoint
+    /// cannot be reached
+    pub async fn check(&self, http: &Client, messages: &[Message]) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let user_messages: Vec<&str> = messages
+            .iter()
+            .filter(|msg| msg.role == Role::User)
+            .map(|msg| msg.content.as_str())
+            .collect();
+
+        if user_messages
+            .iter()
+            .any(|content| self.patterns.iter().any(|pattern| pattern.is_match(content)))
+        {
+            return Err(ApiError::ContentFlagged {
+                message: "Message matches a blocked pattern".to_string(),
+            });
+        }
+
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(());
+        };
+
+        let mut request = http
+            .post(endpoint)
+            .timeout(self.timeout)
+            .json(&serde_json::json!({ "input": user_messages }));
+        if let Some(token) = &self.endpoint_token {
+            request = request.bearer_auth(token);
+        }
+
+        let verdict: ModerationVerdict = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ApiError::Internal {
+                message: format!("Moderation request failed: {}", e),
+            })?
+            .json()
+            .await
+            .map_err(|e| ApiError::Internal {
+                message: format!("Invalid moderation response: {}", e),
+            })?;
+
+        if verdict.flagged || verdict.results.iter().any(|result| result.flagged) {
+            return Err(ApiError::ContentFlagged {
+                message: "Message was flagged by content moderation".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Formatting of DeepSeek reasoning content.
+//!
+//! Reasoning is formatted twice: once for display to the client, and once
+//! for the message handed to Anthropic. The two are configured
+//! independently so the client-facing markup never has to leak into the
+//! prompt Claude sees.
+
+use crate::{
+    config::{ReasoningConfig, ReasoningDisplayFormat},
+    models::{Message, ReasoningInjection, Role},
+};
+use std::borrow::Cow;
+
+/// Formats reasoning for display to the client.
+///
+/// Works both on complete reasoning and incrementally on streamed deltas:
+/// emit `open()`, then `delta()` for each chunk, then `close()`.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayFormatter<'a> {
+    format: ReasoningDisplayFormat,
+    tag: &'a str,
+}
+
+impl<'a> DisplayFormatter<'a> {
+    /// Creates a formatter from the reasoning configuration.
+    pub fn new(config: &'a ReasoningConfig) -> Self {
+        Self {
+            format: config.display_format,
+            tag: &config.display_tag,
+        }
+    }
+
+    /// Creates a formatter that passes reasoning through unchanged.
+    pub fn raw() -> Self {
+        Self {
+            format: ReasoningDisplayFormat::None,
+            tag: "",
+        }
+    }
+
+    /// Returns the text emitted before the reasoning.
+    pub fn open(&self) -> String {
+        match self.format {
+            ReasoningDisplayFormat::XmlTags => open_tag(self.tag),
+            ReasoningDisplayFormat::MarkdownFence => "```reasoning\n".to_string(),
+            ReasoningDisplayFormat::Blockquote => "> ".to_string(),
+            ReasoningDisplayFormat::None => String::new(),
+        }
+    }
+
+    /// Returns the text emitted after the reasoning.
+    pub fn close(&self) -> String {
+        match self.format {
+            ReasoningDisplayFormat::XmlTags => close_tag(self.tag),
+            ReasoningDisplayFormat::MarkdownFence => "\n```".to_string(),
+            ReasoningDisplayFormat::Blockquote | ReasoningDisplayFormat::None => String::new(),
+        }
+    }
+
+    /// Formats a chunk of reasoning that appears between `open()` and `close()`.
+    pub fn delta<'b>(&self, reasoning: &'b str) -> Cow<'b, str> {
+        match self.format {
+            ReasoningDisplayFormat::Blockquote if reasoning.contains('\n') => {
+                Cow::Owned(reasoning.replace('\n', "\n> "))
+            }
+            _ => Cow::Borrowed(reasoning),
+        }
+    }
+
+    /// Formats complete reasoning content.
+    pub fn format(&self, reasoning: &str) -> String {
+        format!("{}{}{}", self.open(), self.delta(reasoning), self.close())
+    }
+}
+
+/// Placeholder replaced with the reasoning in `handoff_template`.
+const REASONING_PLACEHOLDER: &str = "{reasoning}";
+
+/// Builds the assistant message content handed to Anthropic.
+///
+/// Uses the configured `handoff_template` when set, otherwise wraps the
+/// reasoning in `handoff_tag`.
+///
+/// # Arguments
+///
+/// * `config` - The reasoning configuration
+/// * `reasoning` - The raw reasoning content
+///
+/// # Returns
+///
+/// The content of the assistant message sent to Anthropic
+pub fn handoff_content(config: &ReasoningConfig, reasoning: &str) -> String {
+    match &config.handoff_template {
+        Some(template) => template.replace(REASONING_PLACEHOLDER, reasoning),
+        None => wrap_in_tag(&config.handoff_tag, reasoning),
+    }
+}
+
+/// Marks the reasoning removed by `truncate`.
+const TRUNCATION_MARKER: &str = "\n\n[...]\n\n";
+
+/// Cuts reasoning down to its start and end.
+///
+/// The conclusion usually sits at the end of the reasoning and the framing
+/// of the problem at the start, so the middle is dropped.
+///
+/// # Arguments
+///
+/// * `reasoning` - The raw reasoning content
+/// * `max_chars` - Characters kept, excluding the marker
+///
+/// # Returns
+///
+/// The reasoning, unchanged when it is no longer than `max_chars`
+pub fn truncate(reasoning: &str, max_chars: usize) -> Cow<'_, str> {
+    let chars = reasoning.chars().count();
+    if chars <= max_chars {
+        return Cow::Borrowed(reasoning);
+    }
+
+    let head: String = reasoning.chars().take(max_chars / 2).collect();
+    let tail: String = reasoning.chars().skip(chars - (max_chars - max_chars / 2)).collect();
+    Cow::Owned(format!("{}{}{}", head, TRUNCATION_MARKER, tail))
+}
+
+/// Adds the reasoning to the messages sent to Anthropic.
+///
+/// As an assistant message, the reasoning is appended after the
+/// conversation. As a user message, it is merged into the start of the
+/// final user turn rather than added as a turn of its own, since Anthropic
+/// requires user and assistant turns to alternate; without a user turn to
+/// merge into, it is appended as a new one.
+///
+/// # Arguments
+///
+/// * `config` - The reasoning configuration
+/// * `injection` - How the reasoning is passed to Anthropic
+/// * `messages` - The messages for Anthropic, modified in place
+/// * `reasoning` - The raw reasoning content
+pub fn inject(
+    config: &ReasoningConfig,
+    injection: ReasoningInjection,
+    messages: &mut Vec<Message>,
+    reasoning: &str,
+) {
+    match injection {
+        ReasoningInjection::AssistantMessage => messages.push(Message {
+            role: Role::Assistant,
+            content: handoff_content(config, reasoning),
+            tool_blocks: Vec::new(),
+        }),
+        ReasoningInjection::UserMessage => {
+            let content = handoff_content(config, reasoning);
+            match messages.iter_mut().rev().find(|msg| msg.role != Role::System) {
+                // A message carrying only tool results has no text to follow
+                Some(last) if last.role == Role::User && last.content.is_empty() => {
+                    last.content = content;
+                }
+                Some(last) if last.role == Role::User => {
+                    last.content = format!("{}\n\n{}", content, last.content);
+                }
+                _ => messages.push(Message {
+                    role: Role::User,
+                    content,
+                    tool_blocks: Vec::new(),
+                }),
+            }
+        }
+        ReasoningInjection::None => {}
+    }
+}
+
+/// Ends the messages sent to Anthropic with the client's assistant prefill.
+///
+/// Claude continues a final assistant turn rather than answering anew. A
+/// trailing assistant message, such as reasoning injected as
+/// `assistant_message`, is extended with the prefill after a blank line;
+/// otherwise the prefill becomes a new assistant turn. Trailing whitespace
+/// is trimmed since Anthropic rejects it in a final assistant turn.
+///
+/// # Arguments
+///
+/// * `messages` - The messages for Anthropic, modified in place
+/// * `prefill` - The start of the answer
+pub fn prefill(messages: &mut Vec<Message>, prefill: &str) {
+    let prefill = prefill.trim_end();
+    match messages.last_mut() {
+        Some(last) if last.role == Role::Assistant => {
+            last.content = format!("{}\n\n{}", last.content, prefill);
+        }
+        _ => messages.push(Message {
+            role: Role::Assistant,
+            content: prefill.to_string(),
+            tool_blocks: Vec::new(),
+        }),
+    }
+}
+
+/// Returns the opening tag for a reasoning block, or an empty string if
+/// no tag is configured.
+///
+/// # Arguments
+///
+/// * `tag` - The tag name, e.g. `thinking`
+pub fn open_tag(tag: &str) -> String {
+    if tag.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>\n", tag)
+    }
+}
+
+/// Returns the closing tag for a reasoning block, or an empty string if
+/// no tag is configured.
+///
+/// # Arguments
+///
+/// * `tag` - The tag name, e.g. `thinking`
+pub fn close_tag(tag: &str) -> String {
+    if tag.is_empty() {
+        String::new()
+    } else {
+        format!("\n</{}>", tag)
+    }
+}
+
+/// Wraps reasoning content in the given tag.
+///
+/// # Arguments
+///
+/// * `tag` - The tag name; an empty tag returns the reasoning unchanged
+/// * `reasoning` - The raw reasoning content
+///
+/// # Returns
+///
+/// The wrapped reasoning content
+pub fn wrap_in_tag(tag: &str, reasoning: &str) -> String {
+    format!("{}{}{}", open_tag(tag), reasoning, close_tag(tag))
+}
+
+/// Removes reasoning tags that Claude echoes into its answer.
+///
+/// Claude sees the reasoning wrapped in the configured tags and sometimes
+/// answers with tags of its own. Every `<tag>...</tag>` span using the
+/// display or handoff tag is dropped, along with the whitespace after it.
+/// Works on streamed deltas: text that may be the start of a tag, or that
+/// lies inside an unclosed one, is held back until it can be decided. A
+/// tag still open when the answer ends was not a reasoning block, and is
+/// returned unchanged.
+#[derive(Debug, Clone)]
+pub struct LeakedTagFilter {
+    /// Opening and closing markup of each tag
+    tags: Vec<(String, String)>,
+    /// Text not yet returned
+    pending: String,
+    /// Index into `tags` of the span `pending` starts with, if any
+    open: Option<usize>,
+    /// Whether whitespace after a removed span is still being skipped
+    trim_start: bool,
+}
+
+impl LeakedTagFilter {
+    /// Creates a filter for the tags in the reasoning configuration.
+    pub fn new(config: &ReasoningConfig) -> Self {
+        let mut tags: Vec<(String, String)> = Vec::new();
+        for tag in [&config.display_tag, &config.handoff_tag] {
+            let markup = (format!("<{}>", tag), format!("</{}>", tag));
+            if !tag.is_empty() && !tags.contains(&markup) {
+                tags.push(markup);
+            }
+        }
+        Self {
+            tags,
+            pending: String::new(),
+            open: None,
+            trim_start: false,
+        }
+    }
+
+    /// Filters the next chunk of the answer.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The next chunk
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The text that can be emitted so far, possibly empty
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut out = String::new();
+        loop {
+            match self.open {
+                Some(index) => {
+                    let (open, close) = &self.tags[index];
+                    let Some(at) = self.pending[open.len()..].find(close.as_str()) else {
+                        break;
+                    };
+                    self.pending.drain(..open.len() + at + close.len());
+                    self.open = None;
+                    self.trim_start = true;
+                }
+                None => {
+                    if self.trim_start {
+                        let whitespace = self.pending.len() - self.pending.trim_start().len();
+                        self.pending.drain(..whitespace);
+                        if self.pending.is_empty() {
+                            break;
+                        }
+                        self.trim_start = false;
+                    }
+
+                    let next_open = self
+                        .tags
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, (open, _))| self.pending.find(open.as_str()).map(|at| (at, index)))
+                        .min();
+                    if let Some((at, index)) = next_open {
+                        out.extend(self.pending.drain(..at));
+                        self.open = Some(index);
+                        continue;
+                    }
+
+                    // Hold back a possible start of a tag
+                    let held = self
+                        .tags
+                        .iter()
+                        .flat_map(|(open, _)| (1..open.len()).filter(|&len| open.is_char_boundary(len)).map(move |len| &open[..len]))
+                        .filter(|prefix| self.pending.ends_with(prefix))
+                        .map(str::len)
+                        .max()
+                        .unwrap_or(0);
+                    out.extend(self.pending.drain(..self.pending.len() - held));
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    /// Returns the text still held back once the answer has ended.
+    pub fn finish(&mut self) -> String {
+        self.open = None;
+        let pending = std::mem::take(&mut self.pending);
+        if std::mem::take(&mut self.trim_start) {
+            pending.trim_start().to_string()
+        } else {
+            pending
+        }
+    }
+}
+
+/// Removes echoed reasoning tags from a complete answer.
+///
+/// # Arguments
+///
+/// * `config` - Reasoning configuration naming the tags
+/// * `answer` - The answer text
+///
+/// # Returns
+///
+/// * `String` - The answer without reasoning tag spans
+pub fn strip_leaked_tags(config: &ReasoningConfig, answer: &str) -> String {
+    let mut filter = LeakedTagFilter::new(config);
+    let mut stripped = filter.push(answer);
+    stripped.push_str(&filter.finish());
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_blocks: Vec::new(),
+        }
+    }
+
+    fn turns(messages: &[Message]) -> Vec<(Role, &str)> {
+        messages.iter().map(|message| (message.role.clone(), message.content.as_str())).collect()
+    }
+
+    #[test]
+    fn user_message_injection_merges_into_the_final_user_turn() {
+        let mut messages = vec![
+            message(Role::System, "be brief"),
+            message(Role::User, "first"),
+            message(Role::Assistant, "reply"),
+            message(Role::User, "second"),
+        ];
+
+        inject(&ReasoningConfig::default(), ReasoningInjection::UserMessage, &mut messages, "because");
+
+        assert_eq!(turns(&messages), [
+            (Role::System, "be brief"),
+            (Role::User, "first"),
+            (Role::Assistant, "reply"),
+            (Role::User, "<thinking>\nbecause\n</thinking>\n\nsecond"),
+        ]);
+    }
+
+    #[test]
+    fn user_message_injection_appends_a_turn_after_an_assistant_message() {
+        let mut messages = vec![message(Role::User, "question"), message(Role::Assistant, "partial")];
+
+        inject(&ReasoningConfig::default(), ReasoningInjection::UserMessage, &mut messages, "because");
+
+        assert_eq!(turns(&messages), [
+            (Role::User, "question"),
+            (Role::Assistant, "partial"),
+            (Role::User, "<thinking>\nbecause\n</thinking>"),
+        ]);
+    }
+
+    #[test]
+    fn user_message_injection_fills_an_empty_user_turn() {
+        let mut messages = vec![message(Role::User, "question"), message(Role::User, "")];
+
+        inject(&ReasoningConfig::default(), ReasoningInjection::UserMessage, &mut messages, "because");
+
+        assert_eq!(turns(&messages), [
+            (Role::User, "question"),
+            (Role::User, "<thinking>\nbecause\n</thinking>"),
+        ]);
+    }
+}
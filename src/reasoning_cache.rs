@@ -0,0 +1,127 @@
+//! Reuse of DeepSeek reasoning across requests.
+//!
+//! The reasoning stage is the slowest and most expensive part of the
+//! pipeline, and for a given conversation it does not depend on anything
+//! sent to Anthropic. When enabled, the reasoning produced for a set of
+//! messages and DeepSeek config is kept for a configurable TTL, and later
+//! requests with the same inputs skip the DeepSeek call entirely while
+//! still getting a fresh answer.
+//!
+//! Entries are scoped to the caller's DeepSeek token, so reasoning is only
+//! reused by the client that paid for it, and a hit never stands in for a
+//! call with a token DeepSeek would reject. They are pruned lazily after
+//! the TTL.
+
+use crate::{
+    clients::deepseek::{self, DeepSeekResponse},
+    models::{ApiConfig, Message},
+};
+use dashmap::DashMap;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// Reasoning produced by an earlier request.
+#[derive(Debug, Clone)]
+pub struct CachedReasoning {
+    pub reasoning: String,
+    pub model: String,
+    pub system_fingerprint: String,
+}
+
+impl CachedReasoning {
+    /// Rebuilds a DeepSeek response carrying the cached reasoning.
+    ///
+    /// The usage is zero because no tokens were spent producing it.
+    pub fn to_response(&self) -> DeepSeekResponse {
+        DeepSeekResponse {
+            id: "cached".to_string(),
+            object: "chat.completion".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: self.model.clone(),
+            choices: vec![deepseek::Choice {
+                index: 0,
+                message: deepseek::AssistantMessage {
+                    role: "assistant".to_string(),
+                    content: None,
+                    reasoning_content: Some(self.reasoning.clone()),
+                },
+                logprobs: None,
+                finish_reason: Some("stop".to_string()),
+            }],
+            usage: deepseek::Usage::default(),
+            system_fingerprint: self.system_fingerprint.clone(),
+            request_id: None,
+            rate_limit: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    reasoning: CachedReasoning,
+    created_at: Instant,
+}
+
+/// Recent reasoning, keyed by a hash of the caller, messages and DeepSeek config.
+#[derive(Debug)]
+pub struct ReasoningCache {
+    entries: DashMap<u64, CacheEntry>,
+    ttl: Duration,
+}
+
+impl ReasoningCache {
+    /// Creates a cache that keeps reasoning for `ttl`.
+    ///
+    /// A zero `ttl` disables caching entirely.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Computes the cache key for a reasoning request.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Messages sent to DeepSeek, including the system prompt
+    /// * `config` - DeepSeek request configuration
+    /// * `scope` - Values identifying the caller, such as its DeepSeek token
+    ///
+    /// # Returns
+    ///
+    /// * `Option<u64>` - The key, or `None` if caching is disabled
+    pub fn key(&self, messages: &[Message], config: &ApiConfig, scope: &[&str]) -> Option<u64> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+
+        let mut headers: Vec<_> = config.headers.iter().collect();
+        headers.sort();
+
+        let mut hasher = DefaultHasher::new();
+        scope.hash(&mut hasher);
+        serde_json::to_string(messages).unwrap_or_default().hash(&mut hasher);
+        config.body.to_string().hash(&mut hasher);
+        config.base_url.hash(&mut hasher);
+        headers.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// Returns the reasoning cached under `key`, if it has not expired.
+    pub fn get(&self, key: u64) -> Option<CachedReasoning> {
+        self.entries.retain(|_, entry| entry.created_at.elapsed() <= self.ttl);
+        self.entries.get(&key).map(|entry| entry.reasoning.clone())
+    }
+
+    /// Stores reasoning under `key`, replacing any earlier entry.
+    pub fn insert(&self, key: u64, reasoning: CachedReasoning) {
+        self.entries.insert(key, CacheEntry {
+            reasoning,
+            created_at: Instant::now(),
+        });
+    }
+}
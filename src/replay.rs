@@ -0,0 +1,150 @@
+//! Replay buffers for resumable streaming responses.
+//!
+//! Every streaming request records the events it emits, tagged with a
+//! monotonically increasing sequence id. A client that loses its connection
+//! can reconnect with the `Last-Event-ID` header and receive the events it
+//! missed, followed by any events that are still being generated.
+//!
+//! Buffers are kept in memory for a configurable window after their stream
+//! finishes and are pruned lazily whenever the store is accessed.
+
+use axum::response::sse::Event;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+
+/// Capacity of the channel used to forward live events to reconnected clients.
+const LIVE_CHANNEL_CAPACITY: usize = 256;
+
+/// A single stream event as it was sent to the client.
+#[derive(Debug, Clone)]
+pub struct BufferedEvent {
+    pub id: u64,
+    pub name: &'static str,
+    pub data: String,
+}
+
+impl BufferedEvent {
+    /// Converts the buffered event back into an SSE event with its original id.
+    pub fn to_sse(&self) -> Event {
+        Event::default()
+            .id(self.id.to_string())
+            .event(self.name)
+            .data(&self.data)
+    }
+}
+
+/// Events recorded for a single streaming request.
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    inner: Mutex<ReplayInner>,
+}
+
+#[derive(Debug)]
+struct ReplayInner {
+    events: Vec<BufferedEvent>,
+    live: Option<broadcast::Sender<BufferedEvent>>,
+    finished_at: Option<Instant>,
+}
+
+impl ReplayBuffer {
+    fn new() -> Self {
+        let (live, _) = broadcast::channel(LIVE_CHANNEL_CAPACITY);
+        Self {
+            inner: Mutex::new(ReplayInner {
+                events: Vec::new(),
+                live: Some(live),
+                finished_at: None,
+            }),
+        }
+    }
+
+    /// Records an event and forwards it to any reconnected clients.
+    pub fn push(&self, event: BufferedEvent) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(live) = &inner.live {
+            let _ = live.send(event.clone());
+        }
+        inner.events.push(event);
+    }
+
+    /// Marks the stream as finished, closing the live channel.
+    pub fn finish(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.live = None;
+        inner.finished_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Returns the events after `last_event_id` and, if the stream is still
+    /// running, a receiver for the events that follow.
+    ///
+    /// Both are taken under the same lock so no event is missed or duplicated
+    /// between the replayed backlog and the live receiver.
+    pub fn subscribe(
+        &self,
+        last_event_id: u64,
+    ) -> (Vec<BufferedEvent>, Option<broadcast::Receiver<BufferedEvent>>) {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let missed = inner
+            .events
+            .iter()
+            .filter(|event| event.id > last_event_id)
+            .cloned()
+            .collect();
+        let live = inner.live.as_ref().map(|live| live.subscribe());
+        (missed, live)
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner
+            .finished_at
+            .is_some_and(|finished_at| finished_at.elapsed() > ttl)
+    }
+}
+
+/// Replay buffers for all recent streaming requests, keyed by request id.
+#[derive(Debug)]
+pub struct ReplayStore {
+    buffers: Mutex<HashMap<String, Arc<ReplayBuffer>>>,
+    ttl: Duration,
+}
+
+impl ReplayStore {
+    /// Creates a store that keeps finished streams for `ttl`.
+    ///
+    /// A zero `ttl` disables buffering entirely.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            buffers: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Creates and registers a buffer for a new streaming request.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<ReplayBuffer>>` - The new buffer, or `None` if buffering is disabled
+    pub fn create(&self, request_id: &str) -> Option<Arc<ReplayBuffer>> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+
+        let buffer = Arc::new(ReplayBuffer::new());
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        buffers.retain(|_, buffer| !buffer.is_expired(self.ttl));
+        buffers.insert(request_id.to_string(), buffer.clone());
+        Some(buffer)
+    }
+
+    /// Looks up the buffer for a request that has not yet expired.
+    pub fn get(&self, request_id: &str) -> Option<Arc<ReplayBuffer>> {
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        buffers.retain(|_, buffer| !buffer.is_expired(self.ttl));
+        buffers.get(request_id).cloned()
+    }
+}
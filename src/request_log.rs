@@ -0,0 +1,171 @@
+//! Append-only JSONL audit log of completed requests.
+//!
+//! When `request_log.path` is set, every completed request is recorded as
+//! one JSON line, independent of the tracing output. Request handlers only
+//! queue the line; a dedicated writer task owns the file, so concurrent
+//! requests never interleave partial lines and never wait on disk I/O. If
+//! the queue is full the line is dropped with a warning rather than
+//! slowing requests down.
+
+use crate::{
+    config::RequestLogConfig,
+    models::{CombinedUsage, FinishModels, Message},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+/// Lines waiting to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// One line of the request log.
+#[derive(Debug, Serialize)]
+pub struct RequestLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    /// `chat` or `reason`
+    pub endpoint: &'static str,
+    pub stream: bool,
+    pub models: FinishModels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    /// Missing when a stream ended before usage was reported
+    pub usage: Option<CombinedUsage>,
+    /// The request's cost attribution tags
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+    /// The request's messages, with `request_log.include_content` only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<Message>>,
+}
+
+/// Queues request log lines for the writer task.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog {
+    tx: Option<mpsc::Sender<String>>,
+    include_content: bool,
+}
+
+impl RequestLog {
+    /// Opens the log file and starts its writer task.
+    ///
+    /// Must be called from within the Tokio runtime. Logging is disabled
+    /// when no path is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The request log configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log file cannot be opened for appending
+    pub async fn new(config: &RequestLogConfig) -> anyhow::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(Self::default());
+        };
+
+        let path = PathBuf::from(path);
+        let file = open(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("request_log: cannot open {}: {}", path.display(), e))?;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_lines(file, path, config.max_bytes, rx));
+
+        Ok(Self {
+            tx: Some(tx),
+            include_content: config.include_content,
+        })
+    }
+
+    /// Returns whether entries should carry the request's messages.
+    pub fn includes_content(&self) -> bool {
+        self.tx.is_some() && self.include_content
+    }
+
+    /// Returns whether the log is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Queues an entry without waiting for it to be written.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The completed request
+    pub fn record(&self, entry: &RequestLogEntry) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let mut line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize request log entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        if tx.try_send(line).is_err() {
+            tracing::warn!("Request log queue is full, dropped entry for {}", entry.request_id);
+        }
+    }
+}
+
+/// Opens the log file for appending, creating it if needed.
+async fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path).await
+}
+
+/// Writes queued lines in order, rotating the file when it gets too large.
+///
+/// # Arguments
+///
+/// * `file` - The open log file
+/// * `path` - Path of the log file, for rotation
+/// * `max_bytes` - Size past which the file is moved to `<path>.1`
+/// * `rx` - Queued lines
+async fn write_lines(
+    mut file: File,
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    mut rx: mpsc::Receiver<String>,
+) {
+    let mut size = file.metadata().await.map(|metadata| metadata.len()).unwrap_or(0);
+
+    while let Some(line) = rx.recv().await {
+        if let Some(max_bytes) = max_bytes {
+            if size > 0 && size + line.len() as u64 > max_bytes {
+                let mut rotated = path.clone().into_os_string();
+                rotated.push(".1");
+                let reopened = match tokio::fs::rename(&path, &rotated).await {
+                    Ok(()) => open(&path).await,
+                    Err(e) => Err(e),
+                };
+                match reopened {
+                    Ok(new_file) => {
+                        file = new_file;
+                        size = 0;
+                    }
+                    Err(e) => tracing::warn!("Failed to rotate request log {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        // Flushed per line so entries survive a crash
+        let written = match file.write_all(line.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(()) => size += line.len() as u64,
+            Err(e) => tracing::warn!("Failed to write request log {}: {}", path.display(), e),
+        }
+    }
+}
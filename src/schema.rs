@@ -0,0 +1,93 @@
+//! Validation of structured answers against a client-supplied JSON Schema.
+//!
+//! Requests carrying a `response_schema` have the final answer parsed as
+//! JSON and checked against it. A surrounding Markdown code fence, which
+//! models often add even when asked for bare JSON, is ignored. Failures are
+//! described in a short correction prompt so the answer can be requested
+//! again, or reported to the client as `schema_valid: false`.
+
+use crate::error::{ApiError, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+
+/// Maximum number of schema violations listed in a correction prompt.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// Compiles a request's response schema.
+///
+/// # Arguments
+///
+/// * `schema` - The JSON Schema from the request
+///
+/// # Returns
+///
+/// * `Result<Validator>` - The compiled validator
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the schema is not a valid JSON Schema
+pub fn compile(schema: &Value) -> Result<Validator> {
+    jsonschema::validator_for(schema).map_err(|e| ApiError::BadRequest {
+        message: format!("response_schema is not a valid JSON Schema: {}", e),
+    })
+}
+
+/// Checks an answer against a compiled schema.
+///
+/// # Arguments
+///
+/// * `validator` - The compiled response schema
+/// * `answer` - The answer text, optionally wrapped in a code fence
+///
+/// # Returns
+///
+/// * `Vec<String>` - Descriptions of every violation, empty if the answer is valid
+pub fn check(validator: &Validator, answer: &str) -> Vec<String> {
+    let instance: Value = match serde_json::from_str(strip_code_fence(answer)) {
+        Ok(instance) => instance,
+        Err(e) => return vec![format!("the answer is not valid JSON: {}", e)],
+    };
+
+    validator
+        .iter_errors(&instance)
+        .map(|error| match error.instance_path.as_str() {
+            "" => error.to_string(),
+            path => format!("{}: {}", path, error),
+        })
+        .collect()
+}
+
+/// Builds the user message asking the model to correct an invalid answer.
+///
+/// # Arguments
+///
+/// * `errors` - Violations found by [`check`]
+///
+/// # Returns
+///
+/// * `String` - The correction prompt
+pub fn correction_prompt(errors: &[String]) -> String {
+    let listed = errors
+        .iter()
+        .take(MAX_REPORTED_ERRORS)
+        .map(|error| format!("- {}", error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "Your answer does not match the required JSON Schema:\n{}\n\nReply with only the corrected JSON.",
+        listed
+    )
+}
+
+/// Removes a Markdown code fence wrapping the whole answer, if any.
+fn strip_code_fence(answer: &str) -> &str {
+    let trimmed = answer.trim();
+    let Some(inner) = trimmed.strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) else {
+        return trimmed;
+    };
+    // Drop the info string, e.g. "json"
+    match inner.split_once('\n') {
+        Some((_, body)) => body.trim(),
+        None => inner.trim(),
+    }
+}
@@ -0,0 +1,85 @@
+//! Per-client limit on simultaneous streaming responses.
+//!
+//! Streams are long-lived and each holds a connection to the client and
+//! one to a provider, so a single client opening many at once can exhaust
+//! file descriptors and provider concurrency. Every stream takes a permit
+//! for its caller, identified by its API tokens, and returns it when the
+//! permit is dropped at the end of the streaming task.
+
+use crate::error::{ApiError, Result};
+use dashmap::DashMap;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Counts of active streams, keyed by a hash of the caller's API tokens.
+#[derive(Debug)]
+pub struct StreamLimiter {
+    active: Arc<DashMap<u64, usize>>,
+    max_per_client: Option<usize>,
+}
+
+impl StreamLimiter {
+    /// Creates a limiter allowing `max_per_client` simultaneous streams.
+    ///
+    /// `None` disables the limit.
+    pub fn new(max_per_client: Option<usize>) -> Self {
+        Self {
+            active: Arc::new(DashMap::new()),
+            max_per_client,
+        }
+    }
+
+    /// Reserves a stream slot for a caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - Values identifying the caller, such as its API tokens
+    ///
+    /// # Returns
+    ///
+    /// * `Result<StreamPermit>` - A permit releasing the slot when dropped
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::TooManyRequests` if the caller already has the
+    /// maximum number of streams open
+    pub fn acquire(&self, scope: &[&str]) -> Result<StreamPermit> {
+        let mut hasher = DefaultHasher::new();
+        scope.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let mut active = self.active.entry(key).or_insert(0);
+        if let Some(max) = self.max_per_client {
+            if *active >= max {
+                return Err(ApiError::TooManyRequests {
+                    message: format!("Too many concurrent streams (limit {})", max),
+                });
+            }
+        }
+        *active += 1;
+
+        Ok(StreamPermit {
+            active: self.active.clone(),
+            key,
+        })
+    }
+}
+
+/// A reserved stream slot, released when dropped.
+#[derive(Debug)]
+pub struct StreamPermit {
+    active: Arc<DashMap<u64, usize>>,
+    key: u64,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.active.remove_if_mut(&self.key, |_, active| {
+            *active -= 1;
+            *active == 0
+        });
+    }
+}
@@ -0,0 +1,45 @@
+//! Gauge of running streaming tasks.
+//!
+//! Every streaming response is driven by a spawned task. A task that never
+//! finishes, for instance because its client never disconnects cleanly,
+//! would otherwise only show up as slowly growing memory. Each task holds a
+//! guard for as long as it runs; the guard is released when the task ends,
+//! however it ends, including by panic or abort.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Number of streaming tasks currently running.
+#[derive(Debug, Default)]
+pub struct TaskGauge {
+    active: Arc<AtomicUsize>,
+}
+
+impl TaskGauge {
+    /// Counts a newly spawned task until the returned guard is dropped.
+    pub fn track(&self) -> TaskGuard {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        TaskGuard {
+            active: self.active.clone(),
+        }
+    }
+
+    /// Returns the number of running tasks.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps a task counted in its [`TaskGauge`] while alive.
+#[derive(Debug)]
+pub struct TaskGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
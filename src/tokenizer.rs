@@ -0,0 +1,51 @@
+//! Approximate token counting for DeepSeek and Anthropic models.
+//!
+//! Neither provider publishes a tokenizer usable from Rust, so counts come
+//! from the closest bundled BPE encoding: `o200k_base` for DeepSeek, whose
+//! large multilingual vocabulary it most resembles, and `cl100k_base` for
+//! Claude. Counts are estimates suitable for budgeting, not billing.
+
+use crate::models::Provider;
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+/// Resolves which provider's tokenizer to use.
+///
+/// An explicit provider wins; otherwise the model id is matched by prefix,
+/// defaulting to DeepSeek.
+///
+/// # Arguments
+///
+/// * `provider` - Optional explicit provider hint
+/// * `model` - Optional model id hint
+pub fn resolve_provider(provider: Option<Provider>, model: Option<&str>) -> Provider {
+    provider.unwrap_or_else(|| match model {
+        Some(model) if model.starts_with("claude") => Provider::Anthropic,
+        _ => Provider::DeepSeek,
+    })
+}
+
+/// Returns the name of the encoding used for a provider.
+pub fn encoding_name(provider: Provider) -> &'static str {
+    match provider {
+        Provider::DeepSeek => "o200k_base",
+        Provider::Anthropic => "cl100k_base",
+    }
+}
+
+/// Counts the tokens in `text` using the provider's closest encoding.
+///
+/// # Arguments
+///
+/// * `provider` - The provider whose tokenizer to approximate
+/// * `text` - The text to count
+///
+/// # Returns
+///
+/// The approximate number of tokens
+pub fn count_tokens(provider: Provider, text: &str) -> usize {
+    let bpe: &CoreBPE = match provider {
+        Provider::DeepSeek => o200k_base_singleton(),
+        Provider::Anthropic => cl100k_base_singleton(),
+    };
+    bpe.encode_ordinary(text).len()
+}
@@ -0,0 +1,183 @@
+//! Ordered transformations applied to messages before the providers see them.
+//!
+//! Each transform rewrites the prepared message list in place, after the
+//! system prompt is positioned and before history trimming. The pipeline is
+//! built from `prompt.transforms` at startup; further transforms can be
+//! added with [`TransformPipeline::push`].
+
+use crate::{
+    config::{PromptConfig, TransformKind},
+    models::{normalize_content, Message},
+};
+use regex::Regex;
+
+/// Patterns redacted by `redact_pii` when `prompt.pii_patterns` is unset:
+/// email addresses, US social security numbers and payment card numbers.
+const DEFAULT_PII_PATTERNS: &[&str] = &[
+    r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+    r"\b\d{3}-\d{2}-\d{4}\b",
+    r"\b(?:\d[ -]?){12,18}\d\b",
+];
+
+/// Text substituted for redacted matches when `prompt.pii_replacement` is unset.
+const DEFAULT_PII_REPLACEMENT: &str = "[REDACTED]";
+
+/// A rewrite applied to every request's messages.
+pub trait MessageTransform: Send + Sync {
+    /// Rewrites the messages in place.
+    fn transform(&self, messages: &mut Vec<Message>);
+}
+
+/// Normalizes line endings and redundant whitespace in every message.
+#[derive(Debug, Default)]
+pub struct NormalizeWhitespace;
+
+impl MessageTransform for NormalizeWhitespace {
+    fn transform(&self, messages: &mut Vec<Message>) {
+        for message in messages {
+            message.content = normalize_content(&message.content);
+        }
+    }
+}
+
+/// Replaces text matching any of a set of patterns in every message.
+#[derive(Debug)]
+pub struct RedactPii {
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl RedactPii {
+    /// Compiles a redaction transform.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - Regular expressions whose matches are replaced
+    /// * `replacement` - Text substituted for each match
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a pattern is not a valid regular expression
+    pub fn new<S: AsRef<str>>(patterns: &[S], replacement: impl Into<String>) -> Result<Self, regex::Error> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            patterns,
+            replacement: replacement.into(),
+        })
+    }
+}
+
+impl MessageTransform for RedactPii {
+    fn transform(&self, messages: &mut Vec<Message>) {
+        for message in messages {
+            for pattern in &self.patterns {
+                if let std::borrow::Cow::Owned(redacted) =
+                    pattern.replace_all(&message.content, self.replacement.as_str())
+                {
+                    message.content = redacted;
+                }
+            }
+        }
+    }
+}
+
+/// The transforms applied to every request, in order.
+#[derive(Default)]
+pub struct TransformPipeline {
+    transforms: Vec<Box<dyn MessageTransform>>,
+}
+
+impl std::fmt::Debug for TransformPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransformPipeline")
+            .field("transforms", &self.transforms.len())
+            .finish()
+    }
+}
+
+impl TransformPipeline {
+    /// Builds the pipeline configured in `prompt.transforms`.
+    ///
+    /// The legacy `prompt.normalize_messages` flag adds whitespace
+    /// normalization ahead of the listed transforms.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The prompt configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a PII pattern is not a valid regular expression
+    pub fn from_config(config: &PromptConfig) -> Result<Self, regex::Error> {
+        let mut pipeline = Self::default();
+        if config.normalize_messages {
+            pipeline.push(Box::new(NormalizeWhitespace));
+        }
+
+        for kind in &config.transforms {
+            match kind {
+                TransformKind::NormalizeWhitespace => pipeline.push(Box::new(NormalizeWhitespace)),
+                TransformKind::RedactPii => {
+                    let replacement = config.pii_replacement.as_deref().unwrap_or(DEFAULT_PII_REPLACEMENT);
+                    let redact = match &config.pii_patterns {
+                        Some(patterns) => RedactPii::new(patterns, replacement)?,
+                        None => RedactPii::new(DEFAULT_PII_PATTERNS, replacement)?,
+                    };
+                    pipeline.push(Box::new(redact));
+                }
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Appends a transform, run after those already in the pipeline.
+    pub fn push(&mut self, transform: Box<dyn MessageTransform>) {
+        self.transforms.push(transform);
+    }
+
+    /// Runs every transform over the messages, in order.
+    pub fn apply(&self, messages: &mut Vec<Message>) {
+        for transform in &self.transforms {
+            transform.transform(messages);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Role;
+
+    fn normalize(content: &str) -> String {
+        let mut messages = vec![Message {
+            role: Role::User,
+            content: content.to_string(),
+            tool_blocks: Vec::new(),
+        }];
+        NormalizeWhitespace.transform(&mut messages);
+        messages.remove(0).content
+    }
+
+    #[test]
+    fn fenced_code_is_left_unchanged() {
+        let code = "```python\ndef f():\n    x = 1   \n\n\n\n    return x\t\n```";
+        assert_eq!(normalize(code), code);
+    }
+
+    #[test]
+    fn only_text_outside_fences_is_normalized() {
+        let content = "Intro\n\n\n\n```\nkeep\n\n\n\nthis  \n```\n\n\n\nOutro  \n\n";
+        assert_eq!(normalize(content), "Intro\n\n```\nkeep\n\n\n\nthis  \n```\n\nOutro");
+    }
+
+    #[test]
+    fn indented_and_consecutive_fences_are_preserved() {
+        let content = "  ```rust\nlet a = 1;\n\n\n\nlet b = 2;\n  ```\n\n\n\n```\n\n\n```";
+        assert_eq!(normalize(content), "  ```rust\nlet a = 1;\n\n\n\nlet b = 2;\n  ```\n\n```\n\n\n```");
+    }
+}
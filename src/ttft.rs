@@ -0,0 +1,154 @@
+//! Time to first token of streaming responses.
+//!
+//! A streamed chat response has two phases, the DeepSeek reasoning and the
+//! Anthropic answer, and each has its own time to first token (TTFT): how
+//! long after the request arrived its first generated text reached the
+//! client. Markup such as the opening reasoning tag does not count, so the
+//! timer only stops once a token has actually been handed to the stream.
+//! Both values are reported in the stream's final timings and, for chat
+//! streams, collected into Prometheus histograms.
+
+use crate::models::Phase;
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS_SECS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// A Prometheus histogram of TTFT observations.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last is `+Inf`
+    buckets: [AtomicU64; BUCKETS_SECS.len() + 1],
+    count: AtomicU64,
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    /// Records one observation.
+    fn observe(&self, ms: u64) {
+        let secs = ms as f64 / 1000.0;
+        let bucket = BUCKETS_SECS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS_SECS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    /// Appends the histogram's series, labelled with its phase.
+    fn render(&self, name: &str, phase: &str, out: &mut String) {
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = BUCKETS_SECS.get(index).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{}_bucket{{phase=\"{}\",le=\"{}\"}} {}", name, phase, bound, cumulative);
+        }
+        let sum_secs = self.sum_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+        let _ = writeln!(out, "{}_sum{{phase=\"{}\"}} {}", name, phase, sum_secs);
+        let _ = writeln!(out, "{}_count{{phase=\"{}\"}} {}", name, phase, self.count.load(Ordering::Relaxed));
+    }
+}
+
+/// TTFT histograms of chat streams, one per phase.
+#[derive(Debug, Default)]
+pub struct TtftMetrics {
+    reasoning: Histogram,
+    answer: Histogram,
+}
+
+impl TtftMetrics {
+    /// Renders both histograms in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let name = "deepreasoning_stream_ttft_seconds";
+        let mut out = format!(
+            "# HELP {} Time from request receipt to the first streamed token, by phase\n# TYPE {} histogram\n",
+            name, name
+        );
+        self.reasoning.render(name, "reasoning", &mut out);
+        self.answer.render(name, "answer", &mut out);
+        out
+    }
+}
+
+/// Measures the TTFT of each phase of one stream.
+#[derive(Debug)]
+pub struct FirstTokenTimer {
+    started: Instant,
+    phase: Phase,
+    /// A token of the current phase was handed over but not yet sent
+    token_pending: bool,
+    reasoning_ms: Option<u64>,
+    answer_ms: Option<u64>,
+    metrics: Option<Arc<TtftMetrics>>,
+}
+
+impl FirstTokenTimer {
+    /// Starts timing a stream in its reasoning phase.
+    ///
+    /// # Arguments
+    ///
+    /// * `started` - When the request was received
+    /// * `metrics` - Histograms the TTFTs are recorded in, if any
+    pub fn new(started: Instant, metrics: Option<Arc<TtftMetrics>>) -> Self {
+        Self {
+            started,
+            phase: Phase::Reasoning,
+            token_pending: false,
+            reasoning_ms: None,
+            answer_ms: None,
+            metrics,
+        }
+    }
+
+    /// Notes that generated text of the current phase is about to be sent.
+    pub fn token(&mut self) {
+        self.token_pending = true;
+    }
+
+    /// Moves the timer to a new phase.
+    pub fn phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.token_pending = false;
+    }
+
+    /// Notes that content was sent to the client.
+    ///
+    /// Stops the current phase's timer if a token was pending and the
+    /// phase has no TTFT yet.
+    pub fn sent(&mut self) {
+        if !self.token_pending {
+            return;
+        }
+        self.token_pending = false;
+
+        let (ttft, histogram) = match self.phase {
+            Phase::Reasoning => (&mut self.reasoning_ms, self.metrics.as_ref().map(|metrics| &metrics.reasoning)),
+            Phase::Answering => (&mut self.answer_ms, self.metrics.as_ref().map(|metrics| &metrics.answer)),
+        };
+        if ttft.is_none() {
+            let ms = self.started.elapsed().as_millis() as u64;
+            *ttft = Some(ms);
+            if let Some(histogram) = histogram {
+                histogram.observe(ms);
+            }
+        }
+    }
+
+    /// Returns the reasoning TTFT in milliseconds, if a reasoning token was sent.
+    pub fn reasoning_ms(&self) -> Option<u64> {
+        self.reasoning_ms
+    }
+
+    /// Returns the answer TTFT in milliseconds, if an answer token was sent.
+    pub fn answer_ms(&self) -> Option<u64> {
+        self.answer_ms
+    }
+}
@@ -0,0 +1,229 @@
+//! Delivery of completed responses to client-supplied callback URLs.
+//!
+//! Requests carrying a `callback_url` are answered immediately and run in
+//! the background; the final response or error is then POSTed to the URL.
+//! Deliveries can be signed with an HMAC so the receiver can verify they
+//! came from this server.
+//!
+//! A callback makes the server POST model output to a URL of the client's
+//! choosing, so only hosts listed in `webhook.allowed_hosts` are open to
+//! every client. Other hosts need the admin token, and must resolve to
+//! public addresses only. Deliveries connect to the addresses that were
+//! checked and never follow redirects, so neither DNS changes nor a
+//! redirect can point them at loopback, private or link-local hosts such
+//! as a cloud metadata service.
+
+use crate::{
+    clients,
+    config::{HttpConfig, WebhookConfig},
+};
+use hmac::{Hmac, Mac};
+use reqwest::{redirect, Url};
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+/// Header carrying the HMAC-SHA256 signature of the delivery body.
+const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Header carrying the id returned when the request was accepted.
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Checks that a callback URL is an absolute HTTP(S) URL.
+///
+/// # Arguments
+///
+/// * `url` - The callback URL supplied by the client
+///
+/// # Returns
+///
+/// * `bool` - True if the URL can be delivered to
+pub fn is_valid_callback_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+/// Checks whether a callback URL's host is listed in `webhook.allowed_hosts`.
+///
+/// Listed hosts are trusted by the operator and may resolve to any address.
+///
+/// # Arguments
+///
+/// * `config` - Webhook settings containing the allowed hosts
+/// * `url` - The callback URL
+pub fn is_allowed_host(config: &WebhookConfig, url: &str) -> bool {
+    let Some(host) = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase)) else {
+        return false;
+    };
+    config.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host))
+}
+
+/// Checks whether an address is reachable on the public internet.
+///
+/// Rejects loopback, private, shared (carrier-grade NAT), link-local,
+/// unique-local, unspecified, broadcast, multicast and documentation
+/// addresses, including IPv4 addresses mapped into IPv6.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Resolves a callback URL's host, requiring every address to be public.
+///
+/// # Arguments
+///
+/// * `url` - The callback URL
+///
+/// # Returns
+///
+/// * `Result<Vec<SocketAddr>, String>` - The addresses to connect to
+///
+/// # Errors
+///
+/// Returns a description of the problem if the host cannot be resolved or
+/// any of its addresses is not public
+pub async fn resolve_public(url: &str) -> Result<Vec<SocketAddr>, String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().ok_or("callback_url has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    // IPv6 literals are bracketed in URLs but not in lookups
+    let lookup_host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((lookup_host, port))
+        .await
+        .map_err(|e| format!("cannot resolve callback host {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("callback host {} has no addresses", host));
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!("callback host {} resolves to non-public address {}", host, addr.ip()));
+    }
+
+    Ok(addrs)
+}
+
+/// Signs a delivery body.
+///
+/// # Arguments
+///
+/// * `secret` - The shared webhook secret
+/// * `body` - The exact bytes being delivered
+///
+/// # Returns
+///
+/// * `String` - The signature as `sha256=` followed by the hex digest
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Builds the client for one delivery attempt.
+///
+/// The client follows no redirects. Unless the host is in
+/// `webhook.allowed_hosts`, it is resolved again and the client is pinned
+/// to the checked addresses.
+///
+/// # Arguments
+///
+/// * `http_config` - Outbound HTTP settings, such as the proxy and root certificate
+/// * `config` - Webhook settings containing the allowed hosts
+/// * `url` - The callback URL
+///
+/// # Errors
+///
+/// Returns a description of the problem if the host is not public or the
+/// client cannot be built
+async fn delivery_client(http_config: &HttpConfig, config: &WebhookConfig, url: &str) -> Result<reqwest::Client, String> {
+    let mut builder = clients::http_client_builder(http_config)
+        .map_err(|e| e.to_string())?
+        .redirect(redirect::Policy::none());
+    if !is_allowed_host(config, url) {
+        let addrs = resolve_public(url).await?;
+        let host = Url::parse(url).ok().and_then(|url| url.host_str().map(String::from)).unwrap_or_default();
+        builder = builder.resolve_to_addrs(&host, &addrs);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// POSTs a payload to a callback URL, retrying failed deliveries.
+///
+/// A delivery fails on a network error, a non-2xx response or a host that
+/// no longer resolves to public addresses. Failures are logged; after the
+/// final attempt the payload is dropped.
+///
+/// # Arguments
+///
+/// * `http_config` - Outbound HTTP settings, such as the proxy and root certificate
+/// * `config` - Webhook signing, retry and host settings
+/// * `url` - The callback URL
+/// * `request_id` - The id returned to the client when the request was accepted
+/// * `payload` - The response or error body to deliver
+pub async fn deliver(
+    http_config: &HttpConfig,
+    config: &WebhookConfig,
+    url: &str,
+    request_id: &str,
+    payload: &serde_json::Value,
+) {
+    let body = serde_json::to_vec(payload).unwrap_or_default();
+    let signature = config.secret.as_deref().map(|secret| sign(secret, &body));
+    let max_attempts = config.max_attempts.max(1);
+
+    for attempt in 1..=max_attempts {
+        let error = match delivery_client(http_config, config, url).await {
+            Ok(http) => {
+                let mut request = http
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(REQUEST_ID_HEADER, request_id)
+                    .body(body.clone());
+                if let Some(signature) = &signature {
+                    request = request.header(SIGNATURE_HEADER, signature);
+                }
+
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        tracing::debug!("Delivered callback for request {} to {}", request_id, url);
+                        return;
+                    }
+                    Ok(response) => format!("callback returned {}", response.status()),
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(e) => e,
+        };
+
+        if attempt < max_attempts {
+            tracing::warn!("Callback attempt {} for request {} failed, retrying: {}", attempt, request_id, error);
+            tokio::time::sleep(Duration::from_millis(config.backoff_ms * attempt as u64)).await;
+        } else {
+            tracing::error!("Giving up on callback for request {} after {} attempts: {}", request_id, attempt, error);
+        }
+    }
+}